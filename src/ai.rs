@@ -0,0 +1,267 @@
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use raylib::prelude::*;
+
+use crate::constants::TILE_SIZE;
+use crate::entities::{Chase, Wander};
+use crate::grid::Grid;
+use crate::solver::distances_from;
+
+/// Longest distance a creature walks in one step of the game before it picks its next tile again, so that slow frames
+/// don't carry it past several junctions at once
+const MAX_STEP: f32 = TILE_SIZE;
+
+fn tile_of(map: &impl Grid, position: Vector3) -> Option<[usize; 2]> {
+    map.tile(map.topology().tile_at(Vector2::new(position.x, position.z) / TILE_SIZE))
+}
+
+fn tile_center(map: &impl Grid, tile: [usize; 2], y: f32) -> Vector3 {
+    let center = map.topology().center(tile) * TILE_SIZE;
+    Vector3::new(center.x, y, center.y)
+}
+
+/// Tiles a wandering creature can walk to from a tile. Crossings are left out, since a creature walking on a bridge
+/// would have to climb it, and so are tiles across the edge of maps that wrap around, to keep creatures in the map
+fn exits(map: &impl Grid, tile: [usize; 2]) -> impl Iterator<Item = [usize; 2]> + '_ {
+    let center = map.topology().center(tile);
+    map.open_neighbours(tile)
+        .filter(move |&neighbour| !map.is_crossing(neighbour) && map.seam_offset(center, neighbour) == Vector2::zero())
+}
+
+/// Picks the tile a creature walks to after reaching the center of `tile`. Creatures keep going forward and turn at
+/// random at junctions, and only turn back at dead ends. A creature near the player takes the exit that leads
+/// farthest from it, or stays where it is when every exit leads closer
+fn next_tile(
+    map: &impl Grid,
+    tile: [usize; 2],
+    from: Option<[usize; 2]>,
+    fleeing_from: Option<Vector3>,
+    rng: &mut impl Rng,
+) -> [usize; 2] {
+    if let Some(threat) = fleeing_from {
+        let distance = |t: [usize; 2]| tile_center(map, t, threat.y).distance_to(threat);
+        return exits(map, tile)
+            .filter(|&t| distance(t) > distance(tile))
+            .max_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+            .unwrap_or(tile);
+    }
+    exits(map, tile)
+        .filter(|&t| Some(t) != from)
+        .choose(rng)
+        .or(from.filter(|&from| exits(map, tile).any(|t| t == from)))
+        .unwrap_or(tile)
+}
+
+/// Moves a creature for `dt` seconds from its position along the centers of the tiles of its route, and returns its
+/// new position. The creature walks from the center of one tile to the center of an open neighbour, so it never goes
+/// through a wall, and `next` picks the tile after each center it reaches (the same tile stops it there). It walks
+/// back when the wall between the two tiles closes on the way (in a shifting maze), or when `turn_back` says so
+fn walk(
+    map: &impl Grid,
+    position: Vector3,
+    route: &mut Wander,
+    speed: f32,
+    dt: f32,
+    turn_back: impl Fn([usize; 2], [usize; 2]) -> bool,
+    mut next: impl FnMut([usize; 2], Option<[usize; 2]>) -> [usize; 2],
+) -> Vector3 {
+    let Some(here) = tile_of(map, position) else {
+        return position;
+    };
+    // without a route, the creature walks to the center of its tile first, so that it only turns at centers
+    let mut to = route.to.unwrap_or(here);
+    if let Some(from) = route.from {
+        let open = from == to || exits(map, from).any(|t| t == to);
+        if !open || (from != to && turn_back(from, to)) {
+            (route.from, to) = (Some(to), from);
+        }
+    }
+
+    let mut position = position;
+    let mut remaining = (speed * dt).min(MAX_STEP);
+    loop {
+        let target = tile_center(map, to, position.y);
+        let distance = target.distance_to(position);
+        if distance > remaining {
+            position += (target - position).normalized() * remaining;
+            break;
+        }
+        position = target;
+        remaining -= distance;
+        let next = next(to, route.from);
+        route.from = Some(to);
+        if next == to {
+            break;
+        }
+        to = next;
+    }
+    route.to = Some(to);
+    position
+}
+
+/// Moves a wandering creature for `dt` seconds, see `walk`. Creatures that flee turn back when the tile ahead is
+/// closer to the player
+pub fn wander(
+    map: &impl Grid,
+    position: Vector3,
+    wander: &mut Wander,
+    player: Vector3,
+    dt: f32,
+    rng: &mut impl Rng,
+) -> Vector3 {
+    let fleeing = wander.flee_radius > 0.0
+        && Vector2::new(player.x - position.x, player.z - position.z).length() < wander.flee_radius;
+    let distance = |t: [usize; 2]| tile_center(map, t, player.y).distance_to(player);
+    let speed = wander.speed;
+    walk(
+        map,
+        position,
+        wander,
+        speed,
+        dt,
+        |from, to| fleeing && distance(to) < distance(from),
+        |tile, from| next_tile(map, tile, from, fleeing.then_some(player), rng),
+    )
+}
+
+/// Moves a hunting creature for `dt` seconds, see `walk`. It patrols like a wandering creature until it sees the
+/// player (along a line that goes through no wall) or hears it (within its hearing radius, through walls). Then it
+/// runs along the shortest path to the tile where the player was last seen or heard, and patrols again if it gets
+/// there without finding the player. The distances to that tile are only worked out again when it changes
+pub fn chase(
+    map: &impl Grid,
+    position: Vector3,
+    chase: &mut Chase,
+    player: Vector3,
+    dt: f32,
+    rng: &mut impl Rng,
+) -> Vector3 {
+    let flat = |p: Vector3| Vector2::new(p.x, p.z) / TILE_SIZE;
+    let sees = map.line_of_sight(flat(position), flat(player));
+    let hears = (flat(player) - flat(position)).length() * TILE_SIZE < chase.hearing_radius;
+    if sees || hears {
+        let seen = tile_of(map, player).or(chase.last_seen);
+        if seen != chase.last_seen {
+            chase.last_seen = seen;
+            chase.distances = seen.map_or_else(Vec::new, |goal| distances_from(map, goal));
+        }
+    }
+    if chase.last_seen.is_none() {
+        let speed = chase.patrol.speed;
+        return walk(
+            map,
+            position,
+            &mut chase.patrol,
+            speed,
+            dt,
+            |_, _| false,
+            |tile, from| next_tile(map, tile, from, None, rng),
+        );
+    }
+    // exit of a tile that is the fewest steps from the goal, if it is closer than the tile itself
+    let graph = map.graph();
+    let distances = &chase.distances;
+    let distance = |tile: [usize; 2]| distances[graph.index(tile)];
+    let toward = |tile: [usize; 2]| {
+        let here = distance(tile)?;
+        exits(map, tile)
+            .filter_map(|t| Some((distance(t)?, t)))
+            .min()
+            .filter(|&(d, _)| d < here)
+            .map(|(_, t)| t)
+    };
+    let mut lost = false;
+    let position = walk(
+        map,
+        position,
+        &mut chase.patrol,
+        chase.speed,
+        dt,
+        |from, to| toward(to) == Some(from),
+        |tile, _| {
+            let step = toward(tile);
+            lost |= step.is_none();
+            step.unwrap_or(tile)
+        },
+    );
+    if lost {
+        chase.last_seen = None;
+        chase.distances.clear();
+    }
+    position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dfs::random_maze;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn wandering_stays_in_corridors() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let map = random_maze([8, 8]);
+        let tile_of = |p: Vector3| tile_of(&map, p).unwrap();
+        let mut wander = Wander::new(5.0, 0.0);
+        let mut position = tile_center(&map, [3, 3], 0.5) + Vector3::new(0.4, 0.0, -0.2);
+        let mut visited = vec![tile_of(position)];
+        for _ in 0..2000 {
+            let previous = tile_of(position);
+            position = super::wander(
+                &map,
+                position,
+                &mut wander,
+                Vector3::new(-100.0, 0.0, -100.0),
+                0.05,
+                &mut rng,
+            );
+            let tile = tile_of(position);
+            assert_eq!(position.y, 0.5);
+            assert!(tile == previous || map.open_neighbours(previous).any(|t| t == tile));
+            if !visited.contains(&tile) {
+                visited.push(tile);
+            }
+        }
+        // about 140 tiles walked, in a maze of 64
+        assert!(visited.len() > 10);
+    }
+
+    #[test]
+    fn creatures_flee_from_the_player() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let map = random_maze([8, 8]);
+        let mut wander = Wander::new(5.0, 100.0);
+        let player = tile_center(&map, [0, 0], 0.0);
+        let mut position = tile_center(&map, [4, 4], 0.0);
+        let start = position.distance_to(player);
+        for _ in 0..400 {
+            position = super::wander(&map, position, &mut wander, player, 0.05, &mut rng);
+        }
+        // in a perfect maze, fleeing ends in a dead end that is farther from the player, or in the same tile
+        assert!(position.distance_to(player) >= start);
+    }
+
+    #[test]
+    fn hunters_reach_the_player() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let map = random_maze([8, 8]);
+        // deaf, so that only sight starts the chase
+        let mut chase = Chase::new(5.0, 0.0);
+        let mut position = tile_center(&map, [7, 7], 0.0);
+        let player_tile = [0, 0];
+        let player = tile_center(&map, player_tile, 0.0);
+        for _ in 0..4000 {
+            position = super::chase(&map, position, &mut chase, player, 0.05, &mut rng);
+            // the distances follow the tile where the player was last seen
+            match chase.last_seen {
+                Some(goal) => assert_eq!(chase.distances, distances_from(&map, goal)),
+                None => assert!(chase.distances.is_empty()),
+            }
+            if tile_of(&map, position) == Some(player_tile) {
+                return;
+            }
+        }
+        panic!("the player was never caught");
+    }
+}
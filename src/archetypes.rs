@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::fs;
+
+use raylib::prelude::*;
+
+use crate::doors::KeyColor;
+use crate::entities::{Ai, Chase, Collider, Effect, Entity, Mesh, Sprite, Teleporter, Trigger, END, PLAYER, START};
+use crate::goal::{parse_goal, Goal};
+use crate::population::{parse_population, Population};
+use crate::xml::{self, Element};
+
+/// File read when no other archetype file is given
+pub const DEFAULT_ARCHETYPES: &str = "assets/entities.xml";
+
+/// Kind of entity read from an archetype file: the components given to every entity spawned from it
+#[derive(Clone, Debug)]
+pub struct Archetype {
+    /// Height above the floor where the entity is spawned
+    pub height: f32,
+    pub sprite: Option<Sprite>,
+    pub mesh: Option<Mesh>,
+    pub collider: Option<Collider>,
+    pub trigger: Option<Trigger>,
+    pub ai: Option<Ai>,
+    pub teleporter: Option<Teleporter>,
+}
+
+/// Archetypes by name
+#[derive(Clone)]
+pub struct Archetypes {
+    archetypes: HashMap<String, Archetype>,
+    /// Entities placed in levels that don't list their own
+    pub population: Population,
+    /// What the player must do before the end opens, in levels that don't have their own goal
+    pub goal: Goal,
+}
+
+/// Parses a color written as `#rrggbb` or `#rrggbbaa`
+fn parse_color(value: &str) -> Option<Color> {
+    let hex = value.strip_prefix('#')?;
+    if !matches!(hex.len(), 6 | 8) {
+        return None;
+    }
+    let channel = |i: usize| hex.get(i * 2..i * 2 + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+    let alpha = if hex.len() == 8 { channel(3)? } else { 255 };
+    Some(Color::new(channel(0)?, channel(1)?, channel(2)?, alpha))
+}
+
+/// Parses a list of effects separated by spaces, see `Effect::from_name`
+fn parse_effects(value: &str) -> Result<Vec<Effect>, String> {
+    value
+        .split_whitespace()
+        .map(|name| Effect::from_name(name).ok_or_else(|| format!("unknown effect '{name}'")))
+        .collect()
+}
+
+fn parse_archetype(element: &Element) -> Result<Archetype, String> {
+    let number = |name: &str| -> Result<Option<f32>, String> {
+        element
+            .attr(name)
+            .map(|v| v.parse::<f32>().map_err(|_| format!("invalid {name} '{v}'")))
+            .transpose()
+    };
+    let sprite = match element.attr("texture") {
+        Some(texture) => Some(Sprite {
+            texture: texture.to_string(),
+            size: number("size")?.unwrap_or(1.0),
+            tint: match element.attr("tint") {
+                Some(v) => parse_color(v).ok_or_else(|| format!("invalid tint '{v}'"))?,
+                None => Color::WHITE,
+            },
+        }),
+        None => None,
+    };
+    let mesh = match element.attr("model") {
+        Some(model) => Some(Mesh {
+            model: model.to_string(),
+            spin: number("spin")?.unwrap_or(0.0),
+        }),
+        None => None,
+    };
+    let effects = |name: &str| element.attr(name).map_or(Ok(Vec::new()), parse_effects);
+    let trigger = Trigger {
+        on_enter: effects("on-enter")?,
+        on_stay: effects("on-stay")?,
+        on_exit: effects("on-exit")?,
+        touching: false,
+    };
+    let trigger = Some(trigger).filter(|t| !(t.on_enter.is_empty() && t.on_stay.is_empty() && t.on_exit.is_empty()));
+    let mut ai = match element.attr("ai") {
+        Some(v) => Some(Ai::from_name(v).ok_or_else(|| format!("unknown ai '{v}'"))?),
+        None => None,
+    };
+    match &mut ai {
+        Some(Ai::Wander(wander)) => {
+            wander.speed = number("speed")?.unwrap_or(wander.speed);
+            wander.flee_radius = number("flee")?.unwrap_or(wander.flee_radius);
+        }
+        Some(Ai::Chase(chase)) => {
+            *chase = Chase::new(
+                number("speed")?.unwrap_or(chase.speed),
+                number("hearing")?.unwrap_or(chase.hearing_radius),
+            );
+        }
+        _ => {}
+    }
+    let teleporter = match element.attr("teleporter") {
+        Some("true") => Some(Teleporter::new(number("cooldown")?.unwrap_or(1.0) as f64)),
+        Some("false") | None => None,
+        Some(v) => return Err(format!("invalid teleporter '{v}'")),
+    };
+    Ok(Archetype {
+        height: number("height")?.unwrap_or(0.0),
+        sprite,
+        mesh,
+        collider: number("collider")?.map(|radius| Collider { radius }),
+        trigger,
+        ai,
+        teleporter,
+    })
+}
+
+/// Reads the archetypes of an `<archetypes>` document, with one `<archetype>` element per kind of entity. The
+/// player, start and end archetypes must be there, since every game has them. An optional `<population>` element
+/// gives the rules to place entities; without it, the default population is used with the archetypes that exist.
+/// Doors in the population need the archetypes of their keys, see `KeyColor::key`. An optional `<goal>` element
+/// gives what the player must do before the end opens, see `parse_goal`
+pub fn parse_archetypes(source: &str) -> Result<Archetypes, String> {
+    let root = xml::parse(source)?;
+    if root.name != "archetypes" {
+        return Err(format!("expected an <archetypes> element, found <{}>", root.name));
+    }
+    let mut archetypes = HashMap::new();
+    for element in root.children_named("archetype") {
+        let name = element
+            .attr("name")
+            .filter(|name| !name.is_empty())
+            .ok_or("archetype without a name")?
+            .to_lowercase();
+        let archetype = parse_archetype(element).map_err(|e| format!("archetype '{name}': {e}"))?;
+        if archetypes.insert(name.clone(), archetype).is_some() {
+            return Err(format!("archetype '{name}' is defined twice"));
+        }
+    }
+    for required in [PLAYER, START, END] {
+        if !archetypes.contains_key(required) {
+            return Err(format!("archetype '{required}' is missing"));
+        }
+    }
+    let population = match root.children_named("population").next() {
+        Some(element) => {
+            let population = parse_population(element).map_err(|e| format!("population: {e}"))?;
+            if let Some(rule) = population
+                .rules
+                .iter()
+                .find(|rule| !archetypes.contains_key(&rule.archetype))
+            {
+                return Err(format!("population: archetype '{}' is not defined", rule.archetype));
+            }
+            if population.doors > KeyColor::ALL.len() {
+                return Err(format!(
+                    "population: at most {} doors, one of each color",
+                    KeyColor::ALL.len()
+                ));
+            }
+            let colors = KeyColor::ALL.into_iter().take(population.doors);
+            if let Some(key) = colors.map(KeyColor::key).find(|key| !archetypes.contains_key(key)) {
+                return Err(format!(
+                    "population: archetype '{key}' is missing for the keys of the doors"
+                ));
+            }
+            population
+        }
+        None => {
+            let mut population = Population::default();
+            population.rules.retain(|rule| archetypes.contains_key(&rule.archetype));
+            population
+        }
+    };
+    let goal = match root.children_named("goal").next() {
+        Some(element) => parse_goal(|name| element.attr(name)).map_err(|e| format!("goal: {e}"))?,
+        None => Goal::default(),
+    };
+    Ok(Archetypes {
+        archetypes,
+        population,
+        goal,
+    })
+}
+
+/// Loads the archetypes from a file, see `parse_archetypes`
+pub fn load_archetypes(path: &str) -> Result<Archetypes, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    parse_archetypes(&source).map_err(|e| format!("{path}: {e}"))
+}
+
+impl Archetypes {
+    pub fn get(&self, name: &str) -> Option<&Archetype> {
+        self.archetypes.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Archetype)> {
+        self.archetypes
+            .iter()
+            .map(|(name, archetype)| (name.as_str(), archetype))
+    }
+
+    /// Creates an entity of the named archetype standing on the floor at the given position (its height is ignored).
+    /// Unknown archetypes give an entity without components, which can't be seen or touched
+    pub fn spawn(&self, name: &str, floor: Vector3) -> Entity {
+        let Some(archetype) = self.get(name) else {
+            return Entity::new(name, Vector3::new(floor.x, 0.0, floor.z));
+        };
+        let mut entity = Entity::new(name, Vector3::new(floor.x, archetype.height, floor.z));
+        entity.sprite = archetype.sprite.clone();
+        entity.mesh = archetype.mesh.clone();
+        entity.collider = archetype.collider;
+        entity.trigger = archetype.trigger.clone();
+        entity.ai = archetype.ai.clone();
+        entity.teleporter = archetype.teleporter;
+        entity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_archetypes_load() {
+        let archetypes = load_archetypes(DEFAULT_ARCHETYPES).unwrap();
+        let dodecahedron = archetypes.spawn("dodecahedron", Vector3::new(1.0, 9.0, 2.0));
+        assert_eq!(dodecahedron.position(), Vector3::new(1.0, 1.5, 2.0));
+        let trigger = dodecahedron.trigger.clone().unwrap();
+        assert_eq!(trigger.on_enter, vec![Effect::RollCamera]);
+        assert_eq!(dodecahedron.collision_radius(), 1.2);
+        assert_eq!(
+            archetypes
+                .get("opengl")
+                .and_then(|a| a.sprite.as_ref())
+                .map(|s| s.tint.a),
+            Some(0x9a)
+        );
+    }
+
+    #[test]
+    fn bad_archetypes_are_reported() {
+        let required = r#"<archetype name="player"/><archetype name="start"/><archetype name="end"/>"#;
+        let parse = |body: &str| parse_archetypes(&format!("<archetypes>{required}{body}</archetypes>"));
+        assert!(parse(r##"<archetype name="ghost" tint="#fff"/>"##).is_ok());
+        assert!(parse(r##"<archetype name="ghost" texture="g.png" tint="#fff"/>"##).is_err());
+        assert!(parse(r#"<archetype name="ghost" on-enter="haunt"/>"#).is_err());
+        assert!(parse(r#"<archetype name="ghost" on-exit="animate:spin teleport"/>"#).is_err());
+        assert!(parse(r#"<archetype name="ghost" on-stay="animate:spin give-item:ectoplasm"/>"#).is_ok());
+        assert!(parse(r#"<archetype name="player"/>"#).is_err());
+        assert!(parse_archetypes(r#"<archetypes><archetype name="player"/></archetypes>"#).is_err());
+        assert!(parse(r#"<population><spawn archetype="ghost" count="1"/></population>"#).is_err());
+        assert!(parse(r#"<population><spawn archetype="end" place="corner" count="1"/></population>"#).is_err());
+        assert!(parse(r#"<population><spawn archetype="end"/></population>"#).is_err());
+        assert!(parse(r#"<population><doors count="1"/></population>"#).is_err());
+        assert!(parse(r#"<archetype name="red-key"/><population><doors count="1"/></population>"#).is_ok());
+        assert!(parse(r#"<goal collect="smiley" collect-count="none"/>"#).is_err());
+    }
+}
@@ -1,28 +1,37 @@
+use std::collections::HashMap;
+
 use raylib::prelude::*;
 
+use crate::archetypes::Archetypes;
+
 pub struct AssetPack {
-    pub tex_start: Texture2D,
-    pub tex_smiley: Texture2D,
     pub tex_wall: Texture2D,
     pub tex_floor: Texture2D,
     pub tex_ceiling: Texture2D,
-    pub tex_rat: Texture2D,
-    pub tex_opengl: Texture2D,
-    pub model_dodecahedron: Model,
+    /// Images of the sprites of the archetypes, by path
+    sprite_textures: HashMap<String, Texture2D>,
+    /// Models of the meshes of the archetypes, by path
+    mesh_models: HashMap<String, Model>,
+    /// Unit cube with the wall texture, scaled and turned to draw walls that aren't aligned with the axes
+    pub model_wall: Model,
     pub shader_diffuse: Shader,
 }
 
 impl AssetPack {
-    pub fn init(rl: &mut RaylibHandle, thread: &RaylibThread) -> Self {
+    /// Loads the textures of the maze and the images and models named by the archetypes. Fails if one of the
+    /// archetypes' files can't be loaded
+    pub fn init(rl: &mut RaylibHandle, thread: &RaylibThread, archetypes: &Archetypes) -> Result<Self, String> {
         let mut result = Self {
-            tex_start: rl.load_texture(&thread, "assets/3dmaze/start.png").unwrap(),
-            tex_smiley: rl.load_texture(&thread, "assets/3dmaze/smiley.png").unwrap(),
             tex_wall: rl.load_texture(&thread, "assets/3dmaze/wall.png").unwrap(),
             tex_floor: rl.load_texture(&thread, "assets/3dmaze/floor.png").unwrap(),
             tex_ceiling: rl.load_texture(&thread, "assets/3dmaze/ceiling.png").unwrap(),
-            tex_rat: rl.load_texture(&thread, "assets/3dmaze/rat.png").unwrap(),
-            tex_opengl: rl.load_texture(&thread, "assets/3dmaze/opengl.png").unwrap(),
-            model_dodecahedron: rl.load_model(&thread, "assets/dodecahedron.obj").unwrap(),
+            sprite_textures: HashMap::new(),
+            mesh_models: HashMap::new(),
+            model_wall: rl
+                .load_model_from_mesh(thread, unsafe {
+                    Mesh::gen_mesh_cube(thread, 1.0, 1.0, 1.0).make_weak()
+                })
+                .unwrap(),
             shader_diffuse: rl
                 .load_shader(&thread, Some("assets/diffuse.vs"), Some("assets/diffuse.fs"))
                 .unwrap(),
@@ -37,8 +46,39 @@ impl AssetPack {
             .tex_ceiling
             .set_texture_filter(thread, TextureFilter::TEXTURE_FILTER_TRILINEAR);
 
-        result.model_dodecahedron.materials_mut()[0].shader = *result.shader_diffuse;
+        let wall_texture = &result.tex_wall;
+        result.model_wall.materials_mut()[0].set_material_texture(MaterialMapIndex::MATERIAL_MAP_ALBEDO, wall_texture);
+
+        for (name, archetype) in archetypes.iter() {
+            if let Some(path) = archetype.sprite.as_ref().map(|s| &s.texture) {
+                if !result.sprite_textures.contains_key(path) {
+                    let texture = rl
+                        .load_texture(thread, path)
+                        .map_err(|e| format!("archetype '{name}': {e}"))?;
+                    result.sprite_textures.insert(path.clone(), texture);
+                }
+            }
+            if let Some(path) = archetype.mesh.as_ref().map(|m| &m.model) {
+                if !result.mesh_models.contains_key(path) {
+                    let mut model = rl
+                        .load_model(thread, path)
+                        .map_err(|e| format!("archetype '{name}': {e}"))?;
+                    for material in model.materials_mut() {
+                        material.shader = *result.shader_diffuse;
+                    }
+                    result.mesh_models.insert(path.clone(), model);
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub fn sprite_texture(&self, path: &str) -> Option<&Texture2D> {
+        self.sprite_textures.get(path)
+    }
 
-        return result;
+    pub fn mesh_model(&self, path: &str) -> Option<&Model> {
+        self.mesh_models.get(path)
     }
 }
@@ -0,0 +1,63 @@
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes with the standard base64 alphabet, padding the output with '='
+pub fn encode(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let group = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+/// Decodes standard base64, ignoring whitespace. Padding is optional
+pub fn decode(data: &str) -> Result<Vec<u8>, String> {
+    let mut result = Vec::with_capacity(data.len() / 4 * 3);
+    let mut group: u32 = 0;
+    let mut bits = 0;
+    for c in data.chars().filter(|c| !c.is_whitespace()) {
+        if c == '=' {
+            break;
+        }
+        let value = ALPHABET
+            .iter()
+            .position(|&a| a as char == c)
+            .ok_or_else(|| format!("invalid base64 character '{c}'"))?;
+        group = group << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((group >> bits) as u8);
+            group &= (1 << bits) - 1;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_data_decodes_back() {
+        for length in 0..8 {
+            let data: Vec<u8> = (0..length).map(|i| (i * 97 + 200) as u8).collect();
+            assert_eq!(decode(&encode(&data)), Ok(data));
+        }
+        assert_eq!(encode(b"maze"), "bWF6ZQ==");
+        assert_eq!(decode("bWF6\n  ZQ"), Ok(b"maze".to_vec()));
+    }
+
+    #[test]
+    fn invalid_characters_are_rejected() {
+        assert!(decode("bWF6ZQ*=").is_err());
+        assert!(decode("bW-6").is_err());
+    }
+}
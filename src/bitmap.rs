@@ -0,0 +1,173 @@
+use raylib::prelude::*;
+
+use crate::dfs::random_dfs;
+use crate::map::{GetSetMap, Map, MaskedMap, WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST};
+use crate::solver::connected_regions;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BitmapMode {
+    /// Every block of pixels is a cell: black cells are solid, white cells are corridors
+    Tiles,
+    /// The image is a drawing of the maze with cells at odd pixel coordinates and walls between them.
+    /// A 5x5 cell maze is drawn in an 11x11 image, and blocks of pixels are read as one pixel
+    Edges,
+    /// A perfect maze is generated inside the white part of the image. Black cells are solid
+    Mask,
+}
+
+impl BitmapMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tiles" => Some(Self::Tiles),
+            "edges" => Some(Self::Edges),
+            "mask" => Some(Self::Mask),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the image as a grid of black (true) and white (false) blocks of `block_size` pixels.
+/// The center pixel of each block decides its color, and transparent pixels count as black
+fn read_blocks(image: &Image, block_size: usize) -> ([usize; 2], Vec<bool>) {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let pixels = image.get_image_data();
+    let [rows, cols] = [height / block_size, width / block_size];
+    let mut black = Vec::with_capacity(rows * cols);
+    for i in 0..rows {
+        for j in 0..cols {
+            let pixel = pixels[(i * block_size + block_size / 2) * width + j * block_size + block_size / 2];
+            let luminance = 0.299 * pixel.r as f32 + 0.587 * pixel.g as f32 + 0.114 * pixel.b as f32;
+            black.push(pixel.a < 128 || luminance < 128.0);
+        }
+    }
+    ([rows, cols], black)
+}
+
+/// Builds a maze from a black and white image, using blocks of `block_size` pixels as the image's pixels
+pub fn map_from_image(image: &Image, mode: BitmapMode, block_size: usize) -> Result<Map<2>, String> {
+    let ([rows, cols], black) = read_blocks(image, block_size.max(1));
+    if rows == 0 || cols == 0 {
+        return Err("the image is smaller than a single cell".into());
+    }
+    match mode {
+        BitmapMode::Tiles => {
+            let mut map = Map::<2>::from(0, [rows, cols]);
+            map.wall_off_solid_cells(&black);
+            Ok(map)
+        }
+        BitmapMode::Edges => {
+            if rows < 3 || cols < 3 || rows % 2 == 0 || cols % 2 == 0 {
+                return Err(format!(
+                    "edge images must have an odd number of rows and columns (at least 3), found {cols}x{rows}"
+                ));
+            }
+            let is_black = |i: usize, j: usize| black[i * cols + j];
+            let [map_rows, map_cols] = [rows / 2, cols / 2];
+            let mut map = Map::<2>::from(0, [map_rows, map_cols]);
+            let mut solid = vec![false; map_rows * map_cols];
+            for i in 0..map_rows {
+                for j in 0..map_cols {
+                    let [pi, pj] = [2 * i + 1, 2 * j + 1];
+                    solid[i * map_cols + j] = is_black(pi, pj);
+                    let mut walls = 0;
+                    for (wall, [wi, wj]) in [
+                        (WALL_NORTH, [pi - 1, pj]),
+                        (WALL_SOUTH, [pi + 1, pj]),
+                        (WALL_WEST, [pi, pj - 1]),
+                        (WALL_EAST, [pi, pj + 1]),
+                    ] {
+                        if is_black(wi, wj) {
+                            walls |= wall;
+                        }
+                    }
+                    map.set_item([i, j], walls);
+                }
+            }
+            map.wall_off_solid_cells(&solid);
+            Ok(map)
+        }
+        BitmapMode::Mask => {
+            let all_walls = WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH;
+            let mut map = Map::<2>::from(all_walls, [rows, cols]);
+            let inside: Vec<bool> = black.iter().map(|b| !b).collect();
+            if !inside.contains(&true) {
+                return Err("the mask has no white pixels".into());
+            }
+
+            // carve every white area on its own, since generators can't cross the black pixels
+            let areas = {
+                let mut open_map = Map::<2>::from(0, [rows, cols]);
+                open_map.wall_off_solid_cells(&black);
+                connected_regions(&open_map, |[i, j]| inside[i * cols + j])
+            };
+            let mut masked = MaskedMap::from(&mut map, &inside);
+            for area in areas {
+                let [row, col] = area[0];
+                random_dfs(&mut masked, [row as i32, col as i32]);
+            }
+            Ok(map)
+        }
+    }
+}
+
+/// Loads a black and white image (PNG or any other format supported by raylib) and builds a maze from it
+pub fn load_bitmap(path: &str, mode: BitmapMode, block_size: usize) -> Result<Map<2>, String> {
+    let image = Image::load_image(path).map_err(|e| format!("could not load {path}: {e}"))?;
+    map_from_image(&image, mode, block_size).map_err(|e| format!("{path}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::Grid;
+    use crate::map::RectangularMap;
+
+    /// An image of `width`x`height` pixels, white except for the given black pixels, given as [x, y]
+    fn image(width: i32, height: i32, black: &[[i32; 2]]) -> Image {
+        let mut image = Image::gen_image_color(width, height, Color::WHITE);
+        for &[x, y] in black {
+            image.draw_pixel(x, y, Color::BLACK);
+        }
+        image
+    }
+
+    #[test]
+    fn black_blocks_are_solid_tiles() {
+        // blocks of 2x2 pixels, with the top middle block black
+        let map = map_from_image(&image(6, 4, &[[2, 0], [3, 0], [2, 1], [3, 1]]), BitmapMode::Tiles, 2).unwrap();
+        assert_eq!(map.dimensions(), [2, 3]);
+        assert!(map.is_solid([0, 1]));
+        assert_eq!(map.get_item([0, 0]), WALL_NORTH | WALL_WEST | WALL_EAST);
+        assert_eq!(map.get_item([1, 1]), WALL_NORTH | WALL_SOUTH);
+        assert_eq!(map.cells().filter(|&tile| map.is_solid(tile)).count(), 1);
+        assert!(map_from_image(&image(1, 1, &[]), BitmapMode::Tiles, 2).is_err());
+    }
+
+    #[test]
+    fn black_edges_are_walls() {
+        // a 2x2 maze drawn in 5x5 pixels, with a wall between the two top tiles
+        let mut black: Vec<[i32; 2]> = (0..5).flat_map(|i| [[i, 0], [i, 4], [0, i], [4, i]]).collect();
+        black.extend([[2, 2], [2, 1]]);
+        let map = map_from_image(&image(5, 5, &black), BitmapMode::Edges, 1).unwrap();
+        assert_eq!(map.dimensions(), [2, 2]);
+        assert_eq!(map.get_item([0, 0]), WALL_NORTH | WALL_WEST | WALL_EAST);
+        assert_eq!(map.get_item([0, 1]), WALL_NORTH | WALL_EAST | WALL_WEST);
+        assert_eq!(map.get_item([1, 0]), WALL_SOUTH | WALL_WEST);
+        assert_eq!(map.get_item([1, 1]), WALL_SOUTH | WALL_EAST);
+        assert!(map_from_image(&image(4, 5, &black), BitmapMode::Edges, 1).is_err());
+    }
+
+    #[test]
+    fn every_white_area_of_a_mask_gets_its_own_maze() {
+        // a black column splits the image into two 3x3 areas
+        let map = map_from_image(&image(7, 3, &[[3, 0], [3, 1], [3, 2]]), BitmapMode::Mask, 1).unwrap();
+        assert!((0..3).all(|i| map.is_solid([i, 3])));
+        let regions = connected_regions(&map, |tile| !map.is_solid(tile));
+        assert_eq!(regions.iter().map(Vec::len).collect::<Vec<_>>(), [9, 9]);
+        // each area is a perfect maze: a tree with one passage less than its tiles
+        let graph = map.graph();
+        let passages: usize = graph.nodes().map(|tile| graph.degree(tile)).sum::<usize>() / 2;
+        assert_eq!(passages, 16);
+        assert!(map_from_image(&image(1, 1, &[[0, 0]]), BitmapMode::Mask, 1).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use crate::archetypes::DEFAULT_ARCHETYPES;
+use crate::bitmap::BitmapMode;
+use crate::export::ExportOptions;
+use crate::topology::Topology;
+
+pub const USAGE: &str = "\
+Usage: maze3d [OPTIONS]
+
+Options:
+    --size <ROWS>x<COLS>    Dimensions of the generated maze (default: 5x5)
+    --floors <COUNT>        Number of stacked floors of the generated maze, linked by stairs (default: 1)
+    --topology <SHAPE>      Shape of the tiles of the generated maze: square or hex (default: square)
+    --wrap                  Connect the edges of the generated maze to the opposite ones, so corridors wrap around
+    --weave                 Let the corridors of the generated maze pass over and under each other
+    --shift <SECONDS>       Rearrange the walls of a part of the maze every few seconds while playing
+    --endless               Explore an endless maze, generated in chunks of the size given by --size as you walk
+    --seed <NUMBER>         Seed of the endless maze, to explore the same one again (default: random)
+    --minotaur              Release a minotaur that hunts you through the maze, and ends the run if it catches you
+    --tmx <FILE>            Play the level in a map saved by the Tiled editor
+    --entities <FILE>       Read the kinds of entities from this file (default: assets/entities.xml)
+    --bitmap <FILE>         Build the maze from a black and white image
+    --bitmap-mode <MODE>    How the image is read: tiles, edges or mask (default: tiles)
+    --bitmap-block <PIXELS> Size of the block of pixels read as one pixel of the image (default: 1)
+    --validate              Check the maze for one-sided walls, holes in the border and unreachable areas, then exit
+    --repair                Fix the issues that --validate reports before playing or exporting the maze
+    --metrics               Print statistics and the difficulty of the maze, then exit
+    --export-svg <FILE>     Write a top-down SVG of the maze and exit without opening a window
+    --export-png <FILE>     Write a top-down PNG of the maze and exit without opening a window
+    --export-obj <FILE>     Write the maze geometry as a Wavefront OBJ file (plus .mtl and textures) and exit
+    --export-gltf <FILE>    Write the maze geometry as a glTF file (plus textures) and exit
+    --solution              Draw the solution path in exported maps
+    --markers               Draw the start, end and entity positions in exported maps
+    --tile-px <PIXELS>      Size of a tile in exported maps (default: 32)
+    -h, --help              Print this message";
+
+/// Command line options
+pub struct Options {
+    pub map_dimensions: [usize; 2],
+    pub floors: usize,
+    pub topology: Topology,
+    pub wrap: bool,
+    pub weave: bool,
+    pub shift: Option<f64>,
+    pub endless: bool,
+    pub seed: Option<u64>,
+    pub minotaur: bool,
+    pub tmx: Option<String>,
+    /// Path of the archetype file
+    pub archetypes: String,
+    pub bitmap: Option<String>,
+    pub bitmap_mode: BitmapMode,
+    pub bitmap_block_size: usize,
+    pub validate: bool,
+    pub repair: bool,
+    pub metrics: bool,
+    pub export_svg: Option<String>,
+    pub export_png: Option<String>,
+    pub export_obj: Option<String>,
+    pub export_gltf: Option<String>,
+    pub export: ExportOptions,
+    pub help: bool,
+}
+
+fn parse_dimensions(value: &str) -> Result<[usize; 2], String> {
+    let invalid = || format!("invalid maze size '{value}', expected <ROWS>x<COLS>");
+    let (rows, cols) = value.split_once('x').ok_or_else(invalid)?;
+    let rows = rows.parse::<usize>().map_err(|_| invalid())?;
+    let cols = cols.parse::<usize>().map_err(|_| invalid())?;
+    if rows == 0 || cols == 0 {
+        return Err(invalid());
+    }
+    Ok([rows, cols])
+}
+
+impl Options {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self {
+            map_dimensions: [5, 5],
+            floors: 1,
+            topology: Topology::Square,
+            wrap: false,
+            weave: false,
+            shift: None,
+            endless: false,
+            seed: None,
+            minotaur: false,
+            tmx: None,
+            archetypes: DEFAULT_ARCHETYPES.to_string(),
+            bitmap: None,
+            bitmap_mode: BitmapMode::Tiles,
+            bitmap_block_size: 1,
+            validate: false,
+            repair: false,
+            metrics: false,
+            export_svg: None,
+            export_png: None,
+            export_obj: None,
+            export_gltf: None,
+            export: ExportOptions::default(),
+            help: false,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for '{arg}'"));
+            match arg.as_str() {
+                "--size" => options.map_dimensions = parse_dimensions(&value()?)?,
+                "--floors" => {
+                    let v = value()?;
+                    options.floors = v
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| format!("invalid floor count '{v}'"))?;
+                }
+                "--topology" => {
+                    let v = value()?;
+                    options.topology = Topology::from_name(&v).ok_or_else(|| format!("unknown topology '{v}'"))?;
+                }
+                "--wrap" => options.wrap = true,
+                "--weave" => options.weave = true,
+                "--shift" => {
+                    let v = value()?;
+                    let seconds = v
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|&s| s > 0.0)
+                        .ok_or_else(|| format!("invalid shift interval '{v}'"))?;
+                    options.shift = Some(seconds);
+                }
+                "--endless" => options.endless = true,
+                "--seed" => {
+                    let v = value()?;
+                    options.seed = Some(v.parse::<u64>().map_err(|_| format!("invalid seed '{v}'"))?);
+                }
+                "--minotaur" => options.minotaur = true,
+                "--tmx" => options.tmx = Some(value()?),
+                "--entities" => options.archetypes = value()?,
+                "--bitmap" => options.bitmap = Some(value()?),
+                "--bitmap-mode" => {
+                    let v = value()?;
+                    options.bitmap_mode =
+                        BitmapMode::from_name(&v).ok_or_else(|| format!("unknown bitmap mode '{v}'"))?;
+                }
+                "--bitmap-block" => {
+                    let v = value()?;
+                    options.bitmap_block_size = v
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&px| px > 0)
+                        .ok_or_else(|| format!("invalid block size '{v}'"))?;
+                }
+                "--validate" => options.validate = true,
+                "--repair" => options.repair = true,
+                "--metrics" => options.metrics = true,
+                "--export-svg" => options.export_svg = Some(value()?),
+                "--export-png" => options.export_png = Some(value()?),
+                "--export-obj" => options.export_obj = Some(value()?),
+                "--export-gltf" => options.export_gltf = Some(value()?),
+                "--solution" => options.export.show_solution = true,
+                "--markers" => options.export.show_entities = true,
+                "--tile-px" => {
+                    let v = value()?;
+                    options.export.tile_size = v
+                        .parse::<i32>()
+                        .ok()
+                        .filter(|&px| px > 0)
+                        .ok_or_else(|| format!("invalid tile size '{v}'"))?;
+                    options.export.wall_thickness = (options.export.tile_size / 8).max(1);
+                    options.export.margin = options.export.tile_size / 2;
+                }
+                "-h" | "--help" => options.help = true,
+                _ => return Err(format!("unknown option '{arg}'")),
+            }
+        }
+        if options.floors > 1 && options.topology != Topology::Square {
+            return Err("mazes with several floors must have square tiles".to_string());
+        }
+        if options.weave && (options.floors > 1 || options.topology != Topology::Square) {
+            return Err("weave mazes must have square tiles and a single floor".to_string());
+        }
+        if options.endless {
+            if options.tmx.is_some() || options.bitmap.is_some() {
+                return Err("endless mazes are generated and can't be loaded from a file".to_string());
+            }
+            if options.floors > 1 || options.topology != Topology::Square {
+                return Err("endless mazes must have square tiles and a single floor".to_string());
+            }
+            if options.wrap || options.weave || options.shift.is_some() {
+                return Err("endless mazes can't wrap around, weave or shift".to_string());
+            }
+        }
+        if options.shift.is_some() && options.floors > 1 {
+            return Err("mazes with several floors can't shift".to_string());
+        }
+        if options.wrap {
+            let [rows, cols] = options.map_dimensions;
+            if options.floors > 1 {
+                return Err("mazes with several floors can't wrap around".to_string());
+            }
+            if rows < 3 || cols < 3 {
+                return Err("mazes that wrap around must be at least 3x3".to_string());
+            }
+            if options.topology == Topology::Hex && rows % 2 != 0 {
+                return Err("hexagonal mazes that wrap around must have an even number of rows".to_string());
+            }
+        }
+        Ok(options)
+    }
+
+    /// Whether the program only has to write files and should not open a window
+    pub fn is_headless(&self) -> bool {
+        self.metrics
+            || self.export_svg.is_some()
+            || self.export_png.is_some()
+            || self.export_obj.is_some()
+            || self.export_gltf.is_some()
+    }
+}
@@ -4,10 +4,18 @@ pub const SCREEN_H: i32 = 600;
 pub const TARGET_FPS: u32 = 30;
 pub const PI: f32 = 3.141592;
 
+/// Seconds the scoreboard is shown after a level, unless a key skips it
+pub const SCOREBOARD_DURATION: f64 = 8.0;
+
 pub const MOUSE_SENSITIVITY: f32 = 0.05;
 
 pub const TILE_SIZE: f32 = 3.5;
 
+/// Height of the deck of the bridges of weave mazes
+pub const BRIDGE_HEIGHT: f32 = TILE_SIZE * 0.4;
+pub const BRIDGE_EYE_OFFSET: f32 = BRIDGE_HEIGHT * 0.75;
+pub const TUNNEL_EYE_OFFSET: f32 = -TILE_SIZE * 0.3;
+
 pub const PLAYER_SPEED: f32 = 0.2;
 pub const PLAYER_WALK_SPEED: f32 = 0.1;
 pub const PLAYER_SPRINT_SPEED: f32 = 0.3;
@@ -1,26 +1,119 @@
 use raylib::prelude::*;
 
+use crate::constants::{SCREEN_H, SCREEN_W};
+use crate::doors::KeyColor;
 use crate::game::GameState;
+use crate::goal::{best_results, LevelResult};
+use crate::map::RectangularMap;
 
 pub fn draw_debug_text(d: &mut RaylibDrawHandle, camera: &Camera, game: &GameState, player_velocity: Vector3) {
-    let debug_string = format!(
+    let mut debug_string = format!(
         "\
         - Position: {:.3?}\n\
         - Target: {:.3?}\n\
         - Up: {:.3?}\n\
         - Direction: {:.3?}\n\
         - Velocity: {:.3?}\n\
-        - Clock: {:.2}",
+        - Clock: {:.2}\n\
+        - Dead ends: {} / Junctions: {}\n\
+        - Corridors: {} (mean length {:.2})\n\
+        - Solution: {} tiles ({:.1}%)\n\
+        - Difficulty: {:.1}",
         camera.position,
         camera.target,
         camera.up,
         Vector3::forward().transform_with(game.camera_rotation),
         player_velocity,
         game.clock,
+        game.metrics.dead_ends,
+        game.metrics.junctions,
+        game.metrics.corridor_count(),
+        game.metrics.mean_corridor_length(),
+        game.metrics.solution_length.unwrap_or(0),
+        game.metrics.solution_coverage * 100.0,
+        game.metrics.difficulty,
     );
+    if let Some(tower) = &game.tower {
+        debug_string += &format!("\n- Floor: {} / {}", tower.floor + 1, tower.map.dimensions()[0]);
+    }
     d.draw_text(debug_string.as_str(), 10, 10, 20, Color::WHITE);
 }
 
+/// Draws the items the player carries in a row from `position`, sorted by name. Keys are squares of their color, and
+/// other items are their name with how many there are
+pub fn draw_inventory(d: &mut RaylibDrawHandle, game: &GameState, position: Vector2) {
+    let mut items: Vec<(&String, &usize)> = game.inventory.iter().collect();
+    items.sort();
+    let mut x = position.x as i32;
+    let y = position.y as i32;
+    for (item, count) in items {
+        match KeyColor::ALL.into_iter().find(|color| color.key() == *item) {
+            Some(color) => {
+                d.draw_rectangle(x, y, 24, 24, color.color());
+                d.draw_rectangle_lines(x, y, 24, 24, Color::WHITE);
+                x += 32;
+            }
+            None => {
+                let text = format!("{item} x{count}");
+                d.draw_text(&text, x, y + 2, 20, Color::WHITE);
+                x += measure_text(&text, 20) + 12;
+            }
+        }
+    }
+}
+
+/// Draws the score and the progress towards the goal of the level in the top right corner of the screen
+pub fn draw_hud(d: &mut RaylibDrawHandle, game: &GameState) {
+    let mut lines = vec![format!("Score: {}", game.score)];
+    if let (Some(item), Some((collected, count))) = (&game.goal.collect, game.collected()) {
+        lines.push(format!("{item}: {collected}/{count}"));
+    }
+    if let Some((reached, count)) = game.checkpoints() {
+        lines.push(format!("checkpoints: {reached}/{count}"));
+    }
+    if !game.goal_met() {
+        lines.push("the exit is closed".to_string());
+    }
+    for (i, line) in lines.iter().enumerate() {
+        let x = SCREEN_W - measure_text(line, 20) - 10;
+        d.draw_text(line, x, 10 + i as i32 * 24, 20, Color::WHITE);
+    }
+}
+
+/// Number of results listed on the scoreboard
+const SCOREBOARD_ROWS: usize = 5;
+
+/// Draws the result of the last level, followed by the best results of the levels played so far
+pub fn draw_scoreboard(d: &mut RaylibDrawHandle, results: &[LevelResult]) {
+    let center = |text: &str, y: i32, size: i32, color: Color| {
+        (
+            text.to_string(),
+            (SCREEN_W - measure_text(text, size)) / 2,
+            y,
+            size,
+            color,
+        )
+    };
+    let mut lines = vec![center("Level complete", 80, 40, Color::WHITE)];
+    if let Some(last) = results.last() {
+        lines.push(center(&last.to_string(), 150, 20, Color::YELLOW));
+    }
+    lines.push(center("Best levels", 220, 30, Color::WHITE));
+    for (i, result) in best_results(results).into_iter().take(SCOREBOARD_ROWS).enumerate() {
+        let text = format!("{}. {result}", i + 1);
+        lines.push(center(&text, 270 + i as i32 * 30, 20, Color::LIGHTGRAY));
+    }
+    lines.push(center(
+        "Press enter to play the next level",
+        SCREEN_H - 60,
+        20,
+        Color::GRAY,
+    ));
+    for (text, x, y, size, color) in lines {
+        d.draw_text(&text, x, y, size, color);
+    }
+}
+
 pub fn draw_xyz_indicator(d: &mut RaylibDrawHandle, camera_rotation: Matrix, position: Vector2, scale: f32) {
     for (vec, col) in [(Vector3::right(), Color::RED), (Vector3::up(), Color::GREEN), (Vector3::forward(), Color::BLUE)]
     {
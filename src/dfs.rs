@@ -1,71 +1,281 @@
-use crate::map::{GetSetMap, WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST};
+use crate::grid::Grid;
+use crate::map::{GetSetMap, Map, TUNNEL, WALL_DOWN, WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_UP, WALL_WEST};
+use crate::topology::Topology;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{thread_rng, Rng};
 use std::vec::Vec;
 
-const NEIGHBORHOOD_4: [[i32; 2]; 4] = [[1, 0], [-1, 0], [0, 1], [0, -1]];
-const _NEIGHBORHOOD_8: [[i32; 2]; 8] = [[1, 0], [-1, 0], [0, 1], [0, -1], [1, 1], [-1, -1], [-1, 1], [1, -1]];
-
-pub fn get_neighborhood<const N: usize>(
-    map: &impl GetSetMap<i32, 2>,
-    row: i32,
-    col: i32,
-    offsets: &[[i32; 2]; N],
-) -> Vec<[i32; 2]> {
-    Vec::from_iter(
-        offsets
-            .into_iter()
-            .map(|[row_offset, col_offset]| [row + row_offset, col + col_offset])
-            .filter(|[r, c]| map.contains([*r, *c])),
-    )
-}
+/// Offsets to the neighbouring cells of a map with stacked floors, with the walls on both sides of the shared face.
+/// The last two go up and down a floor
+const PASSAGES_6: [(i8, i8, [i32; 3]); 6] = [
+    (WALL_SOUTH, WALL_NORTH, [0, 1, 0]),
+    (WALL_NORTH, WALL_SOUTH, [0, -1, 0]),
+    (WALL_EAST, WALL_WEST, [0, 0, 1]),
+    (WALL_WEST, WALL_EAST, [0, 0, -1]),
+    (WALL_UP, WALL_DOWN, [1, 0, 0]),
+    (WALL_DOWN, WALL_UP, [-1, 0, 0]),
+];
 
-/// Randomized depth-first-search algorithm for maze generation
+/// Randomized depth-first-search algorithm for maze generation. Follows the topology of the map, so it carves square
+/// and hexagonal mazes alike. Only tiles with all their walls are carved into
 pub fn random_dfs(map: &mut impl GetSetMap<i32, 2>, start: [i32; 2]) {
-    let mut stack: Vec<([i32; 2], Option<[i32; 2]>)> = vec![(start, None)];
+    random_dfs_with_rng(map, start, &mut thread_rng());
+}
+
+/// Like `random_dfs`, drawing from the given generator so that a seeded one always carves the same maze
+pub fn random_dfs_with_rng(map: &mut impl GetSetMap<i32, 2>, start: [i32; 2], rng: &mut impl Rng) {
+    let topology = map.topology();
+    let all_walls = topology.all_walls();
+    // each entry is a tile to carve, the tile it is reached from and the walls between them. The start tile is
+    // reached from itself without crossing any wall
+    let start = start.map(|v| v as usize);
+    let mut stack = vec![(start, start, 0, 0)];
+
+    while let Some((position, previous, wall, opposite)) = stack.pop() {
+        let [row, col] = position.map(|v| v as i32);
+        if map.get_walls([row, col]) != all_walls {
+            continue;
+        }
+        let previous = previous.map(|v| v as i32);
+        map.set_walls(previous, map.get_walls(previous) & !wall);
+        map.set_walls([row, col], map.get_walls([row, col]) & !opposite);
+
+        let mut neighbours: Vec<_> = topology
+            .sides(position)
+            .iter()
+            .filter_map(|&(wall, opposite, _)| Some((map.neighbour(position, wall)?, position, wall, opposite)))
+            .collect();
+        neighbours.shuffle(rng);
+        stack.extend(neighbours);
+    }
+}
+
+/// Generates a perfect maze filling a map of the given dimensions
+pub fn random_maze(dimensions: [usize; 2]) -> Map<2> {
+    random_maze_with(Topology::Square, dimensions, false)
+}
 
-    while let Some((current_position, previous)) = stack.pop() {
-        let [row, col] = current_position;
+/// Generates a perfect maze of the given topology filling a map of the given dimensions. See `Map::with_wrapping` for
+/// the dimensions of maps that wrap around
+pub fn random_maze_with(topology: Topology, dimensions: [usize; 2], wraps: bool) -> Map<2> {
+    let mut map = Map::<2>::from(topology.all_walls(), dimensions)
+        .with_topology(topology)
+        .with_wrapping(wraps);
+    random_dfs(&mut map, [0, 0]);
+    map
+}
+
+/// Carves the edges between the given tiles again at random, in place. Only the edges between two of the tiles
+/// change, so the rest of the map and the openings into the region stay as they were. Tiles that are still linked
+/// through the rest of the map aren't joined again, so a perfect maze stays perfect
+pub fn regenerate_region(map: &mut impl GetSetMap<i32, 2>, tiles: &[[usize; 2]]) {
+    regenerate_region_with_rng(map, tiles, &mut thread_rng());
+}
 
-        let mut test_cells = get_neighborhood(map, row, col, &NEIGHBORHOOD_4);
-        test_cells.sort();
+/// Like `regenerate_region`, drawing from the given generator
+pub fn regenerate_region_with_rng(map: &mut impl GetSetMap<i32, 2>, tiles: &[[usize; 2]], rng: &mut impl Rng) {
+    let signed = |tile: [usize; 2]| tile.map(|v| v as i32);
+    let topology = map.topology();
+    let mut edges = Vec::new();
+    for (index, &tile) in tiles.iter().enumerate() {
+        for &(side, opposite, _) in topology.sides(tile) {
+            let Some(neighbour) = map.neighbour(tile, side) else {
+                continue;
+            };
+            if let Some(other) = tiles.iter().position(|&t| t == neighbour) {
+                map.set_walls(signed(tile), map.get_walls(signed(tile)) | side);
+                if index < other {
+                    edges.push((index, other, side, opposite));
+                }
+            }
+        }
+    }
 
-        if let Some([prev_row, prev_col]) = previous {
-            if let Ok(pos) = test_cells.binary_search(&[prev_row, prev_col]) {
-                test_cells.remove(pos);
+    // group the tiles that the rest of the map still links together
+    let mut group: Vec<usize> = (0..tiles.len()).collect();
+    let graph = map.graph();
+    for index in 0..tiles.len() {
+        if group[index] != index {
+            continue;
+        }
+        for (node, _) in graph.breadth_first(tiles[index]) {
+            if let Some(other) = tiles.iter().position(|&t| t == node) {
+                group[other] = index;
             }
-            for prev_neigh in get_neighborhood(map, prev_row, prev_col, &NEIGHBORHOOD_4) {
-                if let Ok(pos) = test_cells.binary_search(&prev_neigh) {
-                    test_cells.remove(pos);
+        }
+    }
+    fn root(group: &mut [usize], mut index: usize) -> usize {
+        while group[index] != index {
+            group[index] = group[group[index]];
+            index = group[index];
+        }
+        index
+    }
+
+    // randomized Kruskal: open the edges that join two groups, until no more can be joined
+    edges.shuffle(rng);
+    for (a, b, side, opposite) in edges {
+        let (root_a, root_b) = (root(&mut group, a), root(&mut group, b));
+        if root_a == root_b {
+            continue;
+        }
+        group[root_b] = root_a;
+        let (tile, neighbour) = (signed(tiles[a]), signed(tiles[b]));
+        map.set_walls(tile, map.get_walls(tile) & !side);
+        map.set_walls(neighbour, map.get_walls(neighbour) & !opposite);
+    }
+}
+
+/// Chance of digging a tunnel under a corridor when the weave generator runs into one
+const TUNNEL_CHANCE: f64 = 0.4;
+
+/// Randomized depth-first search that weaves corridors over and under each other (square tiles only). When the
+/// search runs into a straight corridor with an uncarved tile behind it, it may dig a tunnel under the corridor to
+/// that tile, which turns the corridor tile into a crossing (see `TUNNEL`)
+pub fn random_weave(map: &mut impl GetSetMap<i32, 2>, start: [i32; 2]) {
+    let all_walls = map.topology().all_walls();
+    let signed = |tile: [usize; 2]| tile.map(|v| v as i32);
+    let mut rng = thread_rng();
+    // like in `random_dfs`, plus the corridor crossed by a tunnel if the tile is reached by one
+    let start = start.map(|v| v as usize);
+    let mut stack: Vec<_> = vec![(start, start, 0, 0, None)];
+
+    while let Some((position, previous, wall, opposite, crossing)) = stack.pop() {
+        if map.get_walls(signed(position)) != all_walls {
+            continue;
+        }
+        if let Some(crossing) = crossing {
+            // the corridor may have been carved further since the tunnel was planned
+            if map.get_walls(signed(crossing)) != wall | opposite {
+                continue;
+            }
+            map.set_walls(signed(crossing), wall | opposite | TUNNEL);
+        }
+        map.set_walls(signed(previous), map.get_walls(signed(previous)) & !wall);
+        map.set_walls(signed(position), map.get_walls(signed(position)) & !opposite);
+
+        let mut neighbours = Vec::new();
+        for &(wall, opposite, _) in map.topology().sides(position) {
+            let Some(neighbour) = map.neighbour(position, wall) else {
+                continue;
+            };
+            if map.get_walls(signed(neighbour)) == all_walls {
+                neighbours.push((neighbour, position, wall, opposite, None));
+            } else if map.get_walls(signed(neighbour)) == wall | opposite && rng.gen_bool(TUNNEL_CHANCE) {
+                if let Some(beyond) = map.neighbour(neighbour, wall) {
+                    neighbours.push((beyond, position, wall, opposite, Some(neighbour)));
                 }
             }
         }
+        neighbours.shuffle(&mut rng);
+        stack.extend(neighbours);
+    }
+}
+
+/// Generates a perfect weave maze of square tiles filling a map of the given dimensions
+pub fn random_weave_maze(dimensions: [usize; 2], wraps: bool) -> Map<2> {
+    let mut map = Map::<2>::from(Topology::Square.all_walls(), dimensions).with_wrapping(wraps);
+    random_weave(&mut map, [0, 0]);
+    map
+}
+
+/// Randomized depth-first search through a map of stacked floors, indexed as [floor, row, col].
+///
+/// Stairs are only taken once the search backtracks, so most of a floor is carved before moving to the next one
+/// and floors are linked by few stairs. A cell reached by stairs never leads to more stairs, so no cell is left
+/// without walls on its own floor and no shafts cross several floors
+pub fn random_dfs_floors(map: &mut impl GetSetMap<i32, 3>, start: [i32; 3]) {
+    let all_walls = WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH | WALL_UP | WALL_DOWN;
+    let mut stack: Vec<([i32; 3], Option<usize>)> = vec![(start, None)];
 
-        let current_value = map.get_item([row, col]);
-        if current_value != (WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH) {
+    while let Some((position, passage)) = stack.pop() {
+        if map.get_walls(position) != all_walls {
             continue;
         }
+        let mut reached_by_stairs = false;
+        if let Some(index) = passage {
+            let (wall, opposite, offset) = PASSAGES_6[index];
+            let previous: [i32; 3] = std::array::from_fn(|i| position[i] - offset[i]);
+            map.set_walls(previous, map.get_walls(previous) & !wall);
+            map.set_walls(position, map.get_walls(position) & !opposite);
+            reached_by_stairs = index >= 4;
+        }
 
-        if let Some(previous_position) = previous {
-            if previous_position == [row, col - 1] {
-                map.set_item(previous_position, map.get_item(previous_position) & !WALL_EAST);
-                map.set_item(current_position, map.get_item(current_position) & !WALL_WEST);
-            } else if previous_position == [row, col + 1] {
-                map.set_item(previous_position, map.get_item(previous_position) & !WALL_WEST);
-                map.set_item(current_position, map.get_item(current_position) & !WALL_EAST);
-            } else if previous_position == [row - 1, col] {
-                map.set_item(previous_position, map.get_item(previous_position) & !WALL_SOUTH);
-                map.set_item(current_position, map.get_item(current_position) & !WALL_NORTH);
-            } else if previous_position == [row + 1, col] {
-                map.set_item(previous_position, map.get_item(previous_position) & !WALL_NORTH);
-                map.set_item(current_position, map.get_item(current_position) & !WALL_SOUTH);
+        let mut stairs = Vec::new();
+        let mut corridors = Vec::new();
+        for (index, (_, _, offset)) in PASSAGES_6.iter().enumerate() {
+            let neighbour: [i32; 3] = std::array::from_fn(|i| position[i] + offset[i]);
+            if map.contains(neighbour) {
+                if index < 4 {
+                    corridors.push((neighbour, Some(index)));
+                } else if !reached_by_stairs {
+                    stairs.push((neighbour, Some(index)));
+                }
             }
         }
+        corridors.shuffle(&mut thread_rng());
+        stairs.shuffle(&mut thread_rng());
+        // stairs go deeper in the stack than corridors so they are taken last
+        stack.extend(stairs);
+        stack.extend(corridors);
+    }
+}
+
+/// Generates a maze of stacked floors of the given dimensions ([floors, rows, cols]) in which every cell is reachable
+pub fn random_tower(dimensions: [usize; 3]) -> Map<3> {
+    let mut map = Map::<3>::from(
+        WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH | WALL_UP | WALL_DOWN,
+        dimensions,
+    );
+    random_dfs_floors(&mut map, [0, 0, 0]);
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
 
-        let mut neighbours = get_neighborhood(map, row, col, &NEIGHBORHOOD_4);
-        neighbours.shuffle(&mut thread_rng());
-        for neighbour in neighbours {
-            stack.push((neighbour, Some(current_position)));
+    #[test]
+    fn every_cell_of_a_tower_is_reachable() {
+        for _ in 0..10 {
+            let map = random_tower([3, 4, 5]);
+            let [floors, rows, cols] = [3, 4, 5];
+            let index = |[f, i, j]: [i32; 3]| (f as usize * rows + i as usize) * cols + j as usize;
+            let mut reached = vec![false; floors * rows * cols];
+            reached[0] = true;
+            let mut queue = VecDeque::from([[0, 0, 0]]);
+            while let Some(cell) = queue.pop_front() {
+                for (wall, opposite, offset) in PASSAGES_6 {
+                    let next: [i32; 3] = std::array::from_fn(|i| cell[i] + offset[i]);
+                    if !map.contains(next) || map.get_walls(cell) & wall != 0 {
+                        continue;
+                    }
+                    // passages are open on both sides
+                    assert_eq!(map.get_walls(next) & opposite, 0);
+                    if !reached[index(next)] {
+                        reached[index(next)] = true;
+                        queue.push_back(next);
+                    }
+                }
+            }
+            assert!(reached.iter().all(|&r| r));
+        }
+    }
+
+    #[test]
+    fn adjacent_floors_are_joined_by_stairs() {
+        for _ in 0..10 {
+            let map = random_tower([4, 3, 3]);
+            for f in 0..3 {
+                let (lower, upper) = (map.floor(f), map.floor(f + 1));
+                let stairs: Vec<[usize; 2]> = lower
+                    .cells()
+                    .filter(|&tile| lower.get_walls(tile) & WALL_UP == 0)
+                    .collect();
+                assert!(!stairs.is_empty(), "no stairs from floor {f}");
+                assert!(stairs.iter().all(|&tile| upper.get_walls(tile) & WALL_DOWN == 0));
+            }
         }
     }
 }
@@ -0,0 +1,365 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+use raylib::prelude::*;
+
+use crate::grid::{Grid, Wall};
+use crate::map::{GetSetMap, Map, RectangularMap, WallBits};
+use crate::solver::{distances_from, shortest_path};
+
+/// Colors of the keys and of the doors they open, in the order doors are placed along the solution
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KeyColor {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+}
+
+impl KeyColor {
+    pub const ALL: [Self; 4] = [Self::Red, Self::Yellow, Self::Green, Self::Blue];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Red => "red",
+            Self::Yellow => "yellow",
+            Self::Green => "green",
+            Self::Blue => "blue",
+        }
+    }
+
+    /// Name of the archetype of the keys of this color, which is also the item they give, like `red-key`
+    pub fn key(self) -> String {
+        format!("{}-key", self.name())
+    }
+
+    /// Tint of the doors of this color
+    pub fn color(self) -> Color {
+        match self {
+            Self::Red => Color::new(230, 41, 55, 255),
+            Self::Yellow => Color::new(253, 249, 0, 255),
+            Self::Green => Color::new(0, 228, 48, 255),
+            Self::Blue => Color::new(0, 121, 241, 255),
+        }
+    }
+}
+
+/// Seconds an open door takes to sink into the floor
+pub const DOOR_OPEN_DURATION: f64 = 1.0;
+
+/// Door on an edge between two cells, locked until the player brings the key of its color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Door {
+    pub color: KeyColor,
+    /// Time when the door was opened, while it is closed `None`
+    pub opened_at: Option<f64>,
+}
+
+/// Cell of a maze with doors. A closed door is a wall of both cells it separates, so everything that stops at walls
+/// (collisions, the solver, sight and creatures) stops at doors too. Under a door the edge is always a passage, so
+/// generators can't wall it up, and opening the door leaves the way through
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DoorCell {
+    walls: i8,
+    /// Door on each side of the cell, indexed by the bit of the side
+    doors: [Option<Door>; 6],
+}
+
+impl DoorCell {
+    /// The door on a side of the cell, open or closed
+    pub fn door(&self, side: i8) -> Option<Door> {
+        self.doors.get(side.trailing_zeros() as usize).copied().flatten()
+    }
+
+    /// Sides of the cell with a closed door
+    fn closed_doors(&self) -> i8 {
+        (0..self.doors.len())
+            .filter(|&k| self.doors[k].is_some_and(|door| door.opened_at.is_none()))
+            .fold(0, |sides, k| sides | 1 << k)
+    }
+}
+
+impl WallBits for DoorCell {
+    fn walls(&self) -> i8 {
+        self.walls | self.closed_doors()
+    }
+    fn set_walls(&mut self, walls: i8) {
+        self.walls = walls & !self.closed_doors();
+    }
+}
+
+/// The wall on the edge between two neighbouring tiles, as `Grid::walls` returns it. Tiles that are only linked
+/// through the tunnel of a crossing have none
+fn wall_between(map: &impl Grid, a: [usize; 2], b: [usize; 2]) -> Option<Wall> {
+    let tile = a.max(b);
+    let other = a.min(b);
+    let topology = map.topology();
+    let &(side, _, _) = topology
+        .sides(tile)
+        .iter()
+        .find(|&&(side, _, _)| map.neighbour(tile, side) == Some(other))?;
+    Some(Wall { tile, side })
+}
+
+impl Map<2, DoorCell> {
+    /// Puts a door on a wall, or takes it away, on both of the tiles that share the wall
+    fn set_door(&mut self, wall: Wall, door: Option<Door>) {
+        let topology = self.topology();
+        let sides = topology.sides(wall.tile);
+        let Some(&(side, opposite, _)) = sides.iter().find(|&&(side, _, _)| side == wall.side) else {
+            return;
+        };
+        let neighbour = self.neighbour(wall.tile, side);
+        for (tile, side) in std::iter::once((wall.tile, side)).chain(neighbour.map(|n| (n, opposite))) {
+            let mut cell = self.get_item(tile);
+            cell.walls &= !side;
+            cell.doors[side.trailing_zeros() as usize] = door;
+            self.set_item(tile, cell);
+        }
+    }
+
+    /// Every door of the map, open or closed, on its wall as `Grid::walls` returns it
+    pub fn doors(&self) -> impl Iterator<Item = (Wall, Door)> + '_ {
+        self.cells().flat_map(move |tile| {
+            self.topology().sides(tile).iter().filter_map(move |&(side, _, _)| {
+                let shared = self.neighbour(tile, side).is_some_and(|n| n > tile);
+                let door = self.get_item(tile).door(side).filter(|_| !shared)?;
+                Some((Wall { tile, side }, door))
+            })
+        })
+    }
+
+    /// Color of the closed door on a wall
+    pub fn closed_door(&self, wall: Wall) -> Option<KeyColor> {
+        let door = self.get_item(wall.tile).door(wall.side)?;
+        door.opened_at.is_none().then_some(door.color)
+    }
+
+    /// Whether a closed door is on one of the sides of the tile
+    pub fn touches_door(&self, tile: [usize; 2]) -> bool {
+        self.get_item(tile).closed_doors() != 0
+    }
+
+    /// Closes a door of the given color between two neighbouring tiles. Returns whether there was an edge to close
+    pub fn close_door(&mut self, a: [usize; 2], b: [usize; 2], color: KeyColor) -> bool {
+        let Some(wall) = wall_between(self, a, b) else {
+            return false;
+        };
+        self.set_door(wall, Some(Door { color, opened_at: None }));
+        true
+    }
+
+    /// Opens the closed door on a wall, at `clock`
+    pub fn open_door(&mut self, wall: Wall, clock: f64) {
+        if let Some(color) = self.closed_door(wall) {
+            self.set_door(
+                wall,
+                Some(Door {
+                    color,
+                    opened_at: Some(clock),
+                }),
+            );
+        }
+    }
+
+    /// Copy of the map without the doors of the colors for which `open` is true
+    pub fn without_doors(&self, open: impl Fn(KeyColor) -> bool) -> Self {
+        let mut map = self.clone();
+        let doors: Vec<(Wall, Door)> = self.doors().filter(|(_, door)| open(door.color)).collect();
+        for (wall, _) in doors {
+            map.set_door(wall, None);
+        }
+        map
+    }
+
+    /// Copy of the map with every door open, for paths that may go through doors once their keys are found
+    pub fn opened(&self) -> Self {
+        self.without_doors(|_| true)
+    }
+
+    /// Whether each of the keys (with the color of its door) can be reached from `start` with the doors before its own
+    /// open and the others closed, as `lock` places them
+    pub fn keys_reachable(&self, start: [usize; 2], keys: &[(KeyColor, [usize; 2])]) -> bool {
+        keys.iter().all(|&(color, key)| {
+            let map = self.without_doors(|door| door < color);
+            shortest_path(&map, start, key).is_some()
+        })
+    }
+
+    /// Locks up to `count` edges of the shortest path from `start` to `end` with doors, one color each, and picks
+    /// a tile for the key of each door. Doors are numbered along the path, and the key of a door is put on a tile that
+    /// can be reached from the start with the doors before it open and the others closed, so the player can always
+    /// find the keys in order. Keys prefer dead ends and avoid the `taken` tiles and crossings. Fewer doors are placed
+    /// when the path is too short to make room for their keys. Returns the colors of the doors with the tiles of their
+    /// keys
+    pub fn lock(
+        &mut self,
+        start: [usize; 2],
+        end: [usize; 2],
+        count: usize,
+        taken: &[[usize; 2]],
+        rng: &mut impl Rng,
+    ) -> Vec<(KeyColor, [usize; 2])> {
+        let count = count.min(KeyColor::ALL.len());
+        let map = &*self;
+        let graph = map.graph();
+        // tiles where keys can go, among those that can be reached from the start with the given edges closed
+        let key_tiles = |closed_edges: &[[[usize; 2]; 2]]| {
+            let mut closed = map.clone();
+            for &[a, b] in closed_edges {
+                closed.close_door(a, b, KeyColor::Red);
+            }
+            let distances = distances_from(&closed, start);
+            graph
+                .nodes()
+                .filter(|&tile| distances[graph.index(tile)].is_some() && tile != start && tile != end)
+                .filter(|&tile| !map.is_crossing(tile) && !taken.contains(&tile))
+                .collect::<Vec<[usize; 2]>>()
+        };
+        // a door is only put where there is room for the keys of every door before the end
+        let path = shortest_path(map, start, end).unwrap_or_default();
+        let mut edges: Vec<[[usize; 2]; 2]> = path
+            .windows(2)
+            .map(|step| [step[0], step[1]])
+            .filter(|&[a, b]| !map.is_crossing(a) && !map.is_crossing(b) && wall_between(map, a, b).is_some())
+            .filter(|&edge| key_tiles(&[edge]).len() >= count)
+            .collect();
+        edges.shuffle(rng);
+        edges.truncate(count);
+        edges.sort_by_key(|&[a, _]| path.iter().position(|&tile| tile == a));
+
+        let mut keys: Vec<[usize; 2]> = Vec::new();
+        let mut locked = Vec::new();
+        for (k, &[a, b]) in edges.iter().enumerate() {
+            let mut candidates = key_tiles(&edges[k..]);
+            candidates.retain(|tile| !keys.contains(tile));
+            candidates.shuffle(rng);
+            let key = candidates
+                .iter()
+                .find(|&&tile| graph.degree(tile) == 1)
+                .or(candidates.first())
+                .copied();
+            if let Some(key) = key {
+                keys.push(key);
+                locked.push(([a, b], key));
+            }
+        }
+
+        let mut placed = Vec::new();
+        for (([a, b], key), color) in locked.into_iter().zip(KeyColor::ALL) {
+            if self.close_door(a, b, color) {
+                placed.push((color, key));
+            }
+        }
+        placed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dfs::{random_maze, regenerate_region};
+    use crate::map::positions;
+    use rand::thread_rng;
+
+    /// Whether a player walking from `start`, picking up every key in reach and opening the doors they match, gets
+    /// to `end`
+    fn solvable(map: &Map<2, DoorCell>, start: [usize; 2], end: [usize; 2], keys: &[(KeyColor, [usize; 2])]) -> bool {
+        let mut map = map.clone();
+        let mut found = Vec::new();
+        while shortest_path(&map, start, end).is_none() {
+            let before = found.len();
+            for &(color, key) in keys.iter() {
+                if !found.contains(&color) && shortest_path(&map, start, key).is_some() {
+                    found.push(color);
+                }
+            }
+            if found.len() == before {
+                return false;
+            }
+            let opening: Vec<Wall> = map
+                .doors()
+                .filter(|(_, door)| found.contains(&door.color))
+                .map(|(wall, _)| wall)
+                .collect();
+            for wall in opening {
+                map.open_door(wall, 0.0);
+            }
+        }
+        true
+    }
+
+    #[test]
+    fn keys_are_found_before_their_doors() {
+        for _ in 0..20 {
+            let mut map: Map<2, DoorCell> = random_maze([9, 9]).convert();
+            let (start, end) = ([0, 0], [8, 8]);
+            let keys = map.lock(start, end, 3, &[[0, 1]], &mut thread_rng());
+            assert_eq!(keys.len(), 3);
+            assert_eq!(map.doors().count(), 3);
+            assert!(shortest_path(&map, start, end).is_none());
+            assert!(shortest_path(&map.opened(), start, end).is_some());
+            assert!(keys.iter().all(|&(_, key)| key != [0, 1] && key != start && key != end));
+            assert!(map.keys_reachable(start, &keys));
+            assert!(solvable(&map, start, end, &keys));
+        }
+    }
+
+    #[test]
+    fn regenerated_regions_are_checked_against_the_locks() {
+        let mut rng = thread_rng();
+        let (mut kept, mut broken) = (0, 0);
+        for _ in 0..100 {
+            let mut map: Map<2, DoorCell> = random_maze([6, 6]).convert();
+            let (start, end) = ([0, 0], [5, 5]);
+            let keys = map.lock(start, end, 3, &[], &mut rng);
+            // a region over the doors and the keys, without the tiles next to the doors like `GameState::shift_maze`
+            let origin = [rng.gen_range(0..=2), rng.gen_range(0..=2)];
+            let tiles: Vec<[usize; 2]> = positions([4, 4])
+                .map(|[i, j]| [origin[0] + i, origin[1] + j])
+                .filter(|&tile| !map.touches_door(tile) && tile != start)
+                .collect();
+            regenerate_region(&mut map, &tiles);
+            assert_eq!(map.doors().count(), keys.len());
+            assert!(shortest_path(&map.opened(), start, end).is_some());
+            // the new walls may open a way around a door, or move a key behind it
+            if map.keys_reachable(start, &keys) && shortest_path(&map, start, end).is_none() {
+                assert!(solvable(&map, start, end, &keys));
+                kept += 1;
+            } else {
+                broken += 1;
+            }
+        }
+        assert!(
+            kept > 0 && broken > 0,
+            "{kept} regions kept the locks, {broken} did not"
+        );
+    }
+
+    #[test]
+    fn generators_leave_the_edges_under_doors_open() {
+        let mut map: Map<2, DoorCell> = Map::from(DoorCell::default(), [1, 2]);
+        assert!(map.close_door([0, 0], [0, 1], KeyColor::Green));
+        let (wall, door) = map.doors().next().unwrap();
+        assert_eq!(map.closed_door(wall), Some(KeyColor::Green));
+        assert_eq!(map.get_walls([0, 1]) & wall.side, wall.side);
+        assert!(map.touches_door([0, 0]) && map.touches_door([0, 1]));
+
+        // walls written over the door stay under it, and the door still opens onto a passage
+        map.set_walls([0, 0], map.get_walls([0, 0]) | map.topology().all_walls());
+        map.set_walls([0, 1], map.topology().all_walls());
+        map.open_door(wall, 2.0);
+        assert_eq!(map.closed_door(wall), None);
+        assert_eq!(
+            map.doors().next(),
+            Some((
+                wall,
+                Door {
+                    opened_at: Some(2.0),
+                    ..door
+                }
+            ))
+        );
+        assert!(shortest_path(&map, [0, 0], [0, 1]).is_some());
+        assert!(!map.touches_door([0, 0]));
+    }
+}
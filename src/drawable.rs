@@ -1,11 +1,16 @@
+use std::collections::HashSet;
+
 use raylib::math::Rectangle;
 use raylib::prelude::*;
 
 use crate::assets::AssetPack;
-use crate::constants::TILE_SIZE;
-use crate::entities::Entity;
-use crate::game::GameState;
-use crate::map::{GetSetMap, Map, RectangularMap, WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST};
+use crate::constants::{BRIDGE_HEIGHT, TILE_SIZE};
+use crate::doors::{DoorCell, DOOR_OPEN_DURATION};
+use crate::entities::{AnimationKind, Entity, END};
+use crate::game::{GameState, Tower};
+use crate::grid::{Grid, Wall};
+use crate::map::{positions, GetSetMap, Map, RectangularMap, WALL_DOWN, WALL_UP};
+use crate::topology::Topology;
 
 pub trait Drawable<T> {
     fn draw(&self, game: &GameState, d3d: &mut RaylibMode3D<T>, camera: &Camera3D, assets: &AssetPack);
@@ -20,103 +25,82 @@ impl<T> Drawable<T> for Entity {
         } else {
             1.0
         };
-        fn draw_billboard_yscaled<T>(
-            entity: &Entity,
-            _game: &GameState,
-            d3d: &mut RaylibMode3D<T>,
-            camera: &Camera3D,
-            tex: &Texture2D,
-            size: f32,
-            scale_y: f32,
-            color: Color,
-        ) {
+
+        // bounces lift the entity, spins turn it around once and shrinks scale it down to nothing
+        let (lift, turn, scale) = match self.animation {
+            Some(animation) => {
+                let t = animation.progress(game.clock);
+                match animation.kind {
+                    AnimationKind::Bounce => ((t * std::f32::consts::PI).sin(), 0.0, 1.0),
+                    AnimationKind::Spin => (0.0, t * 360.0, 1.0),
+                    AnimationKind::Shrink => (0.0, 0.0, 1.0 - t),
+                }
+            }
+            None => (0.0, 0.0, 1.0),
+        };
+        let position = self.position + Vector3::new(0.0, lift, 0.0);
+        // the end is see-through while it is closed, and so are the checkpoints already reached
+        let faded = (self.is(END) && !game.goal_met()) || self.checkpoint.is_some_and(|n| n < game.checkpoints_reached);
+        let tint = |color: Color| {
+            if faded {
+                Color {
+                    a: color.a / 4,
+                    ..color
+                }
+            } else {
+                color
+            }
+        };
+
+        // colliders are only shown for entities that can be seen, which leaves out the player
+        let visible = self.sprite.is_some() || self.mesh.is_some();
+        if let Some(collider) = self.collider.filter(|_| visible) {
+            d3d.draw_circle_3D(
+                Vector3::new(self.position.x, 0.1, self.position.z),
+                collider.radius,
+                Vector3::right(),
+                90.0,
+                Color::GREEN,
+            );
+        }
+        if let Some((sprite, tex)) = self
+            .sprite
+            .as_ref()
+            .and_then(|s| Some((s, assets.sprite_texture(&s.texture)?)))
+        {
             d3d.draw_billboard_rec(
                 *camera,
-                &tex,
+                tex,
                 Rectangle::new(
                     0.0,
-                    tex.height() as f32 * (1.0 - scale_y),
+                    tex.height() as f32 * (1.0 - y_axis_scale),
                     tex.width() as f32,
-                    tex.height() as f32 * scale_y,
+                    tex.height() as f32 * y_axis_scale,
                 ),
-                entity.position() * Vector3::new(1.0, scale_y, 1.0),
-                size,
-                color,
+                position * Vector3::new(1.0, y_axis_scale, 1.0),
+                // billboards always face the camera, so spinning ones get narrower and wider instead
+                sprite.size * scale * turn.to_radians().cos().abs().max(0.05),
+                tint(sprite.tint),
             );
         }
-
-        if self.collision_radius() > 0.0 {
-            match self {
-                Entity::Player { .. } => {},
-                _ => {
-                    d3d.draw_circle_3D(
-                        Vector3::new(self.position().x, 0.1, self.position().z),
-                        self.collision_radius(),
-                        Vector3::right(),
-                        90.0,
-                        Color::GREEN,
-                    );
-                }
-            }
-        }
-        match self {
-            Entity::Start { .. } => draw_billboard_yscaled(
-                self,
-                game,
-                d3d,
-                camera,
-                &assets.tex_start,
-                3.0,
-                y_axis_scale,
-                Color::new(255, 255, 255, 128),
-            ),
-            Entity::Rat { .. } => draw_billboard_yscaled(
-                self,
-                game,
-                d3d,
-                camera,
-                &assets.tex_rat,
-                2.0,
-                y_axis_scale,
-                Color::WHITE,
-            ),
-            Entity::OpenGL { .. } => draw_billboard_yscaled(
-                self,
-                game,
-                d3d,
-                camera,
-                &assets.tex_opengl,
-                3.0,
-                y_axis_scale,
-                Color::new(255, 255, 255, 154),
-            ),
-            Entity::End { .. } => draw_billboard_yscaled(
-                self,
-                game,
-                d3d,
-                camera,
-                &assets.tex_smiley,
-                3.0,
-                y_axis_scale,
-                Color::new(255, 255, 255, 128),
-            ),
-            Entity::Dodecahedron { position, .. } => {
-                d3d.draw_model_ex(
-                    &assets.model_dodecahedron,
-                    position,
-                    Vector3::one(),
-                    -40.0 * game.clock as f32,
-                    Vector3::one(),
-                    Color::WHITE,
-                );
-            },
-            Entity::Player { .. } => {},
+        if let Some((mesh, model)) = self.mesh.as_ref().and_then(|m| Some((m, assets.mesh_model(&m.model)?))) {
+            d3d.draw_model_ex(
+                model,
+                position,
+                Vector3::one(),
+                mesh.spin * game.clock as f32 + turn,
+                Vector3::one() * scale,
+                tint(Color::WHITE),
+            );
         }
     }
 }
 
-impl<T> Drawable<T> for Map<2> {
-    fn draw(&self, game: &GameState, d3d: &mut RaylibMode3D<T>, _camera: &Camera3D, texture: &AssetPack) {
+/// Distance from the camera up to which the copies around a map that wraps around are drawn
+const WRAP_VIEW_DISTANCE: f32 = 10.0 * TILE_SIZE;
+
+impl<T> Drawable<T> for Map<2, DoorCell> {
+    fn draw(&self, game: &GameState, d3d: &mut RaylibMode3D<T>, camera: &Camera3D, texture: &AssetPack) {
         //let wall_height = game.game_time.min(1.0) as f32 * TILE_SIZE;
         let wall_height = TILE_SIZE
             * if let Some(e) = &game.game_start_event {
@@ -126,79 +110,265 @@ impl<T> Drawable<T> for Map<2> {
             } else {
                 1.0
             };
-        for i in 0..self.dimensions()[0] {
-            for j in 0..self.dimensions()[1] {
-                let x = j as f32 * TILE_SIZE + TILE_SIZE / 2.0;
-                let z = i as f32 * TILE_SIZE + TILE_SIZE / 2.0;
-                d3d.draw_cube_texture(
-                    &texture.tex_floor,
-                    Vector3::new(x, 0.0, z),
-                    TILE_SIZE,
-                    0.01,
-                    TILE_SIZE,
-                    Color::WHITE,
-                );
-                d3d.draw_cube_texture(
-                    &texture.tex_ceiling,
-                    Vector3::new(x, TILE_SIZE, z),
-                    TILE_SIZE,
-                    0.01,
-                    TILE_SIZE,
-                    Color::WHITE,
-                );
-                let v = self.get_item([i, j]);
-                if v == WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH {
-                    d3d.draw_cube_texture(
+        let topology = self.topology();
+        // maps that wrap around are surrounded by copies of themselves, drawn only near the camera
+        let copies: Vec<Vector2> = if self.wraps() {
+            let period = topology.period(self.dimensions()) * TILE_SIZE;
+            positions([3, 3])
+                .map(|[i, j]| Vector2::new((j as f32 - 1.0) * period.x, (i as f32 - 1.0) * period.y))
+                .collect()
+        } else {
+            vec![Vector2::zero()]
+        };
+        let camera_position = Vector2::new(camera.position.x, camera.position.z);
+        let visible = |offset: Vector2, point: Vector2| {
+            offset == Vector2::zero() || (point - camera_position).length() < WRAP_VIEW_DISTANCE
+        };
+        // while a shifting maze moves, new walls rise as the walls that are gone sink into the floor
+        let shift = match (&game.shift_event, game.shift.as_ref().and_then(|s| s.previous.as_ref())) {
+            (Some(e), Some(previous)) => {
+                let progress = e.elapsed_normalized(game.clock) as f32;
+                let previous_walls: HashSet<Wall> = previous.walls().collect();
+                let current_walls: HashSet<Wall> = self.walls().collect();
+                let sinking = previous_walls.difference(&current_walls).copied().collect();
+                Some(WallShift {
+                    previous_walls,
+                    sinking,
+                    progress,
+                })
+            }
+            _ => None,
+        };
+        let walls = self.moving_walls(shift.as_ref(), game.clock);
+        for offset in copies {
+            self.draw_copy(d3d, texture, offset, wall_height, &walls, |point| {
+                visible(offset, point)
+            });
+        }
+    }
+}
+
+/// Walls that changed in the last shift of the maze, see `GameState::shift_maze`
+struct WallShift {
+    previous_walls: HashSet<Wall>,
+    /// Walls of the maze before the shift that aren't there anymore
+    sinking: Vec<Wall>,
+    /// How far the walls have moved, from 0 to 1
+    progress: f32,
+}
+
+impl Map<2, DoorCell> {
+    /// Walls of the map with the part of their height they have risen to, and their tint. While a shifting maze moves,
+    /// the new walls rise and the walls that are gone are still sinking. Closed doors are walls tinted by their color,
+    /// and doors opened less than `DOOR_OPEN_DURATION` seconds before `clock` are sinking too
+    fn moving_walls(&self, shift: Option<&WallShift>, clock: f64) -> Vec<(Wall, f32, Color)> {
+        let walls = self.walls().map(|wall| {
+            let tint = self.closed_door(wall).map_or(Color::WHITE, |color| color.color());
+            match shift {
+                Some(shift) if !shift.previous_walls.contains(&wall) => (wall, shift.progress, tint),
+                _ => (wall, 1.0, tint),
+            }
+        });
+        let sinking = shift.into_iter().flat_map(|shift| {
+            shift
+                .sinking
+                .iter()
+                .map(|&wall| (wall, 1.0 - shift.progress, Color::WHITE))
+        });
+        let opening = self.doors().filter_map(|(wall, door)| {
+            let t = (clock - door.opened_at?) / DOOR_OPEN_DURATION;
+            (t < 1.0).then_some((wall, 1.0 - t as f32, door.color.color()))
+        });
+        walls.chain(sinking).chain(opening).collect()
+    }
+
+    /// Draws the floor, ceiling and walls of the map moved by `offset`, skipping the parts whose center isn't visible.
+    /// The walls are given with the part of their height they have risen to and their tint, see `moving_walls`
+    fn draw_copy<T>(
+        &self,
+        d3d: &mut RaylibMode3D<T>,
+        texture: &AssetPack,
+        offset: Vector2,
+        wall_height: f32,
+        walls: &[(Wall, f32, Color)],
+        visible: impl Fn(Vector2) -> bool,
+    ) {
+        let topology = self.topology();
+        // tiles are drawn as rectangles one tile wide and one row high centered on the tile, which cover hexagonal
+        // maps too since their rows are shifted like bricks
+        let floor_depth = topology.row_height() * TILE_SIZE;
+        for tile in self.cells() {
+            let center = topology.center(tile) * TILE_SIZE + offset;
+            if !visible(center) {
+                continue;
+            }
+            let (x, z) = (center.x, center.y);
+            d3d.draw_cube_texture(
+                &texture.tex_floor,
+                Vector3::new(x, 0.0, z),
+                TILE_SIZE,
+                0.01,
+                floor_depth,
+                Color::WHITE,
+            );
+            d3d.draw_cube_texture(
+                &texture.tex_ceiling,
+                Vector3::new(x, TILE_SIZE, z),
+                TILE_SIZE,
+                0.01,
+                floor_depth,
+                Color::WHITE,
+            );
+            if self.is_crossing(tile) {
+                self.draw_crossing(d3d, texture, tile, offset, wall_height);
+            }
+            if self.is_solid(tile) {
+                match topology {
+                    Topology::Square => d3d.draw_cube_texture(
                         &texture.tex_wall,
                         Vector3::new(x, wall_height / 2.0, z),
                         TILE_SIZE,
                         wall_height,
                         TILE_SIZE,
                         Color::WHITE,
+                    ),
+                    // raylib puts the first corner of the cylinder to the south, so this is a pointy-top hexagon
+                    Topology::Hex => d3d.draw_cylinder(
+                        Vector3::new(x, 0.0, z),
+                        TILE_SIZE * topology.corner_radius(),
+                        TILE_SIZE * topology.corner_radius(),
+                        wall_height,
+                        6,
+                        Color::GRAY,
+                    ),
+                }
+            }
+        }
+        // walls touching a solid tile are already drawn as a face of its cube
+        for &(wall, scale, tint) in walls {
+            if self.is_solid(wall.tile) || self.neighbour(wall.tile, wall.side).is_some_and(|n| self.is_solid(n)) {
+                continue;
+            }
+            let [start, end] = wall.segment(topology).map(|p| p * TILE_SIZE + offset);
+            let middle = (start + end) / 2.0;
+            if !visible(middle) {
+                continue;
+            }
+            draw_wall(d3d, texture, [start, end], 0.0, wall_height * scale, tint);
+        }
+    }
+
+    /// Draws the bridge deck of a crossing, the railings along it and the sides of the tunnel under it. Like the
+    /// walls, they grow with `wall_height`
+    fn draw_crossing<T>(
+        &self,
+        d3d: &mut RaylibMode3D<T>,
+        texture: &AssetPack,
+        tile: [usize; 2],
+        offset: Vector2,
+        wall_height: f32,
+    ) {
+        let scale = wall_height / TILE_SIZE;
+        let deck_top = BRIDGE_HEIGHT * scale;
+        let deck_thickness = TILE_SIZE * 0.05 * scale;
+        let center = self.topology().center(tile) * TILE_SIZE + offset;
+        d3d.draw_cube_texture(
+            &texture.tex_floor,
+            Vector3::new(center.x, deck_top - deck_thickness / 2.0, center.y),
+            TILE_SIZE,
+            deck_thickness,
+            TILE_SIZE,
+            Color::WHITE,
+        );
+        let segment = |side| self.topology().wall_segment(tile, side).map(|p| p * TILE_SIZE + offset);
+        for side in self.crossing_walls(tile, true) {
+            draw_wall(
+                d3d,
+                texture,
+                segment(side),
+                deck_top,
+                TILE_SIZE * 0.25 * scale,
+                Color::WHITE,
+            );
+        }
+        for side in self.crossing_walls(tile, false) {
+            draw_wall(d3d, texture, segment(side), 0.0, deck_top, Color::WHITE);
+        }
+    }
+}
+
+/// Draws a wall standing on the segment between two points of the xz plane, from `bottom` up to `bottom + height`,
+/// with its texture tinted by `tint`
+fn draw_wall<T>(
+    d3d: &mut RaylibMode3D<T>,
+    texture: &AssetPack,
+    [start, end]: [Vector2; 2],
+    bottom: f32,
+    height: f32,
+    tint: Color,
+) {
+    let middle = (start + end) / 2.0;
+    let direction = end - start;
+    if direction.x.abs() < 0.001 || direction.y.abs() < 0.001 {
+        d3d.draw_cube_texture(
+            &texture.tex_wall,
+            Vector3::new(middle.x, bottom + height / 2.0, middle.y),
+            direction.x.abs().max(0.001),
+            height,
+            direction.y.abs().max(0.001),
+            tint,
+        );
+    } else {
+        // angled walls are a unit cube scaled along the wall and turned around the vertical axis
+        d3d.draw_model_ex(
+            &texture.model_wall,
+            Vector3::new(middle.x, bottom + height / 2.0, middle.y),
+            Vector3::up(),
+            (-direction.y).atan2(direction.x).to_degrees(),
+            Vector3::new(direction.length(), height, 0.001),
+            tint,
+        );
+    }
+}
+
+/// Number of steps of a staircase between two floors
+const STAIR_STEPS: usize = 6;
+
+impl<T> Drawable<T> for Tower {
+    /// Draws the stairs of the floor the player is on. Stairs going up fill the west half of their tile and rise
+    /// towards the north. The way down is an opening in the east half of the floor
+    fn draw(&self, _game: &GameState, d3d: &mut RaylibMode3D<T>, _camera: &Camera3D, texture: &AssetPack) {
+        let [_, rows, cols] = self.map.dimensions();
+        let step_depth = TILE_SIZE / STAIR_STEPS as f32;
+        for [i, j] in positions([rows, cols]) {
+            let walls = self.map.get_walls([self.floor, i, j]);
+            let (x0, z0) = (j as f32 * TILE_SIZE, i as f32 * TILE_SIZE);
+            if walls & WALL_UP == 0 {
+                for step in 0..STAIR_STEPS {
+                    let height = (step + 1) as f32 * step_depth;
+                    d3d.draw_cube_texture(
+                        &texture.tex_wall,
+                        Vector3::new(
+                            x0 + TILE_SIZE / 4.0,
+                            height / 2.0,
+                            z0 + TILE_SIZE - (step as f32 + 0.5) * step_depth,
+                        ),
+                        TILE_SIZE / 2.0,
+                        height,
+                        step_depth,
+                        Color::WHITE,
                     );
-                } else {
-                    if v & WALL_EAST == WALL_EAST {
-                        d3d.draw_cube_texture(
-                            &texture.tex_wall,
-                            Vector3::new(x + TILE_SIZE / 2.0, wall_height / 2.0, z),
-                            0.001,
-                            wall_height,
-                            TILE_SIZE,
-                            Color::WHITE,
-                        );
-                    }
-                    if v & WALL_NORTH == WALL_NORTH {
-                        d3d.draw_cube_texture(
-                            &texture.tex_wall,
-                            Vector3::new(x, wall_height / 2.0, z - TILE_SIZE / 2.0),
-                            TILE_SIZE,
-                            wall_height,
-                            0.001,
-                            Color::WHITE,
-                        );
-                    }
-                    if v & WALL_WEST == WALL_WEST {
-                        d3d.draw_cube_texture(
-                            &texture.tex_wall,
-                            Vector3::new(x - TILE_SIZE / 2.0, wall_height / 2.0, z),
-                            0.001,
-                            wall_height,
-                            TILE_SIZE,
-                            Color::WHITE,
-                        );
-                    }
-                    if v & WALL_SOUTH == WALL_SOUTH {
-                        d3d.draw_cube_texture(
-                            &texture.tex_wall,
-                            Vector3::new(x, wall_height / 2.0, z + TILE_SIZE / 2.0),
-                            TILE_SIZE,
-                            wall_height,
-                            0.001,
-                            Color::WHITE,
-                        );
-                    }
                 }
             }
+            if walls & WALL_DOWN == 0 {
+                d3d.draw_cube(
+                    Vector3::new(x0 + TILE_SIZE * 3.0 / 4.0, 0.0, z0 + TILE_SIZE / 2.0),
+                    TILE_SIZE / 2.0,
+                    0.02,
+                    TILE_SIZE,
+                    Color::BLACK,
+                );
+            }
         }
     }
 }
@@ -1,138 +1,442 @@
 use raylib::prelude::*;
 use std::cmp::Ordering;
 
-pub enum Entity {
-    Player { id: usize, position: Vector3 },
-    Start { id: usize, position: Vector3 },
-    End { id: usize, position: Vector3 },
-    Rat { id: usize, position: Vector3 },
-    OpenGL { id: usize, position: Vector3 },
-    Dodecahedron { id: usize, position: Vector3 },
+/// Names of the archetypes that the game spawns on its own, see `Archetypes`
+pub const PLAYER: &str = "player";
+pub const START: &str = "start";
+pub const END: &str = "end";
+pub const RAT: &str = "rat";
+pub const OPENGL: &str = "opengl";
+pub const DODECAHEDRON: &str = "dodecahedron";
+pub const MINOTAUR: &str = "minotaur";
+pub const TELEPORTER: &str = "teleporter";
+pub const SMILEY: &str = "smiley";
+pub const CHECKPOINT: &str = "checkpoint";
+
+/// Billboard that always faces the camera
+#[derive(Clone, Debug)]
+pub struct Sprite {
+    /// Path of the image, loaded by `AssetPack`
+    pub texture: String,
+    /// Width of the billboard in world units
+    pub size: f32,
+    pub tint: Color,
 }
 
-impl Entity {
-    pub fn id(&self) -> usize {
-        match self {
-            Self::Player { id, .. }
-            | Self::Start { id, .. }
-            | Self::End { id, .. }
-            | Self::Rat { id, .. }
-            | Self::OpenGL { id, .. }
-            | Self::Dodecahedron { id, .. } => *id,
+/// 3D model drawn at the position of the entity
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    /// Path of the model, loaded by `AssetPack`
+    pub model: String,
+    /// Rotation around the model's diagonal, in degrees per second
+    pub spin: f32,
+}
+
+/// Circle on the floor that other entities bump into
+#[derive(Clone, Copy, Debug)]
+pub struct Collider {
+    pub radius: f32,
+}
+
+/// Short animation played by an entity, see `Effect::Animate`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimationKind {
+    /// Hops up and down
+    Bounce,
+    /// Turns around once
+    Spin,
+    /// Shrinks until it can't be seen
+    Shrink,
+}
+
+impl AnimationKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bounce" => Some(Self::Bounce),
+            "spin" => Some(Self::Spin),
+            "shrink" => Some(Self::Shrink),
+            _ => None,
         }
     }
+}
 
-    pub fn position(&self) -> Vector3 {
-        match self {
-            Self::Player {position, .. }
-            | Self::Start { position, .. }
-            | Self::End { position, .. }
-            | Self::Rat { position, .. }
-            | Self::OpenGL { position, .. }
-            | Self::Dodecahedron { position, .. } => *position,
+/// Animation being played by an entity
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Animation {
+    pub kind: AnimationKind,
+    /// Time (game clock) at which the animation started
+    pub start_time: f64,
+}
+
+/// Seconds that an animation lasts
+pub const ANIMATION_DURATION: f64 = 1.0;
+
+impl Animation {
+    /// How far along the animation is at the given time, from 0 to 1
+    pub fn progress(&self, clock: f64) -> f32 {
+        ((clock - self.start_time) / ANIMATION_DURATION).clamp(0.0, 1.0) as f32
+    }
+}
+
+/// Something that happens when the player touches an entity, see `Trigger`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Effect {
+    /// Ends the level
+    EndLevel,
+    /// Catches the player, which ends the run
+    GameOver,
+    /// Rolls the camera around
+    RollCamera,
+    /// Moves the player onto another entity of the named archetype
+    Teleport(String),
+    /// Moves the player onto the partner of the entity, which must be a `Teleporter`
+    Warp,
+    /// Adds an item of the given name to the inventory
+    GiveItem(String),
+    /// Adds points to the score, or takes them away
+    Score(i64),
+    /// Reaches the entity as a checkpoint, when it is the next one in order, see `Entity::checkpoint`
+    Checkpoint,
+    /// Despawns the entity after the given number of seconds
+    Despawn(f64),
+    Animate(AnimationKind),
+}
+
+impl Effect {
+    /// Reads an effect written as its name, followed by `:` and its argument for effects that take one:
+    /// `end-level`, `game-over`, `roll-camera`, `teleport:<archetype>`, `warp`, `give-item:<item>`, `score:<points>`,
+    /// `checkpoint`, `despawn` or `despawn:<seconds>`, and `animate:<bounce|spin|shrink>`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let (name, argument) = match name.split_once(':') {
+            Some((name, argument)) => (name, Some(argument).filter(|a| !a.is_empty())),
+            None => (name, None),
+        };
+        match (name, argument) {
+            ("end-level", None) => Some(Self::EndLevel),
+            ("game-over", None) => Some(Self::GameOver),
+            ("roll-camera", None) => Some(Self::RollCamera),
+            ("teleport", Some(target)) => Some(Self::Teleport(target.to_lowercase())),
+            ("warp", None) => Some(Self::Warp),
+            ("give-item", Some(item)) => Some(Self::GiveItem(item.to_string())),
+            ("score", Some(points)) => points.parse().ok().map(Self::Score),
+            ("checkpoint", None) => Some(Self::Checkpoint),
+            ("despawn", None) => Some(Self::Despawn(0.0)),
+            ("despawn", Some(seconds)) => seconds.parse().ok().filter(|&s: &f64| s >= 0.0).map(Self::Despawn),
+            ("animate", Some(kind)) => AnimationKind::from_name(kind).map(Self::Animate),
+            _ => None,
         }
     }
+}
 
-    pub fn set_position(&mut self, new_position: Vector3) {
+/// Pad of a teleporter, which sends the player to its partner (see `Effect::Warp`): the other teleporter with the same
+/// pair number
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Teleporter {
+    /// Number shared with the partner, given when the teleporters are spawned
+    pub pair: Option<usize>,
+    /// Seconds after a warp during which both pads of the pair do nothing
+    pub cooldown: f64,
+    /// Time (game clock) at which the pad works again
+    pub ready_at: f64,
+}
+
+impl Teleporter {
+    pub fn new(cooldown: f64) -> Self {
+        Self {
+            pair: None,
+            cooldown,
+            ready_at: 0.0,
+        }
+    }
+}
+
+/// Effects of an entity with a collider when the player starts touching it, on every frame while it does, and when it
+/// stops touching it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trigger {
+    pub on_enter: Vec<Effect>,
+    pub on_stay: Vec<Effect>,
+    pub on_exit: Vec<Effect>,
+    /// Whether the player touched the entity on the last frame
+    pub touching: bool,
+}
+
+/// Creature that roams the corridors, see `ai::wander`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wander {
+    /// World units per second
+    pub speed: f32,
+    /// Distance from the player under which the creature runs away from it. Zero never runs away
+    pub flee_radius: f32,
+    /// Tile whose center the creature left last
+    pub from: Option<[usize; 2]>,
+    /// Tile whose center the creature is walking to
+    pub to: Option<[usize; 2]>,
+}
+
+impl Wander {
+    pub fn new(speed: f32, flee_radius: f32) -> Self {
+        Self {
+            speed,
+            flee_radius,
+            from: None,
+            to: None,
+        }
+    }
+
+    /// Drops the route, for when the tiles of the map change place. The creature walks back to the center of the tile
+    /// it is in before picking a new one
+    pub fn forget_route(&mut self) {
+        self.from = None;
+        self.to = None;
+    }
+}
+
+/// Creature that hunts the player, see `ai::chase`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chase {
+    /// World units per second while chasing. Patrols go at half this speed
+    pub speed: f32,
+    /// Distance under which the creature hears the player through walls
+    pub hearing_radius: f32,
+    /// Route of the patrol, also followed while chasing
+    pub patrol: Wander,
+    /// Tile where the player was last seen or heard. The creature goes there, then patrols again
+    pub last_seen: Option<[usize; 2]>,
+    /// Steps from every tile to `last_seen`, indexed like `MazeGraph::index`. Empty when it is None
+    pub distances: Vec<Option<usize>>,
+}
+
+impl Chase {
+    pub fn new(speed: f32, hearing_radius: f32) -> Self {
+        Self {
+            speed,
+            hearing_radius,
+            patrol: Wander::new(speed / 2.0, 0.0),
+            last_seen: None,
+            distances: Vec::new(),
+        }
+    }
+}
+
+/// Behaviour of a creature
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ai {
+    /// Stays where it was spawned
+    Idle,
+    Wander(Wander),
+    Chase(Chase),
+}
+
+impl Ai {
+    /// Behaviour with its default settings
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "idle" => Some(Self::Idle),
+            "wander" => Some(Self::Wander(Wander::new(3.0, 0.0))),
+            "chase" => Some(Self::Chase(Chase::new(8.0, 5.0))),
+            _ => None,
+        }
+    }
+
+    /// Drops the routes and the tiles remembered by the creature, see `Wander::forget_route`
+    pub fn forget_route(&mut self) {
         match self {
-            Self::Player {position, .. }
-            | Self::Start { position, .. }
-            | Self::End { position, .. }
-            | Self::Rat { position, .. }
-            | Self::OpenGL { position, .. }
-            | Self::Dodecahedron { position, .. } => *position = new_position,
+            Self::Idle => {}
+            Self::Wander(wander) => wander.forget_route(),
+            Self::Chase(chase) => {
+                chase.patrol.forget_route();
+                chase.last_seen = None;
+                chase.distances.clear();
+            }
+        }
+    }
+}
+
+/// Time (game clock) at which the entity is despawned
+#[derive(Clone, Copy, Debug)]
+pub struct Lifetime {
+    pub until: f64,
+}
+
+/// Thing in the maze, made of the components it needs. Systems go through the entities that have the components they
+/// work on: entities with a sprite or a mesh are drawn, the ones with a collider and a trigger react to the player, and
+/// the ones with a lifetime are despawned when it runs out.
+///
+/// Components are optional fields rather than maps of components by `EntityId`. A level holds a few dozen entities,
+/// so systems can afford to skip the ones without their components, and an entity stays a single value: archetypes
+/// clone it whole, and towers park it on its floor and bring it back without a component left behind. A new kind of
+/// component is a new field, set to `None` in `Entity::new`
+#[derive(Clone, Debug)]
+pub struct Entity {
+    /// Archetype of the entity, such as `RAT`. Only used to tell entities apart, behaviour comes from the components
+    pub name: String,
+    pub position: Vector3,
+    pub sprite: Option<Sprite>,
+    pub mesh: Option<Mesh>,
+    pub collider: Option<Collider>,
+    pub trigger: Option<Trigger>,
+    pub ai: Option<Ai>,
+    pub lifetime: Option<Lifetime>,
+    pub animation: Option<Animation>,
+    pub teleporter: Option<Teleporter>,
+    /// Place of the entity in the order the checkpoints of the level must be reached, from 0
+    pub checkpoint: Option<usize>,
+}
+
+impl Entity {
+    /// Entity with a position and no components
+    pub fn new(name: &str, position: Vector3) -> Self {
+        Self {
+            name: name.to_string(),
+            position,
+            sprite: None,
+            mesh: None,
+            collider: None,
+            trigger: None,
+            ai: None,
+            lifetime: None,
+            animation: None,
+            teleporter: None,
+            checkpoint: None,
         }
     }
 
+    pub fn is(&self, name: &str) -> bool {
+        self.name == name
+    }
+
+    pub fn position(&self) -> Vector3 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, new_position: Vector3) {
+        self.position = new_position;
+    }
+
     pub fn move_position(&mut self, displacement: Vector3) {
         self.set_position(self.position() + displacement);
     }
 
     pub fn collision_radius(&self) -> f32 {
-        match self {
-            Self::Player { .. } => 0.5,
-            Self::End { .. } => 1.2,
-            Self::Dodecahedron { .. } => 1.2,
-            _ => 0.0,
-        }
+        self.collider.map_or(0.0, |collider| collider.radius)
     }
 }
 
-pub struct EntityManager {
-    pub entities: Vec<Entity>,
-    id_sequence: usize,
-    draw_order: Vec<usize>, // indexes entities in the order they should be drawn to screen
+/// Handle to an entity of an `EntityManager`. The slot of a removed entity is reused by the next one added, with a
+/// new generation, so handles to the removed entity don't reach the new one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EntityId {
+    index: u32,
+    generation: u32,
 }
 
-fn update_index_after_removal(vec: &mut Vec<usize>, removed_index: usize) {
-    let mut i = 0;
-    while i < vec.len() {
-        match vec[i].cmp(&removed_index) {
-            Ordering::Equal => {
-                vec.remove(i);
-            }
-            Ordering::Greater => {
-                vec[i] -= 1;
-                i += 1;
-            }
-            _ => {
-                i += 1;
-            }
-        };
-    }
+struct Slot {
+    generation: u32,
+    entity: Option<Entity>,
+}
+
+fn find(slots: &[Slot], id: EntityId) -> Option<&Entity> {
+    let slot = slots
+        .get(id.index as usize)
+        .filter(|slot| slot.generation == id.generation)?;
+    slot.entity.as_ref()
+}
+
+/// Slot map of entities. Lookup and removal by handle take constant time and leave the other handles valid
+pub struct EntityManager {
+    slots: Vec<Slot>,
+    /// Indices of the empty slots
+    free: Vec<u32>,
+    len: usize,
+    /// Entities in the order they should be drawn to screen. Handles of removed entities stay here until the next
+    /// sort, so that removing doesn't have to search the list
+    draw_order: Vec<EntityId>,
 }
 
 impl EntityManager {
     pub fn new() -> Self {
         EntityManager {
-            entities: Vec::new(),
-            id_sequence: 0,
+            slots: Vec::new(),
+            free: Vec::new(),
+            len: 0,
             draw_order: Vec::new(),
         }
     }
 
-    pub fn generate_id(&mut self) -> usize {
-        let result = self.id_sequence;
-        self.id_sequence += 1;
-        result
+    pub fn add(&mut self, entity: Entity) -> EntityId {
+        let id = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.entity = Some(entity);
+                EntityId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    entity: Some(entity),
+                });
+                EntityId {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        };
+        self.len += 1;
+        self.draw_order.push(id);
+        id
     }
 
-    pub fn get_mut_by_index(&mut self, index: usize) -> &mut Entity {
-        &mut self.entities[index]
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.get_by_id(id).is_some()
     }
 
-    pub fn remove_by_index(&mut self, index: usize) {
-        self.entities.remove(index);
-        update_index_after_removal(&mut self.draw_order, index);
+    pub fn get_by_id(&self, id: EntityId) -> Option<&Entity> {
+        find(&self.slots, id)
     }
 
-    pub fn get_by_id(&self, id: usize) -> Option<&Entity> {
-        if let Ok(i) = self.entities.binary_search_by_key(&id, |a| a.id()) {
-            return Some(&self.entities[i]);
-        }
-        return None;
+    pub fn get_mut_by_id(&mut self, id: EntityId) -> Option<&mut Entity> {
+        let slot = self
+            .slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)?;
+        slot.entity.as_mut()
     }
 
-    pub fn get_mut_by_id(&mut self, id: usize) -> Option<&mut Entity> {
-        if let Ok(i) = self.entities.binary_search_by_key(&id, |a| a.id()) {
-            return Some(&mut self.entities[i]);
-        }
-        return None;
+    pub fn remove_by_id(&mut self, id: EntityId) -> bool {
+        self.take_by_id(id).is_some()
     }
 
-    pub fn remove_by_id(&mut self, id: usize) -> bool {
-        if let Ok(i) = self.entities.binary_search_by_key(&id, |a| a.id()) {
-            self.entities.remove(i);
-            update_index_after_removal(&mut self.draw_order, i);
-            return true;
-        }
-        return false;
+    /// Removes the entity and gives it back, so it can be added again later (with a new handle)
+    pub fn take_by_id(&mut self, id: EntityId) -> Option<Entity> {
+        let slot = self
+            .slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)?;
+        let entity = slot.entity.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index);
+        self.len -= 1;
+        Some(entity)
     }
 
-    pub fn add(&mut self, entity: Entity) {
-        self.draw_order.push(self.entities.len());
-        if let Err(i) = self.entities.binary_search_by_key(&entity.id(), |e| e.id()) {
-            self.entities.insert(i, entity);
+    /// Handles of all the entities. Entities can be removed while going through them, since the handles of removed
+    /// entities just stop finding anything
+    pub fn ids(&self) -> Vec<EntityId> {
+        self.entries().map(|(id, _)| id).collect()
+    }
+
+    /// Removes the entities for which `keep` returns false
+    pub fn retain(&mut self, mut keep: impl FnMut(EntityId, &mut Entity) -> bool) {
+        for id in self.ids() {
+            if let Some(entity) = self.get_mut_by_id(id) {
+                if !keep(id, entity) {
+                    self.take_by_id(id);
+                }
+            }
         }
     }
 
@@ -140,19 +444,128 @@ impl EntityManager {
     where
         F: FnMut(&Entity, &Entity) -> Ordering,
     {
+        let slots = &self.slots;
+        self.draw_order.retain(|&id| find(slots, id).is_some());
         self.draw_order
-            .sort_by(|&i, &j| compare(&self.entities[i], &self.entities[j]));
+            .sort_by(|&i, &j| match (find(slots, i), find(slots, j)) {
+                (Some(a), Some(b)) => compare(a, b),
+                _ => Ordering::Equal,
+            });
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &Entity> {
-        self.entities.iter()
+        self.entries().map(|(_, entity)| entity)
+    }
+
+    /// Iterates over the entities along with their handles
+    pub fn entries(&self) -> impl Iterator<Item = (EntityId, &Entity)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let id = EntityId {
+                index: index as u32,
+                generation: slot.generation,
+            };
+            slot.entity.as_ref().map(|entity| (id, entity))
+        })
+    }
+
+    pub fn entries_mut(&mut self) -> impl Iterator<Item = (EntityId, &mut Entity)> {
+        self.slots.iter_mut().enumerate().filter_map(|(index, slot)| {
+            let id = EntityId {
+                index: index as u32,
+                generation: slot.generation,
+            };
+            slot.entity.as_mut().map(|entity| (id, entity))
+        })
     }
 
     pub fn draw_iter(&self) -> impl Iterator<Item = &Entity> {
-        self.draw_order.iter().map(|&i| &self.entities[i]).into_iter()
+        self.draw_order.iter().filter_map(|&id| self.get_by_id(id))
     }
 
     pub fn len(&self) -> usize {
-        self.entities.len()
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashMap;
+
+    /// Entity told apart from the others by its x coordinate
+    fn marked(mark: usize) -> Entity {
+        Entity::new(RAT, Vector3::new(mark as f32, 0.0, 0.0))
+    }
+
+    fn mark(entity: &Entity) -> usize {
+        entity.position().x as usize
+    }
+
+    #[test]
+    fn lookups_survive_random_adds_and_removes() {
+        for seed in 0..50 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut entities = EntityManager::new();
+            let mut expected: HashMap<EntityId, usize> = HashMap::new();
+            let mut removed: Vec<EntityId> = Vec::new();
+            for step in 0..500 {
+                if expected.is_empty() || rng.gen_bool(0.6) {
+                    let id = entities.add(marked(step));
+                    assert!(expected.insert(id, step).is_none(), "handle {id:?} given twice");
+                } else {
+                    let ids: Vec<EntityId> = expected.keys().copied().collect();
+                    let id = ids[rng.gen_range(0..ids.len())];
+                    assert_eq!(entities.take_by_id(id).as_ref().map(mark), expected.remove(&id));
+                    removed.push(id);
+                }
+                if step % 25 == 0 {
+                    entities.sort_drawables_by(|a, b| mark(b).cmp(&mark(a)));
+                }
+
+                assert_eq!(entities.len(), expected.len());
+                for (&id, &m) in expected.iter() {
+                    assert_eq!(entities.get_by_id(id).map(mark), Some(m));
+                }
+                // handles of removed entities never reach the entities that reuse their slots
+                for &id in removed.iter() {
+                    assert!(!entities.contains(id));
+                    assert!(!entities.remove_by_id(id));
+                }
+                let mut iterated: Vec<(EntityId, usize)> = entities.entries().map(|(id, e)| (id, mark(e))).collect();
+                let mut wanted: Vec<(EntityId, usize)> = expected.iter().map(|(&id, &m)| (id, m)).collect();
+                iterated.sort();
+                wanted.sort();
+                assert_eq!(iterated, wanted);
+                let mut drawn: Vec<usize> = entities.draw_iter().map(mark).collect();
+                drawn.sort();
+                let mut marks: Vec<usize> = expected.values().copied().collect();
+                marks.sort();
+                assert_eq!(drawn, marks);
+            }
+        }
+    }
+
+    #[test]
+    fn draw_order_follows_the_last_sort() {
+        let mut entities = EntityManager::new();
+        let ids: Vec<EntityId> = (0..10).map(|m| entities.add(marked(m))).collect();
+        entities.remove_by_id(ids[3]);
+        entities.add(marked(10));
+        entities.sort_drawables_by(|a, b| mark(b).cmp(&mark(a)));
+        let drawn: Vec<usize> = entities.draw_iter().map(mark).collect();
+        assert_eq!(drawn, vec![10, 9, 8, 7, 6, 5, 4, 2, 1, 0]);
+    }
+
+    #[test]
+    fn retain_removes_while_iterating() {
+        let mut entities = EntityManager::new();
+        for m in 0..20 {
+            entities.add(marked(m));
+        }
+        entities.retain(|_, entity| !mark(entity).is_multiple_of(3));
+        assert_eq!(entities.len(), 13);
+        assert!(entities.iter().all(|entity| !mark(entity).is_multiple_of(3)));
     }
 }
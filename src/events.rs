@@ -1,3 +1,5 @@
+use crate::entities::EntityId;
+
 #[derive(PartialEq, PartialOrd)]
 pub enum GameEventType {
     GameStart {
@@ -8,19 +10,37 @@ pub enum GameEventType {
         start_time: f64,
         duration: f64,
     },
+    /// The player was caught, see `Effect::GameOver`. Like `GameEnd`, but the screen turns red
+    GameOver {
+        start_time: f64,
+        duration: f64,
+    },
     Roll {
         start_time: f64,
         duration: f64,
-        entity_id: usize,
+        entity_id: EntityId,
+    },
+    /// Flash of the player going through a teleporter, see `Effect::Warp`
+    Warp {
+        start_time: f64,
+        duration: f64,
+    },
+    /// Walls of a shifting maze rising and falling into their new places
+    Shift {
+        start_time: f64,
+        duration: f64,
     },
 }
 
 impl GameEventType {
     pub fn start_time(&self) -> f64 {
         match self {
-            Self::GameStart { start_time, .. } | Self::GameEnd { start_time, .. } | Self::Roll { start_time, .. } => {
-                *start_time
-            }
+            Self::GameStart { start_time, .. }
+            | Self::GameEnd { start_time, .. }
+            | Self::GameOver { start_time, .. }
+            | Self::Roll { start_time, .. }
+            | Self::Warp { start_time, .. }
+            | Self::Shift { start_time, .. } => *start_time,
         }
     }
 
@@ -32,8 +52,17 @@ impl GameEventType {
             | Self::GameEnd {
                 start_time, duration, ..
             }
+            | Self::GameOver {
+                start_time, duration, ..
+            }
             | Self::Roll {
                 start_time, duration, ..
+            }
+            | Self::Warp {
+                start_time, duration, ..
+            }
+            | Self::Shift {
+                start_time, duration, ..
             } => start_time + duration,
         }
     }
@@ -0,0 +1,398 @@
+use raylib::ffi;
+use raylib::prelude::*;
+use std::ffi::CString;
+use std::fmt::Write;
+use std::{fs, io};
+
+use crate::constants::TILE_SIZE;
+use crate::doors::KeyColor;
+use crate::entities::{Entity, CHECKPOINT, DODECAHEDRON, END, MINOTAUR, OPENGL, PLAYER, RAT, SMILEY, TELEPORTER};
+use crate::game::GameState;
+use crate::grid::{Grid, Wall};
+use crate::map::{Map, RectangularMap, WallBits};
+use crate::solver::shortest_path_with_links;
+use crate::topology::Topology;
+
+/// Settings for the top-down exports of the maze
+pub struct ExportOptions {
+    /// Size in pixels of a single tile
+    pub tile_size: i32,
+    pub wall_thickness: i32,
+    /// Blank space around the maze, in pixels
+    pub margin: i32,
+    pub show_solution: bool,
+    pub show_entities: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            tile_size: 32,
+            wall_thickness: 4,
+            margin: 16,
+            show_solution: false,
+            show_entities: false,
+        }
+    }
+}
+
+const COLOR_SOLID: Color = Color::new(160, 160, 160, 255);
+const COLOR_SOLUTION: Color = Color::new(230, 41, 55, 255);
+
+/// Color used to mark an entity in the overhead map. Entities without a marker are not drawn
+fn marker_color(entity: &Entity) -> Option<Color> {
+    match entity.name.as_str() {
+        PLAYER => Some(Color::new(0, 158, 47, 255)),
+        END => Some(Color::new(255, 161, 0, 255)),
+        RAT => Some(Color::new(127, 106, 79, 255)),
+        OPENGL => Some(Color::new(0, 121, 241, 255)),
+        DODECAHEDRON => Some(Color::new(200, 122, 255, 255)),
+        MINOTAUR => Some(Color::new(230, 41, 55, 255)),
+        TELEPORTER => Some(Color::new(0, 228, 255, 255)),
+        SMILEY => Some(Color::new(255, 203, 0, 255)),
+        CHECKPOINT => Some(Color::new(255, 109, 194, 255)),
+        name => KeyColor::ALL
+            .into_iter()
+            .find(|color| color.key() == name)
+            .map(KeyColor::color),
+    }
+}
+
+fn marker_radius(entity: &Entity, options: &ExportOptions) -> i32 {
+    if entity.is(PLAYER) || entity.is(END) || entity.is(MINOTAUR) {
+        options.tile_size / 3
+    } else {
+        options.tile_size / 6
+    }
+}
+
+/// Converts a world position into pixel coordinates of the exported map
+fn world_to_pixel(position: Vector3, options: &ExportOptions) -> Vector2 {
+    Vector2::new(
+        options.margin as f32 + position.x / TILE_SIZE * options.tile_size as f32,
+        options.margin as f32 + position.z / TILE_SIZE * options.tile_size as f32,
+    )
+}
+
+/// Converts a position measured in tiles into pixel coordinates of the exported map
+fn to_pixel(point: Vector2, options: &ExportOptions) -> [i32; 2] {
+    [
+        options.margin + (point.x * options.tile_size as f32).round() as i32,
+        options.margin + (point.y * options.tile_size as f32).round() as i32,
+    ]
+}
+
+fn tile_center(topology: Topology, tile: [usize; 2], options: &ExportOptions) -> [i32; 2] {
+    to_pixel(topology.center(tile), options)
+}
+
+fn tile_of<T: WallBits>(map: &Map<2, T>, position: Vector3) -> [usize; 2] {
+    let tile = map.topology().tile_at(Vector2::new(position.x, position.z) / TILE_SIZE);
+    map.tile(tile).unwrap_or(tile.map(|v| v.max(0) as usize))
+}
+
+fn image_size<T: WallBits>(map: &Map<2, T>, options: &ExportOptions) -> [i32; 2] {
+    let extent = map.topology().extent(map.dimensions());
+    [
+        (extent.x * options.tile_size as f32).ceil() as i32 + 2 * options.margin,
+        (extent.y * options.tile_size as f32).ceil() as i32 + 2 * options.margin,
+    ]
+}
+
+/// Returns every wall of the map as a line segment [x0, y0, x1, y1] in pixel coordinates.
+/// Walls shared by two tiles are only returned once. The railings of bridges are walls too, so that seen from above
+/// the bridge covers the tunnel under it
+fn wall_segments<T: WallBits>(map: &Map<2, T>, options: &ExportOptions) -> Vec<[i32; 4]> {
+    let railings = map
+        .cells()
+        .flat_map(|tile| map.crossing_walls(tile, true).map(move |side| Wall { tile, side }));
+    map.walls()
+        .chain(railings)
+        .map(|wall| {
+            let mut ends = wall.segment(map.topology()).map(|p| to_pixel(p, options));
+            ends.sort();
+            let [[x0, y0], [x1, y1]] = ends;
+            [x0, y0, x1, y1]
+        })
+        .collect()
+}
+
+/// Returns the walls on the edges of a map that wraps around once more, on the other side of the map, so that both
+/// edges show them
+fn seam_wall_segments<T: WallBits>(map: &Map<2, T>, options: &ExportOptions) -> Vec<[i32; 4]> {
+    let topology = map.topology();
+    map.walls()
+        .filter_map(|wall| {
+            let neighbour = map.neighbour(wall.tile, wall.side)?;
+            let offset = map.seam_offset(topology.center(neighbour), wall.tile);
+            if offset == Vector2::zero() {
+                return None;
+            }
+            let mut ends = wall.segment(topology).map(|p| to_pixel(p + offset, options));
+            ends.sort();
+            let [[x0, y0], [x1, y1]] = ends;
+            Some([x0, y0, x1, y1])
+        })
+        .collect()
+}
+
+/// Returns the closed doors of the game as line segments in pixel coordinates, with their colors. They are drawn over
+/// the walls they close
+fn door_segments(game: &GameState, options: &ExportOptions) -> Vec<([i32; 4], Color)> {
+    game.map
+        .doors()
+        .filter(|(_, door)| door.opened_at.is_none())
+        .map(|(wall, door)| {
+            let [[x0, y0], [x1, y1]] = wall.segment(game.map.topology()).map(|p| to_pixel(p, options));
+            ([x0, y0, x1, y1], door.color.color())
+        })
+        .collect()
+}
+
+/// Splits a path into lines in pixel coordinates. On maps that wrap around, a step across the edge of the map ends
+/// the line past the edge and starts a new one from the other side. A step through a teleporter starts a new line
+fn path_strokes<T: WallBits>(map: &Map<2, T>, path: &[[usize; 2]], options: &ExportOptions) -> Vec<Vec<[i32; 2]>> {
+    let topology = map.topology();
+    let mut strokes = vec![Vec::new()];
+    for (i, &tile) in path.iter().enumerate() {
+        if let Some(&previous) = i.checked_sub(1).and_then(|i| path.get(i)) {
+            let offset = map.seam_offset(topology.center(previous), tile);
+            if !map.open_neighbours(previous).any(|t| t == tile) {
+                strokes.push(Vec::new());
+            } else if offset != Vector2::zero() {
+                strokes
+                    .last_mut()
+                    .unwrap()
+                    .push(to_pixel(topology.center(tile) + offset, options));
+                strokes.push(vec![to_pixel(topology.center(previous) - offset, options)]);
+            }
+        }
+        strokes.last_mut().unwrap().push(tile_center(topology, tile, options));
+    }
+    strokes
+}
+
+/// Tiles of the shortest path from the player's tile to the end banner, through the teleporters and the doors
+fn solution_path(game: &GameState) -> Option<Vec<[usize; 2]>> {
+    let end = game.entities.iter().find(|e| e.is(END))?;
+    let (start, end) = (
+        tile_of(&game.map, game.player().position()),
+        tile_of(&game.map, end.position()),
+    );
+    shortest_path_with_links(&game.map.opened(), start, end, &game.teleporter_links())
+}
+
+fn svg_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+/// Renders the maze seen from above as an SVG document
+pub fn to_svg(game: &GameState, options: &ExportOptions) -> String {
+    let map = &game.map;
+    let [width, height] = image_size(map, options);
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+    )
+    .unwrap();
+    writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#).unwrap();
+
+    for tile in map.cells().filter(|&tile| map.is_solid(tile)) {
+        let points: Vec<String> = map
+            .topology()
+            .corners(tile)
+            .into_iter()
+            .map(|corner| {
+                let [x, y] = to_pixel(corner, options);
+                format!("{x},{y}")
+            })
+            .collect();
+        writeln!(
+            svg,
+            r#"<polygon points="{}" fill="{}"/>"#,
+            points.join(" "),
+            svg_color(COLOR_SOLID),
+        )
+        .unwrap();
+    }
+
+    if options.show_solution {
+        if let Some(path) = solution_path(game) {
+            for stroke in path_strokes(&map.opened(), &path, options) {
+                let points: Vec<String> = stroke.iter().map(|[x, y]| format!("{x},{y}")).collect();
+                writeln!(
+                    svg,
+                    r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="{}" stroke-linejoin="round"/>"#,
+                    points.join(" "),
+                    svg_color(COLOR_SOLUTION),
+                    options.wall_thickness.max(1),
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    writeln!(
+        svg,
+        r#"<g stroke="black" stroke-width="{}" stroke-linecap="square">"#,
+        options.wall_thickness
+    )
+    .unwrap();
+    for [x0, y0, x1, y1] in wall_segments(map, options)
+        .into_iter()
+        .chain(seam_wall_segments(map, options))
+    {
+        writeln!(svg, r#"<line x1="{x0}" y1="{y0}" x2="{x1}" y2="{y1}"/>"#).unwrap();
+    }
+    for ([x0, y0, x1, y1], color) in door_segments(game, options) {
+        let color = svg_color(color);
+        writeln!(
+            svg,
+            r#"<line x1="{x0}" y1="{y0}" x2="{x1}" y2="{y1}" stroke="{color}"/>"#
+        )
+        .unwrap();
+    }
+    writeln!(svg, "</g>").unwrap();
+
+    if options.show_entities {
+        for entity in game.entities.iter() {
+            if let Some(color) = marker_color(entity) {
+                let center = world_to_pixel(entity.position(), options);
+                writeln!(
+                    svg,
+                    r#"<circle cx="{:.1}" cy="{:.1}" r="{}" fill="{}"/>"#,
+                    center.x,
+                    center.y,
+                    marker_radius(entity, options),
+                    svg_color(color),
+                )
+                .unwrap();
+            }
+        }
+    }
+    writeln!(svg, "</svg>").unwrap();
+    svg
+}
+
+/// Draws a line with square caps. Lines that aren't horizontal or vertical are drawn as a square stamped along them,
+/// since images can only draw lines one pixel wide
+fn draw_thick_line(image: &mut Image, [x0, y0, x1, y1]: [i32; 4], thickness: i32, color: Color) {
+    if x0 == x1 || y0 == y1 {
+        image.draw_rectangle(
+            x0.min(x1) - thickness / 2,
+            y0.min(y1) - thickness / 2,
+            (x1 - x0).abs() + thickness,
+            (y1 - y0).abs() + thickness,
+            color,
+        );
+        return;
+    }
+    let steps = (x1 - x0).abs().max((y1 - y0).abs());
+    for step in 0..=steps {
+        let x = x0 + (x1 - x0) * step / steps;
+        let y = y0 + (y1 - y0) * step / steps;
+        image.draw_rectangle(x - thickness / 2, y - thickness / 2, thickness, thickness, color);
+    }
+}
+
+/// Renders the maze seen from above into a CPU-side image. Does not require a window or an OpenGL context
+pub fn to_image(game: &GameState, options: &ExportOptions) -> Image {
+    let map = &game.map;
+    let [width, height] = image_size(map, options);
+    let mut image = Image::gen_image_color(width, height, Color::WHITE);
+
+    // hexagons are drawn as the rectangle they cover between their neighbours
+    let row_height = (map.topology().row_height() * options.tile_size as f32).round() as i32;
+    for tile in map.cells().filter(|&tile| map.is_solid(tile)) {
+        let [x, y] = tile_center(map.topology(), tile, options);
+        image.draw_rectangle(
+            x - options.tile_size / 2,
+            y - row_height / 2,
+            options.tile_size,
+            row_height,
+            COLOR_SOLID,
+        );
+    }
+
+    if options.show_solution {
+        if let Some(path) = solution_path(game) {
+            let thickness = options.wall_thickness.max(1);
+            for stroke in path_strokes(&map.opened(), &path, options) {
+                for step in stroke.windows(2) {
+                    let ([x0, y0], [x1, y1]) = (step[0], step[1]);
+                    draw_thick_line(&mut image, [x0, y0, x1, y1], thickness, COLOR_SOLUTION);
+                }
+            }
+        }
+    }
+
+    let thickness = options.wall_thickness;
+    for segment in wall_segments(map, options)
+        .into_iter()
+        .chain(seam_wall_segments(map, options))
+    {
+        draw_thick_line(&mut image, segment, thickness, Color::BLACK);
+    }
+    for (segment, color) in door_segments(game, options) {
+        draw_thick_line(&mut image, segment, thickness, color);
+    }
+
+    if options.show_entities {
+        for entity in game.entities.iter() {
+            if let Some(color) = marker_color(entity) {
+                let center = world_to_pixel(entity.position(), options);
+                // ImageDrawCircle only draws the outline of the circle, so fill it with smaller circles
+                for radius in (0..=marker_radius(entity, options)).rev() {
+                    image.draw_circle(center.x as i32, center.y as i32, radius, color);
+                }
+            }
+        }
+    }
+    image
+}
+
+pub fn export_svg(game: &GameState, path: &str, options: &ExportOptions) -> io::Result<()> {
+    fs::write(path, to_svg(game, options))
+}
+
+/// Writes the image of the maze to a file. The format follows the extension of the path, like `.png`
+pub fn export_png(game: &GameState, path: &str, options: &ExportOptions) -> io::Result<()> {
+    let image = to_image(game, options);
+    let c_path = CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // Image::export_image drops the result of ExportImage, which is false when the file could not be written
+    if unsafe { ffi::ExportImage(*image, c_path.as_ptr()) } {
+        Ok(())
+    } else {
+        Err(io::Error::other("the image could not be exported"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archetypes::parse_archetypes;
+    use crate::map::{GetSetMap, WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST};
+
+    const ALL_WALLS: i8 = WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH;
+
+    #[test]
+    fn shared_walls_are_drawn_once() {
+        let options = ExportOptions::default();
+        let mut map = Map::<2>::from(ALL_WALLS, [2, 3]);
+        // 3 lines of 3 walls one way and 4 lines of 2 walls the other, the border included
+        assert_eq!(wall_segments(&map, &options).len(), 17);
+
+        // opening a passage takes its wall away from both tiles
+        let neighbour = map.neighbour([0, 0], WALL_EAST).unwrap();
+        map.set_walls([0, 0], ALL_WALLS & !WALL_EAST);
+        map.set_walls(neighbour.map(|v| v as i32), ALL_WALLS & !WALL_WEST);
+        let segments = wall_segments(&map, &options);
+        assert_eq!(segments.len(), 16);
+        assert!(segments.iter().all(|&[x0, y0, x1, y1]| (x0 == x1) != (y0 == y1)));
+
+        let archetypes = parse_archetypes(include_str!("../assets/entities.xml")).unwrap();
+        let game = GameState::with_map(0.0, map, None, archetypes);
+        assert_eq!(to_svg(&game, &options).matches("<line ").count(), 16);
+    }
+}
@@ -1,18 +1,31 @@
 use crate::{
-    constants::TILE_SIZE,
-    dfs::random_dfs,
-    entities::{Entity, EntityManager},
+    ai::{chase, wander},
+    archetypes::Archetypes,
+    constants::{BRIDGE_EYE_OFFSET, TILE_SIZE, TUNNEL_EYE_OFFSET},
+    dfs::{random_maze, regenerate_region_with_rng},
+    doors::{DoorCell, KeyColor},
+    entities::{Ai, Animation, Effect, Entity, EntityId, EntityManager, Lifetime, END, MINOTAUR, PLAYER, START},
     events::GameEventType,
-    map::{GetSetMap, Map, RectangularMap, WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST},
+    goal::{Goal, LevelResult},
+    grid::{Grid, Wall},
+    map::{positions, GetSetMap, Map, RectangularMap, WALL_DOWN, WALL_UP},
+    metrics::{analyze, MazeMetrics},
+    solver::{connected_regions, distances_from, shortest_path},
+    topology::Topology,
+    world::World,
 };
+use std::collections::HashMap;
+
 use rand::seq::SliceRandom;
+use rand::Rng;
 use raylib::ffi::atan2f;
 use raylib::prelude::*;
 
 pub struct GameState {
-    pub map: Map<2>,
+    /// The maze the player is in, with its doors, see `DoorCell`
+    pub map: Map<2, DoorCell>,
     pub entities: EntityManager,
-    player_id: usize,
+    player_id: EntityId,
 
     pub clock: f64,
     //pub player_position: Vector3,
@@ -21,89 +34,222 @@ pub struct GameState {
     pub game_start_event: Option<GameEventType>,
     pub game_end_event: Option<GameEventType>,
     pub roll_events: Vec<GameEventType>,
+
+    /// Metrics of the maze. For towers they only describe the first floor
+    pub metrics: MazeMetrics,
+    pub tower: Option<Tower>,
+    /// Layer of the weave crossing the player is in: `Some(true)` on its bridge and `Some(false)` in its tunnel
+    pub crossing_layer: Option<bool>,
+    /// Height of the camera above the player, which rises on bridges and lowers in tunnels
+    pub eye_offset: f32,
+    pub shift: Option<MazeShift>,
+    pub shift_event: Option<GameEventType>,
+    /// Chunks of an endless maze. `map` holds the window of chunks around the player
+    pub world: Option<World>,
+    /// Kinds of entities that can be spawned
+    pub archetypes: Archetypes,
+    /// Items picked up by the player, with how many of each, see `Effect::GiveItem`
+    pub inventory: HashMap<String, usize>,
+    /// First pair number not given to teleporters yet, see `pair_teleporters`
+    next_pair: usize,
+    pub warp_event: Option<GameEventType>,
+    pub score: i64,
+    /// What the player must do before the end opens, see `set_goal`
+    pub goal: Goal,
+    /// Checkpoints in the level, numbered by `Entity::checkpoint`
+    pub checkpoint_count: usize,
+    /// Checkpoints reached in order, which is also the number of the next one
+    pub checkpoints_reached: usize,
+    /// Time when the level started
+    pub started_at: f64,
+}
+
+/// Largest side of the region regenerated by a shift of the maze
+const SHIFT_REGION_SIZE: usize = 5;
+/// Regions tried before giving up on a shift, when they are too close to the player or would cut a path
+const SHIFT_ATTEMPTS: usize = 10;
+const SHIFT_DURATION: f64 = 1.0;
+
+/// Timing of a maze whose walls shift during play
+pub struct MazeShift {
+    /// Seconds between two shifts
+    pub interval: f64,
+    next_time: f64,
+    /// The map as it was before the last shift, while its walls are moving
+    pub previous: Option<Map<2, DoorCell>>,
+}
+
+impl MazeShift {
+    pub fn new(clock: f64, interval: f64) -> Self {
+        Self {
+            interval,
+            next_time: clock + interval,
+            previous: None,
+        }
+    }
+}
+
+/// Maze of stacked floors. The floor the player is on is copied to `GameState::map`, and the entities of the other
+/// floors wait here until the player climbs to them
+pub struct Tower {
+    pub map: Map<3>,
+    pub floor: usize,
+    parked: Vec<Vec<Entity>>,
+    /// Tile where the player arrived from another floor. Its stairs are ignored until the player leaves it
+    arrival: Option<[usize; 2]>,
+}
+
+/// Tiles where entities are placed when a level starts
+#[derive(Clone, Default)]
+pub struct Spawns {
+    pub start: Option<[usize; 2]>,
+    pub end: Option<[usize; 2]>,
+    /// Archetypes of the other entities, with their tiles
+    pub entities: Vec<(String, [usize; 2])>,
+}
+
+fn tile_center(topology: Topology, tile: [usize; 2], y: f32) -> Vector3 {
+    let center = topology.center(tile) * TILE_SIZE;
+    Vector3::new(center.x, y, center.y)
+}
+
+fn map_tile(map: &impl Grid, position: Vector3) -> Option<[usize; 2]> {
+    map.tile(map.topology().tile_at(Vector2::new(position.x, position.z) / TILE_SIZE))
+}
+
+/// Distance from the edge of the player's collider at which a door opens when the player has its key
+const DOOR_REACH: f32 = 0.2;
+
+/// Seconds of the flash of a warp through a teleporter
+const WARP_DURATION: f64 = 0.4;
+
+/// Pairs the teleporters among newly spawned entities in the order they were spawned: the first with the second, the
+/// third with the fourth, and so on. `next_pair` is the first pair number that isn't taken yet. A teleporter left
+/// without a partner leads nowhere
+fn pair_teleporters(entities: &mut [Entity], next_pair: &mut usize) {
+    let mut waiting = false;
+    for teleporter in entities.iter_mut().filter_map(|e| e.teleporter.as_mut()) {
+        teleporter.pair = Some(*next_pair);
+        if waiting {
+            *next_pair += 1;
+        }
+        waiting = !waiting;
+    }
+    if waiting {
+        *next_pair += 1;
+    }
+}
+
+/// Whether the entity is reached as a checkpoint when the player touches it, see `Effect::Checkpoint`
+fn is_checkpoint(entity: &Entity) -> bool {
+    entity
+        .trigger
+        .as_ref()
+        .is_some_and(|t| t.on_enter.contains(&Effect::Checkpoint))
+}
+
+/// Tiles of the two pads of each pair of teleporters in the map
+fn teleporter_links<'a>(map: &impl Grid, entities: impl Iterator<Item = &'a Entity>) -> Vec<[[usize; 2]; 2]> {
+    let mut pads: Vec<(usize, [usize; 2])> = entities
+        .filter_map(|e| Some((e.teleporter?.pair?, map_tile(map, e.position())?)))
+        .collect();
+    pads.sort();
+    pads.windows(2)
+        .filter(|pair| pair[0].0 == pair[1].0)
+        .map(|pair| [pair[0].1, pair[1].1])
+        .collect()
 }
 
 impl GameState {
-    pub fn new(clock: f64, map_dimensions: [usize; 2]) -> Self {
-        let mut map = Map::<2>::from(WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH, map_dimensions);
+    pub fn new(clock: f64, map_dimensions: [usize; 2], archetypes: Archetypes) -> Self {
+        Self::with_map(clock, random_maze(map_dimensions), None, archetypes)
+    }
+
+    /// Starts a game in the given map. Without spawns, entities are placed at random on free tiles.
+    /// With spawns, only the listed entities are created, and the start and end are picked at random if missing
+    pub fn with_map(clock: f64, map: Map<2>, spawns: Option<Spawns>, archetypes: Archetypes) -> Self {
         let mut entities = EntityManager::new();
-        random_dfs(&mut map, [0, 0]);
-
-        // Find all free tiles where we can put game objects
-        let mut free_tiles = Vec::<[usize; 2]>::new();
-        for i in 0..map.dimensions()[0] {
-            for j in 0..map.dimensions()[1] {
-                if map.get_item([i, j]) != WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH {
-                    free_tiles.push([i, j]);
-                }
-            }
-        }
+
+        let random_entities = spawns.is_none();
+        let spawns = spawns.unwrap_or_default();
+
+        // Find all free tiles where we can put game objects. Only the region where the player starts is used
+        // (or the largest one) so that the end is always reachable
+        let mut regions = connected_regions(&map, |tile| !map.is_solid(tile));
+        let region = match spawns.start {
+            Some(start) => regions.iter().position(|r| r.contains(&start)).unwrap_or(0),
+            None => 0,
+        };
+        let mut free_tiles = if region < regions.len() {
+            regions.swap_remove(region)
+        } else {
+            Vec::new()
+        };
+        // nothing is put on the crossings of weave mazes, since it would be either on the bridge or in the tunnel
+        free_tiles.retain(|&tile| !map.is_crossing(tile));
         free_tiles.shuffle(&mut rand::thread_rng());
+        if let Some(start) = spawns.start {
+            free_tiles.retain(|&v| v != start);
+            free_tiles.push(start);
+        }
 
         // find start and end positions
         let [start_row, start_col] = free_tiles.pop().unwrap_or([0, 0]);
-        let [facing_row, facing_col] = {
-            if map.get_item([start_row, start_col]) & WALL_SOUTH == 0 {
-                [start_row + 1, start_col]
-            } else if map.get_item([start_row, start_col]) & WALL_NORTH == 0 {
-                [start_row - 1, start_col]
-            } else if map.get_item([start_row, start_col]) & WALL_EAST == 0 {
-                [start_row, start_col + 1]
-            } else if map.get_item([start_row, start_col]) & WALL_WEST == 0 {
-                [start_row, start_col - 1]
-            } else {
-                [start_row, start_col]
-            }
-        };
+        let [facing_row, facing_col] = map
+            .open_neighbours([start_row, start_col])
+            .next()
+            .unwrap_or([start_row, start_col]);
 
-        free_tiles.retain(|&v| v != [facing_row as usize, facing_col as usize]);
+        free_tiles.retain(|&v| v != [facing_row, facing_col]);
+        if let Some(end) = spawns.end {
+            free_tiles.retain(|&v| v != end);
+            free_tiles.push(end);
+        }
         let [end_row, end_col] = free_tiles.pop().unwrap_or([start_row, start_col]);
 
-        // initialize entities: 3 rats, 2 opengl logos, 1 start indicator
-        let map_offset = Vector3::new(TILE_SIZE / 2.0, 0.0, TILE_SIZE / 2.0);
-        while let Some([entity_row, entity_col]) = free_tiles.pop() {
-            let (x, z) = (entity_col as f32 * TILE_SIZE, entity_row as f32 * TILE_SIZE);
-            let entity = if entities.len() < 3 {
-                Entity::Rat {
-                    id: entities.generate_id(),
-                    position: Vector3::new(x, 0.5, z) + map_offset,
-                }
-            } else if entities.len() < 5 {
-                Entity::OpenGL {
-                    id: entities.generate_id(),
-                    position: Vector3::new(x, 1.5, z) + map_offset,
-                }
-            } else if entities.len() < 9 {
-                Entity::Dodecahedron {
-                    id: entities.generate_id(),
-                    position: Vector3::new(x, 1.5, z) + map_offset,
-                }
-            } else {
-                break;
-            };
+        let placed = if random_entities {
+            let (start, end) = (Some([start_row, start_col]), Some([end_row, end_col]));
+            archetypes
+                .population
+                .place(&map, &free_tiles, start, end, &mut rand::thread_rng())
+        } else {
+            spawns.entities
+        };
+        let mut spawned: Vec<Entity> = placed
+            .iter()
+            .map(|(name, tile)| archetypes.spawn(name, tile_center(map.topology(), *tile, 0.0)))
+            .collect();
+        let mut next_pair = 0;
+        pair_teleporters(&mut spawned, &mut next_pair);
+        // checkpoints are numbered in the order they are listed, or from the nearest to the start when they are placed
+        // at random
+        let mut checkpoints: Vec<usize> = (0..spawned.len()).filter(|&i| is_checkpoint(&spawned[i])).collect();
+        if random_entities {
+            let distances = distances_from(&map, [start_row, start_col]);
+            let graph = map.graph();
+            checkpoints.sort_by_key(|&i| distances[graph.index(placed[i].1)]);
+        }
+        for (number, &i) in checkpoints.iter().enumerate() {
+            spawned[i].checkpoint = Some(number);
+        }
+        let checkpoint_count = checkpoints.len();
+        let links = teleporter_links(&map, spawned.iter());
+        for entity in spawned {
             entities.add(entity);
         }
-        let start_banner = Entity::Start {
-            id: entities.generate_id(),
-            position: Vector3::new(
-                facing_col as f32 * TILE_SIZE,
-                TILE_SIZE / 2.0,
-                facing_row as f32 * TILE_SIZE,
-            ) + map_offset,
-        };
-        let end_banner = Entity::End {
-            id: entities.generate_id(),
-            position: Vector3::new(end_col as f32 * TILE_SIZE, TILE_SIZE / 2.0, end_row as f32 * TILE_SIZE)
-                + map_offset,
-        };
+        // on maps that wrap around, the tile the player faces may be across the edge of the map. The banner is put
+        // in front of the player anyway, since it is only there to be looked at
+        let facing_offset = map.seam_offset(map.topology().center([start_row, start_col]), [facing_row, facing_col]);
+        let start_banner = archetypes.spawn(
+            START,
+            tile_center(map.topology(), [facing_row, facing_col], 0.0)
+                + Vector3::new(facing_offset.x, 0.0, facing_offset.y) * TILE_SIZE,
+        );
+        let end_banner = archetypes.spawn(END, tile_center(map.topology(), [end_row, end_col], 0.0));
 
-        let player_position = Vector3::new(
-            start_col as f32 * TILE_SIZE,
-            TILE_SIZE / 2.0,
-            start_row as f32 * TILE_SIZE,
-        ) + map_offset;
-        let player_id = entities.generate_id();
-        entities.add(Entity::Player { id: player_id, position: player_position });
+        let player = archetypes.spawn(PLAYER, tile_center(map.topology(), [start_row, start_col], 0.0));
+        let player_position = player.position();
+        let player_id = entities.add(player);
 
         let camera_rotation = Matrix::rotate_y(unsafe {
             atan2f(
@@ -114,8 +260,10 @@ impl GameState {
         entities.add(start_banner);
         entities.add(end_banner);
 
+        let metrics = analyze(&map, [start_row, start_col], [end_row, end_col], &links);
+
         return Self {
-            map,
+            map: map.convert(),
             entities,
             player_id,
             camera_rotation,
@@ -126,9 +274,697 @@ impl GameState {
             }),
             game_end_event: None,
             roll_events: Vec::new(),
+            metrics,
+            tower: None,
+            crossing_layer: None,
+            eye_offset: 0.0,
+            shift: None,
+            shift_event: None,
+            world: None,
+            archetypes,
+            inventory: HashMap::new(),
+            next_pair,
+            warp_event: None,
+            score: 0,
+            goal: Goal::default(),
+            checkpoint_count,
+            checkpoints_reached: 0,
+            started_at: clock,
+        };
+    }
+
+    /// Starts a game in an endless maze. The player starts in the middle of the first chunk, and there is no end
+    pub fn with_world(clock: f64, mut world: World, archetypes: Archetypes) -> Self {
+        let [rows, cols] = world.chunk_dimensions();
+        let first_chunk = world.center();
+        let start = world.window_origin(first_chunk);
+        let spawns = Spawns {
+            start: Some([start[0] + rows / 2, start[1] + cols / 2]),
+            ..Default::default()
+        };
+        let mut game = Self::with_map(clock, world.window(), Some(spawns), archetypes);
+        let end_id = game.entities.entries().find(|(_, e)| e.is(END)).map(|(id, _)| id);
+        if let Some(id) = end_id {
+            game.entities.remove_by_id(id);
+        }
+        // the start banner is dropped along with the first chunk
+        let start_id = game.entities.entries().find(|(_, e)| e.is(START)).map(|(id, _)| id);
+        if let Some(id) = start_id {
+            world.add_entity(first_chunk, id);
+        }
+        let window: Vec<[i64; 2]> = world.window_coords().collect();
+        for coords in window {
+            game.populate_chunk(&mut world, coords);
+        }
+        game.world = Some(world);
+        game
+    }
+
+    /// Spawns the entities of a chunk of an endless maze that was just loaded. They are the same every time the chunk
+    /// is loaded, except that none is put on the player's tile. The population rules apply to each chunk on its own
+    fn populate_chunk(&mut self, world: &mut World, coords: [i64; 2]) {
+        let Some(map) = world.chunk_map(coords) else {
+            return;
+        };
+        let origin = world.window_origin(coords);
+        let player = self.tile_of(self.player().position());
+        let free: Vec<[usize; 2]> = positions(world.chunk_dimensions())
+            .filter(|tile| player != Some([origin[0] + tile[0], origin[1] + tile[1]]))
+            .collect();
+        let placed = self
+            .archetypes
+            .population
+            .place(map, &free, None, None, &mut world.entity_rng(coords));
+        let mut spawned: Vec<Entity> = placed
+            .iter()
+            .map(|(name, [row, col])| {
+                let tile = [origin[0] + row, origin[1] + col];
+                self.archetypes.spawn(name, tile_center(self.map.topology(), tile, 0.0))
+            })
+            .collect();
+        pair_teleporters(&mut spawned, &mut self.next_pair);
+        for entity in spawned {
+            world.add_entity(coords, self.entities.add(entity));
+        }
+    }
+
+    /// Keeps the chunks of an endless maze loaded around the player. When the player walks into another chunk, the
+    /// window of chunks moves to be centered on it: chunks left behind are dropped with their entities, new ones are
+    /// generated ahead, and everything is moved back by the size of the chunks so the player stays in the middle
+    pub fn stream_world(&mut self) {
+        let Some(mut world) = self.world.take() else {
+            return;
+        };
+        let chunk = self.tile_of(self.player().position()).map(|tile| world.chunk_at(tile));
+        if let Some(chunk) = chunk.filter(|&chunk| chunk != world.center()) {
+            let [rows, cols] = world.chunk_dimensions();
+            let [di, dj] = [chunk[0] - world.center()[0], chunk[1] - world.center()[1]];
+            let (dropped, loaded) = world.recenter(chunk);
+            for id in dropped {
+                self.entities.remove_by_id(id);
+            }
+            let offset = Vector3::new((dj * cols as i64) as f32, 0.0, (di * rows as i64) as f32) * TILE_SIZE;
+            for (_, entity) in self.entities.entries_mut() {
+                entity.move_position(-offset);
+                if let Some(ai) = &mut entity.ai {
+                    ai.forget_route();
+                }
+            }
+            self.map = world.window().convert();
+            for coords in loaded {
+                self.populate_chunk(&mut world, coords);
+            }
+        }
+        self.world = Some(world);
+    }
+
+    /// Starts a game in a maze of stacked floors ([floor, row, col]). The player starts on the first floor and the end
+    /// is on the last one. Every floor gets its own random entities
+    pub fn with_tower(clock: f64, tower: Map<3>, archetypes: Archetypes) -> Self {
+        let [floors, rows, cols] = tower.dimensions();
+        let mut game = Self::with_map(clock, tower.floor(0), None, archetypes);
+        let mut parked: Vec<Vec<Entity>> = (0..floors).map(|_| Vec::new()).collect();
+        let mut rng = rand::thread_rng();
+        for (floor, floor_entities) in parked.iter_mut().enumerate().skip(1) {
+            let mut tiles: Vec<[usize; 2]> = positions([rows, cols]).collect();
+            tiles.shuffle(&mut rng);
+            if floor == floors - 1 {
+                let end_id = game.entities.entries().find(|(_, e)| e.is(END)).map(|(id, _)| id);
+                if let Some(mut end) = end_id.and_then(|id| game.entities.take_by_id(id)) {
+                    let tile = tiles.pop().unwrap_or([0, 0]);
+                    end.set_position(tile_center(tower.topology(), tile, end.position().y));
+                    floor_entities.push(end);
+                }
+            }
+            // floors above the first have no start, so the rules measured from it don't apply there
+            let placed = game
+                .archetypes
+                .population
+                .place(&tower.floor(floor), &tiles, None, None, &mut rng);
+            let mut spawned: Vec<Entity> = placed
+                .iter()
+                .map(|(name, tile)| game.archetypes.spawn(name, tile_center(tower.topology(), *tile, 0.0)))
+                .collect();
+            pair_teleporters(&mut spawned, &mut game.next_pair);
+            // the checkpoints of a floor come after those of the floors below it
+            for entity in spawned.iter_mut().filter(|e| is_checkpoint(e)) {
+                entity.checkpoint = Some(game.checkpoint_count);
+                game.checkpoint_count += 1;
+            }
+            floor_entities.append(&mut spawned);
+        }
+        game.tower = Some(Tower {
+            map: tower,
+            floor: 0,
+            parked,
+            arrival: None,
+        });
+        game
+    }
+
+    fn tile_of(&self, position: Vector3) -> Option<[usize; 2]> {
+        map_tile(&self.map, position)
+    }
+
+    /// Tiles of the two pads of each pair of teleporters on the current map, see `shortest_path_with_links`
+    pub fn teleporter_links(&self) -> Vec<[[usize; 2]; 2]> {
+        teleporter_links(&self.map, self.entities.iter())
+    }
+
+    /// Direction (in the XZ plane) out of the tile at the given position through its first open side. Pads of
+    /// teleporters are put in dead ends, where this is the way along the only corridor
+    fn corridor_direction(&self, position: Vector3) -> Option<Vector2> {
+        let tile = self.tile_of(position)?;
+        let topology = self.map.topology();
+        let exit = self.map.open_neighbours(tile).next()?;
+        let offset = self.map.seam_offset(topology.center(tile), exit);
+        Some((topology.center(exit) + offset - topology.center(tile)).normalized())
+    }
+
+    /// Sends the player through a teleporter to its partner. The player comes out of the partner's corridor facing the
+    /// same way, relative to it, as it went into the corridor of the first pad. Both pads rest for their cooldown
+    fn warp(&mut self, id: EntityId) {
+        let clock = self.clock;
+        let Some(entrance) = self.entities.get_by_id(id) else {
+            return;
+        };
+        let Some(pair) = entrance.teleporter.filter(|t| t.ready_at <= clock).and_then(|t| t.pair) else {
+            return;
+        };
+        let entrance_position = entrance.position();
+        let exit = self
+            .entities
+            .entries()
+            .find(|&(other, e)| other != id && e.teleporter.is_some_and(|t| t.pair == Some(pair)))
+            .map(|(other, e)| (other, e.position()));
+        let Some((exit_id, exit_position)) = exit else {
+            return;
+        };
+        for pad in [id, exit_id] {
+            if let Some(teleporter) = self.entities.get_mut_by_id(pad).and_then(|e| e.teleporter.as_mut()) {
+                teleporter.ready_at = clock + teleporter.cooldown;
+            }
+        }
+        if let (Some(inward), Some(outward)) = (
+            self.corridor_direction(entrance_position),
+            self.corridor_direction(exit_position),
+        ) {
+            // walking into the entrance is walking against its corridor's way out. Turning the camera by an angle
+            // around Y turns the view the other way in the XZ plane
+            let angle = |v: Vector2| v.x.atan2(v.y);
+            self.camera_rotation *= Matrix::rotate_y(angle(-inward) - angle(outward));
+        }
+        let player = self.player_mut();
+        let y = player.position().y;
+        player.set_position(Vector3::new(exit_position.x, y, exit_position.z));
+        // the player lands on the exit, whose effects wait until the player has stepped off it
+        if let Some(trigger) = self.entities.get_mut_by_id(exit_id).and_then(|e| e.trigger.as_mut()) {
+            trigger.touching = true;
+        }
+        self.warp_event = Some(GameEventType::Warp {
+            start_time: clock,
+            duration: WARP_DURATION,
+        });
+    }
+
+    /// Whether walking into a crossing from a tile leads onto its bridge. Bridges are entered by their ends, which
+    /// are the sides of the crossing without railings
+    fn enters_bridge(&self, crossing: [usize; 2], from: Option<[usize; 2]>) -> bool {
+        let railings = self.map.get_walls([crossing[0] as i32, crossing[1] as i32]);
+        self.map
+            .topology()
+            .sides(crossing)
+            .iter()
+            .find(|&&(side, _, _)| self.map.neighbour(crossing, side) == from)
+            .is_none_or(|&(side, _, _)| railings & side == 0)
+    }
+
+    /// Whether the player is on the bridge of a crossing, or would be after walking into it from their tile
+    pub fn on_bridge(&self, crossing: [usize; 2]) -> bool {
+        let from = self.tile_of(self.player().position());
+        if from == Some(crossing) {
+            return self.crossing_layer.unwrap_or(true);
+        }
+        self.enters_bridge(crossing, from)
+    }
+
+    /// Keeps track of the layer the player is on when they walk through a weave crossing, and moves the camera up or
+    /// down to it. `previous_position` is the position of the player before they last moved
+    pub fn update_layer(&mut self, previous_position: Vector3) {
+        let current = self
+            .tile_of(self.player().position())
+            .filter(|&tile| self.map.is_crossing(tile));
+        self.crossing_layer = match current {
+            Some(crossing) if self.crossing_layer.is_none() => {
+                Some(self.enters_bridge(crossing, self.tile_of(previous_position)))
+            }
+            Some(_) => self.crossing_layer,
+            None => None,
+        };
+        let target = match self.crossing_layer {
+            Some(true) => BRIDGE_EYE_OFFSET,
+            Some(false) => TUNNEL_EYE_OFFSET,
+            None => 0.0,
+        };
+        self.eye_offset += (target - self.eye_offset) * 0.25;
+    }
+
+    /// Regenerates a region of the maze when the time of the next shift comes. The region never includes the tile of
+    /// the player or the tiles around it, and the shift is undone if the player would be cut off from the start or
+    /// the end, could walk around a closed door, or could only find a key behind its door. Walls that changed rise and
+    /// fall during `shift_event`
+    pub fn shift_maze(&mut self) {
+        self.shift_maze_with_rng(&mut rand::thread_rng());
+    }
+
+    /// Like `shift_maze`, drawing the regions from the given generator
+    pub fn shift_maze_with_rng(&mut self, rng: &mut impl Rng) {
+        if self.shift_event.as_ref().is_some_and(|e| e.is_completed(self.clock)) {
+            self.shift_event = None;
+            if let Some(shift) = self.shift.as_mut() {
+                shift.previous = None;
+            }
+        }
+        let Some(shift) = self.shift.as_mut() else {
+            return;
+        };
+        if self.clock < shift.next_time
+            || self.shift_event.is_some()
+            || self.game_start_event.is_some()
+            || self.game_end_event.is_some()
+        {
+            return;
+        }
+        shift.next_time = self.clock + shift.interval;
+        let Some(player) = self.tile_of(self.player().position()) else {
+            return;
+        };
+        let targets: Vec<[usize; 2]> = self
+            .entities
+            .iter()
+            .filter(|e| e.is(START) || e.is(END))
+            .filter_map(|e| self.tile_of(e.position()))
+            .collect();
+        let regions = connected_regions(&self.map, |tile| !self.map.is_solid(tile)).len();
+        let keys = self.keys();
+        // targets behind closed doors, which must stay behind them
+        let locked: Vec<bool> = targets
+            .iter()
+            .map(|&target| shortest_path(&self.map, player, target).is_none())
+            .collect();
+        let topology = self.map.topology();
+        let player_center = topology.center(player);
+        let [rows, cols] = self.map.dimensions();
+
+        for _ in 0..SHIFT_ATTEMPTS {
+            let size = [rng.gen_range(3..=SHIFT_REGION_SIZE).min(rows), rng.gen_range(3..=SHIFT_REGION_SIZE).min(cols)];
+            let origin = [rng.gen_range(0..=rows - size[0]), rng.gen_range(0..=cols - size[1])];
+            let tiles: Vec<[usize; 2]> = positions(size)
+                .map(|[i, j]| [origin[0] + i, origin[1] + j])
+                .filter(|&tile| !self.map.is_solid(tile))
+                .collect();
+            // crossings are left alone, since carving next to them could cut their tunnels, and so are doors
+            let allowed = |tile: [usize; 2]| {
+                let center = topology.center(tile) + self.map.seam_offset(player_center, tile);
+                (center - player_center).length() > 1.5
+                    && !self.map.is_crossing(tile)
+                    && !self.map.touches_door(tile)
+                    && !topology
+                        .sides(tile)
+                        .iter()
+                        .any(|&(side, _, _)| self.map.neighbour(tile, side).is_some_and(|n| self.map.is_crossing(n)))
+            };
+            if tiles.len() < 2 || !tiles.iter().all(|&tile| allowed(tile)) {
+                continue;
+            }
+
+            let previous = self.map.clone();
+            regenerate_region_with_rng(&mut self.map, &tiles, rng);
+            // closed doors open with keys, so the paths may go through them. The doors must still lock the way, with
+            // every key found before its door like `Map::lock` placed them
+            let unlocked = self.map.opened();
+            let connected = targets
+                .iter()
+                .all(|&target| shortest_path(&unlocked, player, target).is_some())
+                && connected_regions(&self.map, |tile| !self.map.is_solid(tile)).len() <= regions;
+            let still_locked = targets
+                .iter()
+                .zip(&locked)
+                .all(|(&target, &locked)| !locked || shortest_path(&self.map, player, target).is_none());
+            if connected && still_locked && self.map.keys_reachable(player, &keys) {
+                if let Some(shift) = self.shift.as_mut() {
+                    shift.previous = Some(previous);
+                }
+                self.shift_event = Some(GameEventType::Shift {
+                    start_time: self.clock,
+                    duration: SHIFT_DURATION,
+                });
+                return;
+            }
+            self.map = previous;
+        }
+    }
+
+    /// Moves the player to the other side of a map that wraps around when they cross its edge. The map is drawn
+    /// repeated around itself, so the view doesn't change
+    pub fn wrap_player(&mut self) {
+        if !self.map.wraps() {
+            return;
+        }
+        let period = self.map.topology().period(self.map.dimensions()) * TILE_SIZE;
+        let player = self.player_mut();
+        let position = player.position();
+        player.set_position(Vector3::new(
+            position.x.rem_euclid(period.x),
+            position.y,
+            position.z.rem_euclid(period.y),
+        ));
+    }
+
+    /// Moves the player to another floor of the tower when they walk onto stairs. In a tile with stairs, the way up
+    /// is in the west half and the way down is in the east half
+    pub fn climb_stairs(&mut self) {
+        let position = self.player().position();
+        let player_id = self.player_id;
+        let Some(tower) = self.tower.as_mut() else {
+            return;
+        };
+        if position.x < 0.0 || position.z < 0.0 {
+            return;
+        }
+        let tile = [(position.z / TILE_SIZE) as usize, (position.x / TILE_SIZE) as usize];
+        if tower.arrival.is_some_and(|arrival| arrival != tile) {
+            tower.arrival = None;
+        }
+        let cell = [tower.floor, tile[0], tile[1]];
+        if tower.arrival.is_some() || !tower.map.contains(cell) {
+            return;
+        }
+        let walls = tower.map.get_walls(cell);
+        let west_half = position.x - tile[1] as f32 * TILE_SIZE < TILE_SIZE / 2.0;
+        let next_floor = if west_half && walls & WALL_UP == 0 {
+            tower.floor + 1
+        } else if !west_half && walls & WALL_DOWN == 0 && tower.floor > 0 {
+            tower.floor - 1
+        } else {
+            return;
+        };
+        if next_floor >= tower.map.dimensions()[0] {
+            return;
+        }
+
+        for id in self.entities.ids() {
+            if id == player_id {
+                continue;
+            }
+            if let Some(entity) = self.entities.take_by_id(id) {
+                tower.parked[tower.floor].push(entity);
+            }
+        }
+        for entity in std::mem::take(&mut tower.parked[next_floor]) {
+            self.entities.add(entity);
+        }
+        self.map = tower.map.floor(next_floor).convert();
+        tower.floor = next_floor;
+        tower.arrival = Some(tile);
+    }
+
+    /// Spawns a minotaur on the tile farthest from the player that can be walked to. Crossings are left out, since the
+    /// minotaur walks around them
+    pub fn release_minotaur(&mut self) {
+        let Some(start) = self.tile_of(self.player().position()) else {
+            return;
+        };
+        let distances = distances_from(&self.map, start);
+        let graph = self.map.graph();
+        let lair = graph
+            .nodes()
+            .filter(|&tile| !self.map.is_crossing(tile))
+            .filter_map(|tile| Some((distances[graph.index(tile)]?, tile)))
+            .max();
+        if let Some((_, tile)) = lair.filter(|&(_, tile)| tile != start) {
+            let minotaur = self
+                .archetypes
+                .spawn(MINOTAUR, tile_center(self.map.topology(), tile, 0.0));
+            self.entities.add(minotaur);
+        }
+    }
+
+    /// Locks the way from the player to the end with the doors of the population, and spawns their keys, see
+    /// `Map::lock`. Keys aren't put on tiles that already have an entity
+    pub fn lock_doors(&mut self) {
+        self.lock_doors_with_rng(&mut rand::thread_rng());
+    }
+
+    /// Like `lock_doors`, drawing the doors and keys from the given generator
+    pub fn lock_doors_with_rng(&mut self, rng: &mut impl Rng) {
+        let count = self.archetypes.population.doors;
+        if count == 0 {
+            return;
+        }
+        let start = self.tile_of(self.player().position());
+        let end = self
+            .entities
+            .iter()
+            .find(|e| e.is(END))
+            .and_then(|e| self.tile_of(e.position()));
+        let (Some(start), Some(end)) = (start, end) else {
+            return;
+        };
+        let taken: Vec<[usize; 2]> = self
+            .entities
+            .iter()
+            .filter_map(|e| self.tile_of(e.position()))
+            .collect();
+        let keys = self.map.lock(start, end, count, &taken, rng);
+        for (color, tile) in keys {
+            let key = self
+                .archetypes
+                .spawn(&color.key(), tile_center(self.map.topology(), tile, 0.0));
+            self.entities.add(key);
+        }
+    }
+
+    /// Tiles of the keys that are still to be picked up, with the color of their doors
+    pub fn keys(&self) -> Vec<(KeyColor, [usize; 2])> {
+        KeyColor::ALL
+            .into_iter()
+            .flat_map(|color| {
+                self.entities
+                    .iter()
+                    .filter(move |e| e.is(&color.key()))
+                    .map(move |e| (color, e))
+            })
+            .filter_map(|(color, key)| Some((color, self.tile_of(key.position())?)))
+            .collect()
+    }
+
+    /// Opens the closed doors within reach of the player at the given position, when the player has their key
+    pub fn open_doors(&mut self, position: Vector3) {
+        let point = Vector2::new(position.x, position.z) / TILE_SIZE;
+        let reach = (self.player().collision_radius() + DOOR_REACH) / TILE_SIZE;
+        let topology = self.map.topology();
+        let within_reach: Vec<Wall> = self
+            .map
+            .doors()
+            .filter(|(_, door)| door.opened_at.is_none() && self.inventory.contains_key(&door.color.key()))
+            .filter(|(wall, _)| {
+                let offset = self.map.seam_offset(point, wall.tile);
+                let segment = wall.segment(topology).map(|p| p + offset);
+                let [start, end] = segment;
+                let t = (point - start).dot(end - start) / (end - start).length_sqr().max(f32::EPSILON);
+                (start + (end - start) * t.clamp(0.0, 1.0)).distance_to(point) < reach
+            })
+            .map(|(wall, _)| wall)
+            .collect();
+        for wall in within_reach {
+            self.map.open_door(wall, self.clock);
+        }
+    }
+
+    /// Finds the entities with a collider that the player touches, and applies the effects of their triggers when the
+    /// player starts touching them, while it does and when it stops, see `Trigger`. Returns whether the player touches
+    /// any of them
+    pub fn touch_entities(&mut self) -> bool {
+        let player = self.player();
+        let (center, radius) = (
+            Vector2::new(player.position.x, player.position.z),
+            player.collision_radius(),
+        );
+        let mut colliding = false;
+        let mut fired = Vec::new();
+        for (id, entity) in self.entities.entries_mut() {
+            if id == self.player_id {
+                continue;
+            }
+            let position = Vector2::new(entity.position.x, entity.position.z);
+            let touching = entity.collision_radius() > 0.0
+                && check_collision_circles(position, entity.collision_radius(), center, radius);
+            colliding |= touching;
+            let Some(trigger) = &mut entity.trigger else {
+                continue;
+            };
+            let effects: Vec<Effect> = match (trigger.touching, touching) {
+                (false, true) => trigger.on_enter.iter().chain(trigger.on_stay.iter()).cloned().collect(),
+                (true, true) => trigger.on_stay.clone(),
+                (true, false) => trigger.on_exit.clone(),
+                (false, false) => Vec::new(),
+            };
+            trigger.touching = touching;
+            if !effects.is_empty() {
+                fired.push((id, effects));
+            }
+        }
+        for (id, effects) in fired {
+            for effect in effects {
+                self.apply_effect(id, effect);
+            }
+        }
+        colliding
+    }
+
+    /// Applies an effect of the trigger of an entity
+    pub fn apply_effect(&mut self, id: EntityId, effect: Effect) {
+        let clock = self.clock;
+        match effect {
+            Effect::EndLevel | Effect::GameOver => {
+                // the end stays closed until the goal is met
+                if self.game_end_event.is_none() && (effect == Effect::GameOver || self.goal_met()) {
+                    self.game_end_event = Some(if effect == Effect::GameOver {
+                        GameEventType::GameOver {
+                            start_time: clock,
+                            duration: 2.0,
+                        }
+                    } else {
+                        GameEventType::GameEnd {
+                            start_time: clock,
+                            duration: 1.0,
+                        }
+                    });
+                }
+            }
+            Effect::RollCamera => {
+                let rolling =
+                    |e: &GameEventType| matches!(e, GameEventType::Roll { entity_id, .. } if *entity_id == id);
+                if !self.roll_events.iter().any(rolling) {
+                    self.roll_events.push(GameEventType::Roll {
+                        start_time: clock,
+                        duration: 1.0,
+                        entity_id: id,
+                    });
+                }
+            }
+            Effect::Teleport(target) => {
+                let destination = self
+                    .entities
+                    .entries()
+                    .find(|&(other, entity)| other != id && other != self.player_id && entity.is(&target))
+                    .map(|(other, entity)| (other, entity.position()));
+                if let Some((other, position)) = destination {
+                    let player = self.player_mut();
+                    let y = player.position().y;
+                    player.set_position(Vector3::new(position.x, y, position.z));
+                    // the player lands on the destination, whose effects wait until the player has stepped off it
+                    if let Some(trigger) = self.entities.get_mut_by_id(other).and_then(|e| e.trigger.as_mut()) {
+                        trigger.touching = true;
+                    }
+                }
+            }
+            Effect::Warp => self.warp(id),
+            Effect::GiveItem(item) => *self.inventory.entry(item).or_default() += 1,
+            Effect::Score(points) => self.score += points,
+            Effect::Checkpoint => {
+                if self.entities.get_by_id(id).and_then(|e| e.checkpoint) == Some(self.checkpoints_reached) {
+                    self.checkpoints_reached += 1;
+                }
+            }
+            Effect::Despawn(delay) => {
+                if let Some(entity) = self.entities.get_mut_by_id(id) {
+                    let until = entity.lifetime.map_or(clock + delay, |l| l.until.min(clock + delay));
+                    entity.lifetime = Some(Lifetime { until });
+                }
+            }
+            Effect::Animate(kind) => {
+                if let Some(entity) = self.entities.get_mut_by_id(id) {
+                    entity.animation = Some(Animation {
+                        kind,
+                        start_time: clock,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Moves the creatures for `dt` seconds, according to their AI
+    pub fn move_creatures(&mut self, dt: f32) {
+        let player = self.player().position();
+        let mut rng = rand::thread_rng();
+        for (_, entity) in self.entities.entries_mut() {
+            match &mut entity.ai {
+                Some(Ai::Wander(state)) => {
+                    entity.position = wander(&self.map, entity.position, state, player, dt, &mut rng);
+                }
+                Some(Ai::Chase(state)) => {
+                    entity.position = chase(&self.map, entity.position, state, player, dt, &mut rng);
+                }
+                Some(Ai::Idle) | None => {}
+            }
+        }
+    }
+
+    /// Sets the goal of the level. Without a count, the items to collect are those given by the entities of the level
+    /// (on every floor of a tower), so picking up every one of them is the goal
+    pub fn set_goal(&mut self, goal: Goal) {
+        let parked = self.tower.iter().flat_map(|tower| tower.parked.iter().flatten());
+        let gives_item = |entity: &&Entity| {
+            let gives = |e: &Effect| matches!(e, Effect::GiveItem(item) if goal.collect.as_ref() == Some(item));
+            entity.trigger.as_ref().is_some_and(|t| t.on_enter.iter().any(gives))
+        };
+        let available = self.entities.iter().chain(parked).filter(gives_item).count();
+        self.goal = Goal {
+            collect_count: goal.collect.as_ref().map(|_| goal.collect_count.unwrap_or(available)),
+            ..goal
         };
     }
 
+    /// Items of the goal collected so far, with how many must be collected
+    pub fn collected(&self) -> Option<(usize, usize)> {
+        let item = self.goal.collect.as_ref()?;
+        let count = self.goal.collect_count.unwrap_or(0);
+        Some((self.inventory.get(item).copied().unwrap_or(0), count))
+    }
+
+    /// Checkpoints reached so far, with how many there are, when the goal asks for them
+    pub fn checkpoints(&self) -> Option<(usize, usize)> {
+        self.goal
+            .checkpoints
+            .then_some((self.checkpoints_reached, self.checkpoint_count))
+    }
+
+    /// Whether everything the goal asks for is done, which opens the end
+    pub fn goal_met(&self) -> bool {
+        let done = |progress: Option<(usize, usize)>| progress.is_none_or(|(done, count)| done >= count);
+        done(self.collected()) && done(self.checkpoints())
+    }
+
+    /// How the level went so far. The time stops when the level ends
+    pub fn result(&self) -> LevelResult {
+        let end = self.game_end_event.as_ref().map_or(self.clock, |e| e.start_time());
+        LevelResult {
+            score: self.score,
+            seconds: end - self.started_at,
+            collected: self.collected(),
+            checkpoints: self.checkpoints(),
+        }
+    }
+
+    /// Despawns the entities whose lifetime has run out
+    pub fn expire_entities(&mut self) {
+        let clock = self.clock;
+        self.entities
+            .retain(|_, entity| entity.lifetime.is_none_or(|lifetime| lifetime.until > clock));
+    }
+
     pub fn update_events(&mut self) {
         if let Some(e) = &self.game_start_event {
             if e.is_completed(self.clock) {
@@ -142,6 +978,10 @@ impl GameState {
         }
     }
 
+    pub fn player_id(&self) -> EntityId {
+        self.player_id
+    }
+
     pub fn player(&self) -> &Entity {
         return self.entities.get_by_id(self.player_id).unwrap()
     }
@@ -154,3 +994,273 @@ impl GameState {
         self.clock = new_clock;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archetypes::parse_archetypes;
+    use crate::dfs::{random_dfs_with_rng, random_tower};
+    use crate::entities::{Collider, Trigger};
+    use crate::map::{WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST};
+    use crate::population::parse_population;
+    use crate::xml;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn archetypes() -> Archetypes {
+        parse_archetypes(include_str!("../assets/entities.xml")).unwrap()
+    }
+
+    /// Puts the player in the west or east half of a tile
+    fn stand_on(game: &mut GameState, [i, j]: [usize; 2], west: bool) {
+        let x = (j as f32 + if west { 0.25 } else { 0.75 }) * TILE_SIZE;
+        let z = (i as f32 + 0.5) * TILE_SIZE;
+        let y = game.player().position().y;
+        game.player_mut().set_position(Vector3::new(x, y, z));
+    }
+
+    #[test]
+    fn stairs_lead_to_the_next_floor_and_back() {
+        let tower = random_tower([2, 3, 3]);
+        let ground = tower.floor(0);
+        let stairs = ground
+            .cells()
+            .find(|&tile| ground.get_walls(tile) & WALL_UP == 0)
+            .unwrap();
+        let elsewhere = ground.cells().find(|&tile| tile != stairs).unwrap();
+        let mut game = GameState::with_tower(0.0, tower, archetypes());
+        assert!(!game.entities.iter().any(|e| e.is(END)));
+
+        // the way down is in the east half, and there is no floor below the first one
+        stand_on(&mut game, stairs, false);
+        game.climb_stairs();
+        assert_eq!(game.tower.as_ref().unwrap().floor, 0);
+
+        stand_on(&mut game, stairs, true);
+        game.climb_stairs();
+        assert_eq!(game.tower.as_ref().unwrap().floor, 1);
+        assert_eq!(game.map.get_walls(stairs) & WALL_DOWN, 0);
+        // the end waits on the top floor
+        assert!(game.entities.iter().any(|e| e.is(END)));
+
+        // the stairs the player arrived by are ignored until they step off them
+        stand_on(&mut game, stairs, false);
+        game.climb_stairs();
+        assert_eq!(game.tower.as_ref().unwrap().floor, 1);
+        stand_on(&mut game, elsewhere, false);
+        game.climb_stairs();
+        stand_on(&mut game, stairs, false);
+        game.climb_stairs();
+        assert_eq!(game.tower.as_ref().unwrap().floor, 0);
+        assert!(!game.entities.iter().any(|e| e.is(END)));
+    }
+
+    #[test]
+    fn triggers_fire_on_enter_stay_and_exit() {
+        let mut map = Map::<2>::from(0, [3, 3]);
+        map.wall_off_solid_cells(&[false; 9]);
+        let spawns = Spawns {
+            start: Some([0, 0]),
+            end: Some([2, 0]),
+            entities: Vec::new(),
+        };
+        let mut game = GameState::with_map(0.0, map, Some(spawns), archetypes());
+        let center = |tile| tile_center(Topology::Square, tile, 0.0);
+        let mut entity = Entity::new("plate", center([0, 2]));
+        entity.collider = Some(Collider { radius: 0.1 });
+        // each effect scores a different digit, so the score counts how many times each one fired
+        entity.trigger = Some(Trigger {
+            on_enter: vec![Effect::Score(1)],
+            on_stay: vec![Effect::Score(10)],
+            on_exit: vec![Effect::Score(100)],
+            touching: false,
+        });
+        game.entities.add(entity);
+
+        let walk_to = |game: &mut GameState, position: Vector3| {
+            let y = game.player().position().y;
+            game.player_mut().set_position(Vector3::new(position.x, y, position.z));
+            game.touch_entities()
+        };
+        assert!(!walk_to(&mut game, center([0, 1])));
+        assert_eq!(game.score, 0);
+        assert!(walk_to(&mut game, center([0, 2])));
+        assert_eq!(game.score, 11);
+        assert!(walk_to(&mut game, center([0, 2]) + Vector3::new(0.05, 0.0, 0.0)));
+        assert_eq!(game.score, 21);
+        assert!(!walk_to(&mut game, center([0, 1])));
+        assert_eq!(game.score, 121);
+        assert!(!walk_to(&mut game, center([1, 1])));
+        assert_eq!(game.score, 121);
+    }
+
+    #[test]
+    fn systems_only_use_the_components_an_entity_has() {
+        let archetypes = archetypes();
+        let smiley = archetypes.spawn("smiley", Vector3::zero());
+        assert!(smiley.sprite.is_some() && smiley.collider.is_some() && smiley.trigger.is_some());
+        assert!(smiley.ai.is_none() && smiley.lifetime.is_none());
+        let rat = archetypes.spawn("rat", Vector3::zero());
+        assert!(matches!(rat.ai, Some(Ai::Wander(_))) && rat.collider.is_none() && rat.trigger.is_none());
+
+        let mut map = Map::<2>::from(0, [3, 3]);
+        map.wall_off_solid_cells(&[false; 9]);
+        let spawns = Spawns {
+            start: Some([0, 0]),
+            end: Some([2, 2]),
+            entities: Vec::new(),
+        };
+        let mut game = GameState::with_map(0.0, map, Some(spawns), archetypes);
+        let position = game.player().position();
+        // a trigger without a collider is never touched, and a collider without a trigger is touched with no effect
+        let mut ghost = Entity::new("ghost", position);
+        ghost.trigger = Some(Trigger {
+            on_enter: vec![Effect::Score(1)],
+            ..Trigger::default()
+        });
+        let ghost = game.entities.add(ghost);
+        assert!(!game.touch_entities());
+        let mut post = Entity::new("post", position);
+        post.collider = Some(Collider { radius: 0.5 });
+        let post = game.entities.add(post);
+        assert!(game.touch_entities());
+        assert_eq!(game.score, 0);
+
+        // any entity with a lifetime expires, whatever else it is made of
+        game.entities.get_mut_by_id(post).unwrap().lifetime = Some(Lifetime { until: 1.0 });
+        game.clock = 2.0;
+        game.expire_entities();
+        assert!(!game.entities.contains(post));
+        assert!(game.entities.contains(ghost));
+    }
+
+    #[test]
+    fn shifts_keep_every_key_before_its_door() {
+        // seeded, so that every game has shifts that are kept. Three doors leave few regions that can be shifted
+        for seed in 0..5 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut map = Map::<2>::from(WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH, [9, 9]);
+            random_dfs_with_rng(&mut map, [0, 0], &mut rng);
+            let spawns = Spawns {
+                start: Some([0, 0]),
+                end: Some([8, 8]),
+                entities: Vec::new(),
+            };
+            let mut archetypes = archetypes();
+            archetypes.population.doors = 3;
+            let mut game = GameState::with_map(0.0, map, Some(spawns), archetypes);
+            game.lock_doors_with_rng(&mut rng);
+            let keys = game.keys();
+            assert!(!keys.is_empty());
+            let player = game.tile_of(game.player().position()).unwrap();
+            let end = game
+                .entities
+                .iter()
+                .find(|e| e.is(END))
+                .and_then(|e| game.tile_of(e.position()))
+                .unwrap();
+            assert!(game.map.keys_reachable(player, &keys));
+
+            game.shift = Some(MazeShift::new(0.0, 1.0));
+            game.game_start_event = None;
+            let mut shifts = 0;
+            for step in 1..=40 {
+                game.clock = step as f64 * 2.0;
+                game.shift_maze_with_rng(&mut rng);
+                shifts += game.shift_event.is_some() as usize;
+                assert!(game.map.keys_reachable(player, &keys));
+                assert!(shortest_path(&game.map, player, end).is_none());
+                assert!(shortest_path(&game.map.opened(), player, end).is_some());
+            }
+            assert!(shifts > 0);
+        }
+    }
+
+    /// A corridor of 5 tiles from the start on the west to the end on the east, with the given entities in between
+    fn corridor(entities: &[(&str, [usize; 2])]) -> GameState {
+        let mut map = Map::<2>::from(0, [1, 5]);
+        map.wall_off_solid_cells(&[false; 5]);
+        let spawns = Spawns {
+            start: Some([0, 0]),
+            end: Some([0, 4]),
+            entities: entities.iter().map(|&(name, tile)| (name.to_string(), tile)).collect(),
+        };
+        GameState::with_map(0.0, map, Some(spawns), archetypes())
+    }
+
+    /// Moves the player to the center of a tile and touches what is there
+    fn walk_onto(game: &mut GameState, tile: [usize; 2]) {
+        let position = tile_center(Topology::Square, tile, game.player().position().y);
+        game.player_mut().set_position(position);
+        game.touch_entities();
+    }
+
+    #[test]
+    fn the_end_opens_once_everything_is_collected() {
+        let mut game = corridor(&[("smiley", [0, 2]), ("smiley", [0, 3])]);
+        game.set_goal(Goal {
+            collect: Some("smiley".to_string()),
+            ..Goal::default()
+        });
+        assert_eq!(game.collected(), Some((0, 2)));
+
+        walk_onto(&mut game, [0, 4]);
+        assert!(game.game_end_event.is_none());
+        walk_onto(&mut game, [0, 2]);
+        walk_onto(&mut game, [0, 4]);
+        assert_eq!(game.collected(), Some((1, 2)));
+        assert!(game.game_end_event.is_none());
+
+        walk_onto(&mut game, [0, 3]);
+        assert!(game.goal_met());
+        walk_onto(&mut game, [0, 4]);
+        assert!(matches!(game.game_end_event, Some(GameEventType::GameEnd { .. })));
+        assert_eq!(game.score, 20);
+    }
+
+    #[test]
+    fn checkpoints_only_count_in_order() {
+        let mut game = corridor(&[("checkpoint", [0, 2]), ("checkpoint", [0, 3])]);
+        game.set_goal(Goal {
+            checkpoints: true,
+            ..Goal::default()
+        });
+        assert_eq!(game.checkpoints(), Some((0, 2)));
+
+        // the second checkpoint is passed by first, then reached again after the first one
+        walk_onto(&mut game, [0, 3]);
+        assert_eq!(game.checkpoints(), Some((0, 2)));
+        walk_onto(&mut game, [0, 2]);
+        assert_eq!(game.checkpoints(), Some((1, 2)));
+        walk_onto(&mut game, [0, 4]);
+        assert!(game.game_end_event.is_none());
+        walk_onto(&mut game, [0, 3]);
+        assert_eq!(game.checkpoints(), Some((2, 2)));
+        walk_onto(&mut game, [0, 4]);
+        assert!(game.game_end_event.is_some());
+    }
+
+    #[test]
+    fn goals_count_the_items_on_every_floor() {
+        let mut archetypes = archetypes();
+        let rules = xml::parse(r#"<population><spawn archetype="smiley" count="2"/></population>"#).unwrap();
+        archetypes.population = parse_population(&rules).unwrap();
+        let mut game = GameState::with_tower(0.0, random_tower([3, 4, 4]), archetypes);
+        let on_this_floor = game.entities.iter().filter(|e| e.is("smiley")).count();
+        assert_eq!(on_this_floor, 2);
+
+        game.set_goal(Goal {
+            collect: Some("smiley".to_string()),
+            ..Goal::default()
+        });
+        assert_eq!(game.collected(), Some((0, 6)));
+
+        // a count given by the goal is kept as it is
+        game.set_goal(Goal {
+            collect: Some("smiley".to_string()),
+            collect_count: Some(3),
+            ..Goal::default()
+        });
+        assert_eq!(game.collected(), Some((0, 3)));
+    }
+}
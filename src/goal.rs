@@ -0,0 +1,95 @@
+use std::fmt;
+
+/// What the player must do before the end of a level opens, on top of walking to it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Goal {
+    /// Item to collect, see `Effect::GiveItem`
+    pub collect: Option<String>,
+    /// How many of the item to collect. Without a count, every entity that gives the item must be collected
+    pub collect_count: Option<usize>,
+    /// Whether every checkpoint must be reached, in order, see `Effect::Checkpoint`
+    pub checkpoints: bool,
+}
+
+/// Reads a goal from named values, the attributes of a `<goal>` element or the properties of a Tiled map:
+/// `collect` (an item), `collect-count` (a number, or `all`) and `checkpoints` (`true` or `false`)
+pub fn parse_goal<'a>(value: impl Fn(&str) -> Option<&'a str>) -> Result<Goal, String> {
+    let collect = value("collect").filter(|item| !item.is_empty()).map(str::to_string);
+    let collect_count = match value("collect-count") {
+        Some("all") | None => None,
+        Some(v) => Some(v.parse().map_err(|_| format!("invalid collect-count '{v}'"))?),
+    };
+    if collect.is_none() && collect_count.is_some() {
+        return Err("collect-count without an item to collect".into());
+    }
+    let checkpoints = match value("checkpoints") {
+        Some("true") => true,
+        Some("false") | None => false,
+        Some(v) => return Err(format!("invalid checkpoints '{v}'")),
+    };
+    Ok(Goal {
+        collect,
+        collect_count,
+        checkpoints,
+    })
+}
+
+/// How a level went, shown on the scoreboard when it ends
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevelResult {
+    pub score: i64,
+    /// Seconds from the start of the level to the end
+    pub seconds: f64,
+    /// Items collected for the goal, with how many the goal asked for
+    pub collected: Option<(usize, usize)>,
+    /// Checkpoints reached, with how many there are
+    pub checkpoints: Option<(usize, usize)>,
+}
+
+impl fmt::Display for LevelResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} points in {:.1}s", self.score, self.seconds)?;
+        if let Some((collected, count)) = self.collected {
+            write!(f, ", {collected}/{count} collected")?;
+        }
+        if let Some((reached, count)) = self.checkpoints {
+            write!(f, ", {reached}/{count} checkpoints")?;
+        }
+        Ok(())
+    }
+}
+
+/// Results of the levels played so far, best first: by score, then by time
+pub fn best_results(results: &[LevelResult]) -> Vec<&LevelResult> {
+    let mut best: Vec<&LevelResult> = results.iter().collect();
+    best.sort_by(|a, b| b.score.cmp(&a.score).then(a.seconds.total_cmp(&b.seconds)));
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn goals_are_parsed() {
+        let parse = |pairs: &[(&'static str, &'static str)]| {
+            let values: HashMap<&str, &str> = pairs.iter().copied().collect();
+            parse_goal(|name| values.get(name).copied())
+        };
+        assert_eq!(parse(&[]), Ok(Goal::default()));
+        let goal = parse(&[("collect", "smiley"), ("collect-count", "3"), ("checkpoints", "true")]).unwrap();
+        assert_eq!(goal.collect.as_deref(), Some("smiley"));
+        assert_eq!(goal.collect_count, Some(3));
+        assert!(goal.checkpoints);
+        assert_eq!(
+            parse(&[("collect", "smiley"), ("collect-count", "all")])
+                .unwrap()
+                .collect_count,
+            None
+        );
+        assert!(parse(&[("collect-count", "3")]).is_err());
+        assert!(parse(&[("collect", "smiley"), ("collect-count", "many")]).is_err());
+        assert!(parse(&[("checkpoints", "yes")]).is_err());
+    }
+}
@@ -0,0 +1,475 @@
+use std::collections::VecDeque;
+
+use raylib::prelude::Vector2;
+
+use crate::map::{positions, GetSetMap, TUNNEL, WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST};
+use crate::topology::Topology;
+
+pub const ALL_WALLS: i8 = WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH;
+
+/// Each side of a tile with the wall on the other side of the same edge and the offset to the neighbouring tile
+pub const SIDES: [(i8, i8, [i32; 2]); 4] = [
+    (WALL_SOUTH, WALL_NORTH, [1, 0]),
+    (WALL_NORTH, WALL_SOUTH, [-1, 0]),
+    (WALL_EAST, WALL_WEST, [0, 1]),
+    (WALL_WEST, WALL_EAST, [0, -1]),
+];
+
+fn signed([i, j]: [usize; 2]) -> [i32; 2] {
+    [i as i32, j as i32]
+}
+
+/// Distance between the points where `Grid::line_of_sight` looks up the tile under the line, in tiles. Lower than
+/// the width of the thinnest corner of a tile the line can cut, so that no tile is skipped
+const SIGHT_STEP: f32 = 0.25;
+
+/// Whether the segments a-b and c-d cross each other. Segments that only touch don't cross
+fn segments_cross([a, b]: [Vector2; 2], [c, d]: [Vector2; 2]) -> bool {
+    let side = |p: Vector2, q: Vector2, r: Vector2| (q.x - p.x) * (r.y - p.y) - (q.y - p.y) * (r.x - p.x);
+    side(a, b, c) * side(a, b, d) < 0.0 && side(c, d, a) * side(c, d, b) < 0.0
+}
+
+/// A wall on one side of a tile
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Wall {
+    pub tile: [usize; 2],
+    pub side: i8,
+}
+
+impl Wall {
+    /// End points of the wall, in tiles. See `Topology::wall_segment`
+    pub fn segment(&self, topology: Topology) -> [Vector2; 2] {
+        topology.wall_segment(self.tile, self.side)
+    }
+}
+
+/// Traversal of 2D maps and views of them, following the topology of the map. An edge between two tiles is closed if
+/// either tile has a wall on it, which is how wall collisions see it too
+pub trait Grid: GetSetMap<i32, 2> + Sized {
+    /// Every position of the map in row-major order
+    fn cells(&self) -> impl Iterator<Item = [usize; 2]> + 'static {
+        positions(self.dimensions())
+    }
+
+    /// Whether the tile has all its walls, so it can't be walked into
+    fn is_solid(&self, tile: [usize; 2]) -> bool {
+        self.get_walls(signed(tile)) == self.topology().all_walls()
+    }
+
+    /// The tile at a position that may be outside of the map. Maps that wrap around bring every position back inside
+    fn tile(&self, [i, j]: [i32; 2]) -> Option<[usize; 2]> {
+        let [i, j] = if self.wraps() {
+            let [rows, cols] = self.dimensions();
+            [i.rem_euclid(rows as i32), j.rem_euclid(cols as i32)]
+        } else {
+            [i, j]
+        };
+        self.contains([i, j]).then_some([i as usize, j as usize])
+    }
+
+    /// The tile on the other side of the given side, if it is part of the map
+    fn neighbour(&self, tile: [usize; 2], side: i8) -> Option<[usize; 2]> {
+        let (_, _, [di, dj]) = self.topology().sides(tile).iter().find(|(wall, _, _)| *wall == side)?;
+        let [i, j] = signed(tile);
+        self.tile([i + di, j + dj])
+    }
+
+    /// Offset that moves the geometry of the tile to its copy closest to the point, in tiles. Maps that wrap around
+    /// repeat in every direction, so a neighbour across the edge of the map is drawn and collided with next to the
+    /// tile rather than on the other side of the map. Always zero for maps that don't wrap around
+    fn seam_offset(&self, point: Vector2, tile: [usize; 2]) -> Vector2 {
+        if !self.wraps() {
+            return Vector2::zero();
+        }
+        let period = self.topology().period(self.dimensions());
+        let distance = point - self.topology().center(tile);
+        Vector2::new(
+            (distance.x / period.x).round() * period.x,
+            (distance.y / period.y).round() * period.y,
+        )
+    }
+
+    /// Whether the tile is the crossing of a bridge and a tunnel, see `TUNNEL`
+    fn is_crossing(&self, tile: [usize; 2]) -> bool {
+        self.get_walls(signed(tile)) & TUNNEL != 0
+    }
+
+    /// Whether there is a wall on the given side of the tile, set on the tile or on the neighbour sharing the edge.
+    /// The walls of a crossing are railings above its tunnel, so they don't close the edge
+    fn has_wall(&self, tile: [usize; 2], side: i8) -> bool {
+        let Some(&(_, opposite, _)) = self.topology().sides(tile).iter().find(|(wall, _, _)| *wall == side) else {
+            return false;
+        };
+        let closes = |tile: [usize; 2], side: i8| !self.is_crossing(tile) && self.get_walls(signed(tile)) & side != 0;
+        closes(tile, side) || self.neighbour(tile, side).is_some_and(|n| closes(n, opposite))
+    }
+
+    /// Sides of a crossing that are closed to someone on its bridge (the railings) or in its tunnel (the ends of the
+    /// bridge). Tiles that aren't crossings have none
+    fn crossing_walls(&self, tile: [usize; 2], on_bridge: bool) -> impl Iterator<Item = i8> + '_ {
+        let walls = self.get_walls(signed(tile));
+        let crossing = self.is_crossing(tile);
+        self.topology()
+            .sides(tile)
+            .iter()
+            .map(|(side, _, _)| *side)
+            .filter(move |&side| crossing && (walls & side != 0) == on_bridge)
+    }
+
+    /// Sides of the tile that have a wall
+    fn walls_around(&self, tile: [usize; 2]) -> impl Iterator<Item = i8> + '_ {
+        self.topology()
+            .sides(tile)
+            .iter()
+            .map(|(side, _, _)| *side)
+            .filter(move |&side| self.has_wall(tile, side))
+    }
+
+    /// Whether the straight line between two points (in tiles) goes through no wall and stays in the map. The tiles
+    /// under the line are looked up every `SIGHT_STEP`, and the line is checked against each of their walls. The
+    /// layers of weave crossings are ignored: their railings and tunnel walls don't block the line
+    fn line_of_sight(&self, from: Vector2, to: Vector2) -> bool {
+        let topology = self.topology();
+        let steps = ((to - from).length() / SIGHT_STEP).ceil().max(1.0) as usize;
+        let mut checked: Vec<[i32; 2]> = Vec::new();
+        for step in 0..=steps {
+            let point = from + (to - from) * (step as f32 / steps as f32);
+            let cell = topology.tile_at(point);
+            if checked.contains(&cell) {
+                continue;
+            }
+            checked.push(cell);
+            let Some(tile) = self.tile(cell) else {
+                return false;
+            };
+            let offset = self.seam_offset(point, tile);
+            if self
+                .walls_around(tile)
+                .any(|side| segments_cross([from, to], topology.wall_segment(tile, side).map(|p| p + offset)))
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Tiles that can be reached from the tile in one step. A crossing is reached by its bridge, and walking into
+    /// its tunnel leads to the tile on the other side of it, so the crossing stands for its bridge only
+    fn open_neighbours(&self, tile: [usize; 2]) -> impl Iterator<Item = [usize; 2]> + '_ {
+        let railings = self.get_walls(signed(tile));
+        let crossing = self.is_crossing(tile);
+        self.topology()
+            .sides(tile)
+            .iter()
+            .filter_map(move |&(side, opposite, _)| {
+                if self.has_wall(tile, side) || (crossing && railings & side != 0) {
+                    return None;
+                }
+                let mut neighbour = self.neighbour(tile, side)?;
+                while self.is_crossing(neighbour) && self.get_walls(signed(neighbour)) & opposite != 0 {
+                    neighbour = self
+                        .neighbour(neighbour, side)
+                        .filter(|_| !self.has_wall(neighbour, side))?;
+                }
+                Some(neighbour)
+            })
+    }
+
+    /// Every open edge between two tiles, once. The first tile is the one that comes first in row-major order
+    fn passages(&self) -> impl Iterator<Item = [[usize; 2]; 2]> + '_ {
+        self.cells().flat_map(move |tile| {
+            self.open_neighbours(tile)
+                .filter(move |&neighbour| neighbour > tile)
+                .map(move |neighbour| [tile, neighbour])
+        })
+    }
+
+    /// Every wall of the map, once. Walls shared by two tiles are returned on the tile that comes last in row-major
+    /// order, which for square tiles is their north or west wall
+    fn walls(&self) -> impl Iterator<Item = Wall> + '_ {
+        self.cells().flat_map(move |tile| {
+            self.topology().sides(tile).iter().filter_map(move |&(side, _, _)| {
+                let shared = self.neighbour(tile, side).is_some_and(|n| n > tile);
+                (!shared && self.has_wall(tile, side)).then_some(Wall { tile, side })
+            })
+        })
+    }
+
+    fn graph(&self) -> MazeGraph<'_, Self> {
+        MazeGraph { map: self }
+    }
+}
+
+impl<M: GetSetMap<i32, 2>> Grid for M {}
+
+/// The maze seen as an undirected graph: the nodes are the walkable tiles and the edges are the open passages
+pub struct MazeGraph<'a, M> {
+    map: &'a M,
+}
+
+impl<'a, M> Clone for MazeGraph<'a, M> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, M> Copy for MazeGraph<'a, M> {}
+
+impl<'a, M: Grid> MazeGraph<'a, M> {
+    pub fn nodes(&self) -> impl Iterator<Item = [usize; 2]> + 'a {
+        let map = self.map;
+        map.cells().filter(move |&tile| !map.is_solid(tile))
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = [[usize; 2]; 2]> + 'a {
+        self.map.passages()
+    }
+
+    pub fn neighbours(&self, node: [usize; 2]) -> impl Iterator<Item = [usize; 2]> + 'a {
+        self.map.open_neighbours(node)
+    }
+
+    pub fn degree(&self, node: [usize; 2]) -> usize {
+        self.neighbours(node).count()
+    }
+
+    /// Position of a node in buffers holding a value for every tile of the map
+    pub fn index(&self, [i, j]: [usize; 2]) -> usize {
+        i * self.map.dimensions()[1] + j
+    }
+
+    /// Length of buffers holding a value for every tile of the map
+    pub fn capacity(&self) -> usize {
+        self.map.dimensions().iter().product()
+    }
+
+    /// Visits the nodes reachable from `start` in breadth-first order, each one along with the node it was reached
+    /// from. The start node comes first and is reached from itself
+    pub fn breadth_first(&self, start: [usize; 2]) -> BreadthFirst<'a, M> {
+        let mut visited = vec![false; self.capacity()];
+        let mut queue = VecDeque::new();
+        if self.map.contains(signed(start)) {
+            visited[self.index(start)] = true;
+            queue.push_back((start, start));
+        }
+        BreadthFirst {
+            graph: *self,
+            visited,
+            queue,
+        }
+    }
+}
+
+pub struct BreadthFirst<'a, M> {
+    graph: MazeGraph<'a, M>,
+    visited: Vec<bool>,
+    queue: VecDeque<([usize; 2], [usize; 2])>,
+}
+
+impl<'a, M: Grid> Iterator for BreadthFirst<'a, M> {
+    type Item = ([usize; 2], [usize; 2]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (node, previous) = self.queue.pop_front()?;
+        for neighbour in self.graph.neighbours(node) {
+            let index = self.graph.index(neighbour);
+            if !self.visited[index] {
+                self.visited[index] = true;
+                self.queue.push_back((neighbour, node));
+            }
+        }
+        Some((node, previous))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dfs::{random_maze, random_maze_with, random_weave_maze, regenerate_region};
+    use crate::map::positions;
+    use crate::map::{Map, RectangularMap};
+
+    /// 2x3 map with a wall between [0, 0] and [0, 1] only set on [0, 0], and a solid tile at [1, 2]:
+    /// ```text
+    /// ┌───┬───────┐
+    /// │ a │ b   c │
+    /// │   └───┬───┤
+    /// │ d   e │###│
+    /// └───────┴───┘
+    /// ```
+    fn sample() -> Map<2> {
+        let mut map = Map::<2>::from(0, [2, 3]);
+        map.set_item([0, 0], WALL_NORTH | WALL_WEST | WALL_EAST);
+        map.set_item([0, 1], WALL_NORTH | WALL_SOUTH);
+        map.set_item([0, 2], WALL_NORTH | WALL_EAST | WALL_SOUTH);
+        map.set_item([1, 0], WALL_WEST | WALL_SOUTH);
+        map.set_item([1, 1], WALL_NORTH | WALL_SOUTH | WALL_EAST);
+        map.set_item([1, 2], ALL_WALLS);
+        map
+    }
+
+    #[test]
+    fn one_sided_walls_close_the_edge() {
+        let map = sample();
+        assert!(map.has_wall([0, 1], WALL_WEST));
+        assert_eq!(map.open_neighbours([0, 1]).collect::<Vec<_>>(), vec![[0, 2]]);
+        let mut neighbours: Vec<_> = map.open_neighbours([1, 0]).collect();
+        neighbours.sort();
+        assert_eq!(neighbours, vec![[0, 0], [1, 1]]);
+    }
+
+    #[test]
+    fn sight_is_blocked_by_walls() {
+        let map = sample();
+        let center = |[i, j]: [usize; 2]| Vector2::new(j as f32 + 0.5, i as f32 + 0.5);
+        assert!(map.line_of_sight(center([0, 1]), center([0, 2])));
+        assert!(map.line_of_sight(center([1, 0]), center([1, 1])));
+        // the one-sided wall between a and b, the wall between b and e, and the solid tile
+        assert!(!map.line_of_sight(center([0, 0]), center([0, 1])));
+        assert!(!map.line_of_sight(center([0, 1]), center([1, 1])));
+        assert!(!map.line_of_sight(center([1, 1]), center([1, 2])));
+        // from a to e, just above the corner where the walls of a and b meet, and out of the map
+        assert!(!map.line_of_sight(center([0, 0]), Vector2::new(1.6, 1.4)));
+        assert!(map.line_of_sight(center([0, 0]), Vector2::new(1.4, 1.6)));
+        assert!(!map.line_of_sight(center([0, 2]), Vector2::new(5.0, 0.5)));
+    }
+
+    #[test]
+    fn passages_are_listed_once() {
+        let map = sample();
+        let passages: Vec<_> = map.passages().collect();
+        assert_eq!(passages, vec![[[0, 0], [1, 0]], [[0, 1], [0, 2]], [[1, 0], [1, 1]]]);
+    }
+
+    #[test]
+    fn walls_are_listed_once() {
+        let map = sample();
+        let walls: Vec<_> = map.walls().collect();
+        // 9 horizontal and 8 vertical edges, 3 of them open
+        assert_eq!(walls.len(), 14);
+        let mut segments: Vec<_> = walls
+            .iter()
+            .map(|w| {
+                let mut ends = w.segment(Topology::Square).map(|p| [p.x as i32, p.y as i32]);
+                ends.sort();
+                ends
+            })
+            .collect();
+        segments.sort();
+        segments.dedup();
+        assert_eq!(segments.len(), walls.len());
+        assert!(walls.contains(&Wall {
+            tile: [0, 1],
+            side: WALL_WEST
+        }));
+        assert!(!walls.contains(&Wall {
+            tile: [0, 0],
+            side: WALL_EAST
+        }));
+    }
+
+    #[test]
+    fn graph_skips_solid_tiles() {
+        let map = sample();
+        let graph = map.graph();
+        assert_eq!(graph.nodes().count(), 5);
+        assert_eq!(graph.degree([1, 2]), 0);
+        assert_eq!(graph.degree([1, 0]), 2);
+        let order: Vec<_> = graph.breadth_first([0, 0]).collect();
+        assert_eq!(order, vec![([0, 0], [0, 0]), ([1, 0], [0, 0]), ([1, 1], [1, 0])]);
+    }
+
+    #[test]
+    fn perfect_maze_is_a_tree() {
+        for _ in 0..20 {
+            let map = random_maze([7, 9]);
+            let graph = map.graph();
+            assert_eq!(graph.edges().count(), graph.nodes().count() - 1);
+            assert_eq!(graph.breadth_first([3, 4]).count(), 63);
+            let degrees: usize = graph.nodes().map(|n| graph.degree(n)).sum();
+            assert_eq!(degrees, 2 * graph.edges().count());
+            // every edge is either open or has a wall, the outer border included
+            assert_eq!(map.walls().count() + graph.edges().count(), 7 * 10 + 9 * 8);
+        }
+    }
+
+    #[test]
+    fn hex_maze_is_a_tree() {
+        let map = random_maze_with(Topology::Hex, [6, 7], false);
+        let graph = map.graph();
+        assert_eq!(graph.edges().count(), 41);
+        assert_eq!(graph.breadth_first([0, 0]).count(), 42);
+        for tile in map.cells() {
+            let [i, j] = Topology::Hex.tile_at(Topology::Hex.center(tile));
+            assert_eq!([i as usize, j as usize], tile);
+            // neighbours share the edge, so both see the same wall
+            for &(side, opposite, _) in Topology::Hex.sides(tile) {
+                if let Some(neighbour) = map.neighbour(tile, side) {
+                    assert_eq!(map.neighbour(neighbour, opposite), Some(tile));
+                    let [a, b] = Topology::Hex.wall_segment(tile, side);
+                    let [c, d] = Topology::Hex.wall_segment(neighbour, opposite);
+                    assert!((a - d).length() < 1e-4 && (b - c).length() < 1e-4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wrapped_maze_has_no_border() {
+        for topology in [Topology::Square, Topology::Hex] {
+            let map = random_maze_with(topology, [4, 5], true);
+            let graph = map.graph();
+            assert_eq!(graph.edges().count(), 19);
+            assert_eq!(graph.breadth_first([0, 0]).count(), 20);
+            for tile in map.cells() {
+                assert_eq!(map.topology().sides(tile).len(), map.topology().side_count());
+                for &(side, opposite, _) in map.topology().sides(tile) {
+                    let neighbour = map.neighbour(tile, side).unwrap();
+                    assert_eq!(map.neighbour(neighbour, opposite), Some(tile));
+                    // the neighbour's copy next to the tile is one step away
+                    let center = map.topology().center(tile);
+                    let distance = map.topology().center(neighbour) + map.seam_offset(center, neighbour) - center;
+                    assert!((distance.length() - 1.0).abs() < 1e-4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn weave_maze_is_a_tree() {
+        let mut crossings = 0;
+        for _ in 0..10 {
+            let map = random_weave_maze([9, 9], false);
+            let graph = map.graph();
+            // every tile is a node, crossings included, and tunnels are edges between the tiles on both sides
+            assert_eq!(graph.edges().count(), 80);
+            assert_eq!(graph.breadth_first([0, 0]).count(), 81);
+            for tile in map.cells().filter(|&tile| map.is_crossing(tile)) {
+                crossings += 1;
+                assert_eq!(graph.degree(tile), 2);
+                assert_eq!(map.walls_around(tile).count(), 0);
+                assert_eq!(map.crossing_walls(tile, true).count(), 2);
+                assert_eq!(map.crossing_walls(tile, false).count(), 2);
+            }
+        }
+        assert!(crossings > 0);
+    }
+
+    #[test]
+    fn regenerated_region_keeps_a_tree() {
+        for _ in 0..10 {
+            let mut map = random_maze([8, 8]);
+            let before: Vec<Wall> = map.walls().collect();
+            let region: Vec<[usize; 2]> = positions([3, 4]).map(|[i, j]| [i + 2, j + 3]).collect();
+            regenerate_region(&mut map, &region);
+            assert_eq!(map.passages().count(), 63);
+            assert_eq!(map.graph().breadth_first([0, 0]).count(), 64);
+            // walls with a tile outside of the region don't move
+            let inside = |tile: [usize; 2]| region.contains(&tile);
+            let outside =
+                |wall: &Wall| !inside(wall.tile) || map.neighbour(wall.tile, wall.side).is_none_or(|n| !inside(n));
+            let after: Vec<Wall> = map.walls().filter(outside).collect();
+            assert_eq!(before.into_iter().filter(outside).collect::<Vec<_>>(), after);
+        }
+    }
+}
@@ -1,39 +1,71 @@
+pub mod ai;
+pub mod archetypes;
 pub mod assets;
+pub mod base64;
+pub mod bitmap;
+pub mod cli;
 pub mod constants;
 pub mod debug_ui;
 pub mod dfs;
+pub mod doors;
 pub mod drawable;
 pub mod entities;
 pub mod events;
+pub mod export;
 pub mod game;
+pub mod goal;
+pub mod grid;
 pub mod input;
 pub mod map;
+pub mod metrics;
+pub mod model_export;
+pub mod population;
 pub mod camera;
+pub mod solver;
+pub mod tiled;
+pub mod topology;
+pub mod validate;
+pub mod world;
+pub mod xml;
 
 use crate::{
+    archetypes::{load_archetypes, Archetypes},
     assets::AssetPack,
+    bitmap::load_bitmap,
+    cli::{Options, USAGE},
     constants::{
-        MOUSE_SENSITIVITY, PI, PLAYER_SPEED, PLAYER_SPRINT_SPEED, PLAYER_WALK_SPEED, SCREEN_H, SCREEN_W,
-        TARGET_FPS, TILE_SIZE,
+        MOUSE_SENSITIVITY, PI, PLAYER_SPEED, PLAYER_SPRINT_SPEED, PLAYER_WALK_SPEED, SCOREBOARD_DURATION, SCREEN_H,
+        SCREEN_W, TARGET_FPS, TILE_SIZE,
     },
-    debug_ui::{draw_debug_text, draw_xyz_indicator},
+    debug_ui::{draw_debug_text, draw_hud, draw_inventory, draw_scoreboard, draw_xyz_indicator},
+    dfs::{random_maze_with, random_tower, random_weave_maze},
     drawable::Drawable,
-    entities::Entity,
+    entities::MINOTAUR,
     events::GameEventType,
-    game::GameState,
+    export::{export_png, export_svg},
+    game::{GameState, MazeShift, Spawns},
+    goal::LevelResult,
+    grid::Grid,
     input::InputController,
-    map::{GetSetMap, WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST},
+    map::{Map, RectangularMap},
+    model_export::{export_gltf, export_obj},
+    tiled::{load_tmx, TiledLevel},
+    validate::{repair, validate},
+    world::World,
 };
 use camera::{get_xz_plane_parallel_rotation_matrix, get_camera_rotation_matrix};
 use raylib::prelude::*;
 use std::ops::Mul;
 
-/// Raylib implements Rectangle::check_collision_circle_rec but it's bugged. Use this implementation instead
-fn rec_circle_collision_point(rect: &Rectangle, center: Vector2, radius: f32) -> Option<Vector2> {
-    let closest_point = Vector2::new(
-        center.x.clamp(rect.x, rect.x + rect.width),
-        center.y.clamp(rect.y, rect.y + rect.height),
-    );
+/// Projects the point onto the segment, clamped to its ends
+fn closest_point_on_segment([start, end]: [Vector2; 2], point: Vector2) -> Vector2 {
+    let direction = end - start;
+    let t = (point - start).dot(direction) / direction.length_sqr().max(f32::EPSILON);
+    start + direction * t.clamp(0.0, 1.0)
+}
+
+fn segment_circle_collision_point(segment: [Vector2; 2], center: Vector2, radius: f32) -> Option<Vector2> {
+    let closest_point = closest_point_on_segment(segment, center);
     if (center - closest_point).length_sqr() < radius * radius {
         Some(closest_point)
     } else {
@@ -41,10 +73,164 @@ fn rec_circle_collision_point(rect: &Rectangle, center: Vector2, radius: f32) ->
     }
 }
 
+/// Builds the maze for a new game from the level or image given in the command line, or a random maze otherwise.
+/// Returns the spawns of the level, if it has them
+fn new_level(options: &Options, level: &Option<TiledLevel>, map_dimensions: [usize; 2]) -> (Map<2>, Option<Spawns>) {
+    if let Some(level) = level {
+        (level.map.clone(), Some(level.spawns.clone()))
+    } else if let Some(path) = &options.bitmap {
+        match load_bitmap(path, options.bitmap_mode, options.bitmap_block_size) {
+            Ok(map) => (map, None),
+            Err(message) => {
+                eprintln!("{message}");
+                std::process::exit(1);
+            }
+        }
+    } else if options.endless {
+        (World::new(endless_seed(options), map_dimensions).window(), None)
+    } else if options.weave {
+        (random_weave_maze(map_dimensions, options.wrap), None)
+    } else {
+        (random_maze_with(options.topology, map_dimensions, options.wrap), None)
+    }
+}
+
+/// Seed of an endless maze, random unless given in the command line
+fn endless_seed(options: &Options) -> u64 {
+    options.seed.unwrap_or_else(rand::random)
+}
+
+fn new_game(
+    options: &Options,
+    level: &Option<TiledLevel>,
+    archetypes: &Archetypes,
+    clock: f64,
+    map_dimensions: [usize; 2],
+) -> GameState {
+    let archetypes = archetypes.clone();
+    let mut game = if options.endless {
+        GameState::with_world(clock, World::new(endless_seed(options), map_dimensions), archetypes)
+    } else if options.floors > 1 && level.is_none() && options.bitmap.is_none() {
+        let [rows, cols] = map_dimensions;
+        GameState::with_tower(clock, random_tower([options.floors, rows, cols]), archetypes)
+    } else {
+        let (mut map, spawns) = new_level(options, level, map_dimensions);
+        if options.repair {
+            repair(&mut map);
+        }
+        // levels that list their entities have no doors
+        let locked = spawns.is_none();
+        let mut game = GameState::with_map(clock, map, spawns, archetypes);
+        game.shift = options.shift.map(|interval| MazeShift::new(clock, interval));
+        if locked {
+            game.lock_doors();
+        }
+        game
+    };
+    // endless mazes have no end to open
+    if !options.endless {
+        let goal = level.as_ref().and_then(|level| level.goal.clone());
+        game.set_goal(goal.unwrap_or_else(|| game.archetypes.goal.clone()));
+    }
+    if options.minotaur {
+        game.release_minotaur();
+    }
+    game
+}
+
 fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{USAGE}");
+        return;
+    }
+
+    let archetypes = match load_archetypes(&options.archetypes) {
+        Ok(archetypes) => archetypes,
+        Err(message) => {
+            eprintln!("{message}");
+            std::process::exit(1);
+        }
+    };
+    if options.minotaur && archetypes.get(MINOTAUR).is_none() {
+        eprintln!(
+            "{}: --minotaur needs an archetype named '{MINOTAUR}'",
+            options.archetypes
+        );
+        std::process::exit(1);
+    }
+
+    let level = options.tmx.as_ref().map(|path| match load_tmx(path) {
+        Ok(level) => {
+            for warning in level
+                .warnings
+                .iter()
+                .chain(level.archetype_warnings(&archetypes).iter())
+            {
+                eprintln!("{path}: warning: {warning}");
+            }
+            level
+        }
+        Err(message) => {
+            eprintln!("{message}");
+            std::process::exit(1);
+        }
+    });
+
+    if options.validate {
+        let (map, _) = new_level(&options, &level, options.map_dimensions);
+        let issues = validate(&map);
+        for issue in issues.iter() {
+            println!("{issue}");
+        }
+        if !issues.is_empty() {
+            std::process::exit(1);
+        }
+        println!("No issues found");
+        return;
+    }
+
+    if options.is_headless() {
+        let game = new_game(&options, &level, &archetypes, 0.0, options.map_dimensions);
+        if options.metrics {
+            println!("{}", game.metrics);
+        }
+        if let Some(path) = &options.export_svg {
+            if let Err(e) = export_svg(&game, path, &options.export) {
+                eprintln!("Could not write {path}: {e}");
+                std::process::exit(1);
+            }
+        }
+        if let Some(path) = &options.export_png {
+            if let Err(e) = export_png(&game, path, &options.export) {
+                eprintln!("Could not write {path}: {e}");
+                std::process::exit(1);
+            }
+        }
+        if let Some(path) = &options.export_obj {
+            if let Err(e) = export_obj(&game.map, path) {
+                eprintln!("Could not write {path}: {e}");
+                std::process::exit(1);
+            }
+        }
+        if let Some(path) = &options.export_gltf {
+            if let Err(e) = export_gltf(&game.map, path) {
+                eprintln!("Could not write {path}: {e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     let (mut rl, thread) = raylib::init().size(SCREEN_W, SCREEN_H).title("3d maze").build();
 
-    let mut game = GameState::new(rl.get_time(), [5, 5]);
+    let mut game = new_game(&options, &level, &archetypes, rl.get_time(), options.map_dimensions);
 
     let player_position = game.player().position();
 
@@ -61,17 +247,39 @@ fn main() {
     }
     rl.set_target_fps(TARGET_FPS);
 
-    let texture = AssetPack::init(&mut rl, &thread);
+    let texture = match AssetPack::init(&mut rl, &thread, &archetypes) {
+        Ok(texture) => texture,
+        Err(message) => {
+            eprintln!("{message}");
+            std::process::exit(1);
+        }
+    };
 
     rl.set_mouse_scale(MOUSE_SENSITIVITY, MOUSE_SENSITIVITY);
     let mut input = InputController::new(&rl);
+    // levels finished so far, and when the scoreboard of the last one was shown
+    let mut results: Vec<LevelResult> = Vec::new();
+    let mut scoreboard_since: Option<f64> = None;
 
     while !rl.window_should_close() {
         game.clock = rl.get_time();
 
-        let (player_position, player_collision_radius, player_id) = {
+        if let Some(since) = scoreboard_since {
+            let skipped = rl.is_key_pressed(KeyboardKey::KEY_ENTER) || rl.is_key_pressed(KeyboardKey::KEY_SPACE);
+            if skipped || game.clock > since + SCOREBOARD_DURATION {
+                scoreboard_since = None;
+                game = new_game(&options, &level, &archetypes, game.clock, options.map_dimensions);
+                continue;
+            }
+            let mut d = rl.begin_drawing(&thread);
+            d.clear_background(Color::BLACK);
+            draw_scoreboard(&mut d, &results);
+            continue;
+        }
+
+        let (player_position, player_collision_radius) = {
             let player = game.player();
-            (player.position(), player.collision_radius(), player.id())
+            (player.position(), player.collision_radius())
         };
 
         let mut translation_velocity = Vector3::zero();
@@ -99,6 +307,9 @@ fn main() {
         }
         input.last_mouse_position = rl.get_mouse_position();
 
+        // doors open as the player walks into them with their key, before they are collided with as walls
+        game.open_doors(player_position + translation_velocity);
+
         // detect collision vs walls and adjust velocity accordingly
         let mut colliding = false;
         {
@@ -107,78 +318,37 @@ fn main() {
                 player_position.x + translation_velocity.x,
                 player_position.z + translation_velocity.z,
             );
-            let translated_bb = Rectangle::new(
-                translated_position.x - player_collision_radius,
-                translated_position.y - player_collision_radius,
-                player_collision_radius * 2.0,
-                player_collision_radius * 2.0,
-            );
-            // find all nearby walls
-            let mut walls = Vec::<Rectangle>::new();
-            for i in ((translated_bb.y) / TILE_SIZE) as i32
-                ..((translated_bb.y + translated_bb.height) / TILE_SIZE) as i32 + 1
-            {
-                for j in ((translated_bb.x) / TILE_SIZE) as i32
-                    ..((translated_bb.x + translated_bb.width) / TILE_SIZE) as i32 + 1
-                {
-                    if !game.map.contains([i, j]) {
-                        continue;
-                    }
-                    if game.map.get_item([i, j]) & WALL_EAST == WALL_EAST {
-                        walls.push(Rectangle::new(
-                            (j + 1) as f32 * TILE_SIZE,
-                            i as f32 * TILE_SIZE,
-                            0.001,
-                            TILE_SIZE,
-                        ));
-                    }
-                    if game.map.get_item([i, j]) & WALL_WEST == WALL_WEST {
-                        walls.push(Rectangle::new(
-                            j as f32 * TILE_SIZE,
-                            i as f32 * TILE_SIZE,
-                            0.001,
-                            TILE_SIZE,
-                        ));
-                    }
-                    if game.map.get_item([i, j]) & WALL_SOUTH == WALL_SOUTH {
-                        walls.push(Rectangle::new(
-                            j as f32 * TILE_SIZE,
-                            (i + 1) as f32 * TILE_SIZE,
-                            TILE_SIZE,
-                            0.001,
-                        ));
-                    }
-                    if game.map.get_item([i, j]) & WALL_NORTH == WALL_NORTH {
-                        walls.push(Rectangle::new(
-                            j as f32 * TILE_SIZE,
-                            i as f32 * TILE_SIZE,
-                            TILE_SIZE,
-                            0.001,
-                        ));
+            // find all walls of the tile the player is moving into and of its neighbours
+            let topology = game.map.topology();
+            let mut walls = Vec::<[Vector2; 2]>::new();
+            let point = translated_position / TILE_SIZE;
+            if let Some(tile) = game.map.tile(topology.tile_at(point)) {
+                let neighbours = topology
+                    .sides(tile)
+                    .iter()
+                    .filter_map(|&(side, _, _)| game.map.neighbour(tile, side));
+                for nearby in std::iter::once(tile).chain(neighbours) {
+                    // on maps that wrap around, the tile may be across the edge of the map
+                    let offset = game.map.seam_offset(point, nearby);
+                    // crossings are closed by their railings on the bridge and by the bridge ends in the tunnel
+                    let layer_walls = game.map.crossing_walls(nearby, game.on_bridge(nearby));
+                    for side in game.map.walls_around(nearby).chain(layer_walls) {
+                        walls.push(topology.wall_segment(nearby, side).map(|p| (p + offset) * TILE_SIZE));
                     }
                 }
             }
             // apply wall collisions from closest to player to farthest
-            let fdist = |a: &Rectangle| {
-                Vector2::new(
-                    a.x + a.width / 2.0 - player_position2d.x,
-                    a.y + a.height / 2.0 - player_position2d.y,
-                )
-                .length_sqr()
-            };
+            let fdist = |[a, b]: &[Vector2; 2]| ((*a + *b) / 2.0 - player_position2d).length_sqr();
             walls.sort_by(|a, b| (-fdist(a)).total_cmp(&-fdist(b)));
-            while let Some(wall_bb) = walls.pop() {
+            while let Some(wall) = walls.pop() {
                 let translation_velocity2d = Vector2::new(translation_velocity.x, translation_velocity.z);
                 let translated_position2d = player_position2d + translation_velocity2d;
                 if let Some(collision_point) =
-                    rec_circle_collision_point(&wall_bb, translated_position2d, player_collision_radius)
+                    segment_circle_collision_point(wall, translated_position2d, player_collision_radius)
                 {
                     colliding = true;
                     // find a vector to remove the player from the wall and add that to the velocity
-                    let closest_point = Vector2::new(
-                        player_position2d.x.clamp(wall_bb.x, wall_bb.x + wall_bb.width),
-                        player_position2d.y.clamp(wall_bb.y, wall_bb.y + wall_bb.height),
-                    );
+                    let closest_point = closest_point_on_segment(wall, player_position2d);
                     let collision_edge_normal = (player_position2d - closest_point).normalized();
                     let correction = collision_edge_normal
                         * (player_collision_radius - collision_edge_normal.dot(translated_position2d - collision_point));
@@ -195,75 +365,36 @@ fn main() {
         {
             game.game_start_event = None;
         }
-        if game.game_end_event.as_ref().is_some_and(|e| e.is_completed(game.clock)) {
-            game = GameState::new(game.clock, [8, 8]);
-            continue;
+        if game.warp_event.as_ref().is_some_and(|e| e.is_completed(game.clock)) {
+            game.warp_event = None;
         }
-        for e in game.roll_events.iter() {
-            if e.is_completed(game.clock) {
-                match e {
-                    GameEventType::Roll { entity_id, .. } => {
-                        game.entities.remove_by_id(*entity_id);
-                    }
-                    _ => {}
-                }
+        if game.game_end_event.as_ref().is_some_and(|e| e.is_completed(game.clock)) {
+            // a finished level shows the scoreboard before the next one, while a player who was caught starts over
+            if let Some(GameEventType::GameEnd { .. }) = game.game_end_event {
+                results.push(game.result());
+                scoreboard_since = Some(game.clock);
+            } else {
+                game = new_game(&options, &level, &archetypes, game.clock, options.map_dimensions);
             }
+            continue;
         }
+        game.expire_entities();
         if game.roll_events.iter().all(|e| e.is_completed(game.clock)) && game.roll_events.len() % 2 == 0 {
             game.roll_events.clear();
         }
 
-        // Collision vs other entities
-        {
-            let mut i = 0;
-            while i < game.entities.len() {
-                let player_position2d = Vector2::new(player_position.x, player_position.z);
-                let e = game.entities.get_mut_by_index(i);
-                let entity_position2d = Vector2::new(e.position().x, e.position().z);
-
-                let objects_colliding = e.collision_radius() > 0.0
-                    && check_collision_circles(
-                        entity_position2d,
-                        e.collision_radius(),
-                        player_position2d,
-                        player_collision_radius,
-                    )
-                    && e.id() != player_id;
-                colliding |= objects_colliding;
-                match e {
-                    Entity::End { .. } => {
-                        if objects_colliding && game.game_end_event.is_none() {
-                            game.game_end_event = Some(GameEventType::GameEnd {
-                                start_time: game.clock,
-                                duration: 1.0,
-                            });
-                        }
-                    }
-                    Entity::Dodecahedron { .. } => {
-                        // When colliding with a diamond create a new camera roll event
-                        if objects_colliding {
-                            if game.roll_events.iter().all(|ev| match ev {
-                                GameEventType::Roll { entity_id, .. } => *entity_id != e.id(),
-                                _ => false,
-                            }) {
-                                let new_event = GameEventType::Roll {
-                                    start_time: game.clock,
-                                    duration: 1.0,
-                                    entity_id: e.id(),
-                                };
-                                game.roll_events.push(new_event);
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-                i += 1;
-            }
-        }
+        // Collision vs other entities, which applies the effects of their triggers
+        colliding |= game.touch_entities();
 
         game.player_mut().move_position(translation_velocity);
-        camera.position = game.player().position();
-        camera.target = game.player().position() + Vector3::forward().transform_with(game.camera_rotation);
+        game.update_layer(player_position);
+        game.wrap_player();
+        game.shift_maze();
+        game.stream_world();
+        game.climb_stairs();
+        game.move_creatures(rl.get_frame_time());
+        camera.position = game.player().position() + Vector3::up() * game.eye_offset;
+        camera.target = camera.position + Vector3::forward().transform_with(game.camera_rotation);
         camera.up = Vector3::up()
             .transform_with(game.camera_rotation)
             .transform_with(Matrix::rotate(
@@ -284,6 +415,9 @@ fn main() {
                 let mut d3d = d.begin_mode3D(camera);
                 // draw tile map
                 game.map.draw(&game, &mut d3d, &camera, &texture);
+                if let Some(tower) = &game.tower {
+                    tower.draw(&game, &mut d3d, &camera, &texture);
+                }
 
                 // Draw entities z-ordered
                 game.entities.sort_drawables_by(|a, b| {
@@ -304,7 +438,19 @@ fn main() {
                 }
             }
 
+            // the screen flashes white as the player comes out of a teleporter
+            if let Some(e) = &game.warp_event {
+                let alpha = 1.0 - e.elapsed_normalized(game.clock) as f32;
+                d.draw_rectangle(0, 0, SCREEN_W, SCREEN_H, Color::WHITE.fade(alpha));
+            }
+            // the screen turns red while the walls sink after the player is caught
+            if let Some(e @ GameEventType::GameOver { .. }) = &game.game_end_event {
+                let alpha = e.elapsed_normalized(game.clock) as f32 * 0.6;
+                d.draw_rectangle(0, 0, SCREEN_W, SCREEN_H, Color::RED.fade(alpha));
+            }
             draw_debug_text(&mut d, &camera, &game, translation_velocity);
+            draw_inventory(&mut d, &game, Vector2::new(10.0, SCREEN_H as f32 - 40.0));
+            draw_hud(&mut d, &game);
             draw_xyz_indicator(
                 &mut d,
                 game.camera_rotation,
@@ -1,6 +1,8 @@
 use std::vec::Vec;
 use std::{array, fmt};
 
+use crate::topology::Topology;
+
 /// Converts a position from an N-dimensional array into a position from a 1-D array
 fn pos2i<const D: usize>(dimensions: &[usize; D], position: [usize; D]) -> usize {
     if (0..D).any(|i| position[i] >= dimensions[i]) {
@@ -15,16 +17,60 @@ fn pos2i<const D: usize>(dimensions: &[usize; D], position: [usize; D]) -> usize
     return index;
 }
 
-pub struct Map<const D: usize> {
-    tiles: Vec<i8>,
+/// N-dimensional grid of cells. By default every cell is an `i8` holding only its wall bits, but any type
+/// implementing `WallBits` can be used to attach more data to each cell
+#[derive(Clone)]
+pub struct Map<const D: usize, T = i8> {
+    tiles: Vec<T>,
     dimensions: [usize; D],
+    topology: Topology,
+    wraps: bool,
 }
 
-impl<const D: usize> Map<D> {
-    pub fn from(value: i8, dimensions: [usize; D]) -> Self {
+impl<const D: usize, T: Clone> Map<D, T> {
+    pub fn from(value: T, dimensions: [usize; D]) -> Self {
         let size: usize = dimensions.into_iter().fold(1, |a, e| a * e);
         let tiles = vec![value; size];
-        Map { tiles, dimensions }
+        Map {
+            tiles,
+            dimensions,
+            topology: Topology::Square,
+            wraps: false,
+        }
+    }
+
+    /// Sets the shape of the map's tiles. Only 2D maps can have tiles that aren't square
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// Connects the east edge of the map to the west one and the north edge to the south one, so that the map has no
+    /// border. Each side needs at least 3 tiles, and hexagonal maps need an even number of rows
+    pub fn with_wrapping(mut self, wraps: bool) -> Self {
+        self.wraps = wraps;
+        self
+    }
+}
+
+impl<const D: usize, T: WallBits> Map<D, T> {
+    /// Copy of the map with cells of another type, which get the same walls and default data
+    pub fn convert<U: WallBits>(&self) -> Map<D, U> {
+        let tiles = self
+            .tiles
+            .iter()
+            .map(|tile| {
+                let mut cell = U::default();
+                cell.set_walls(tile.walls());
+                cell
+            })
+            .collect();
+        Map {
+            tiles,
+            dimensions: self.dimensions,
+            topology: self.topology,
+            wraps: self.wraps,
+        }
     }
 }
 
@@ -32,101 +78,303 @@ pub const WALL_EAST: i8 = 1 << 0;
 pub const WALL_NORTH: i8 = 1 << 1;
 pub const WALL_WEST: i8 = 1 << 2;
 pub const WALL_SOUTH: i8 = 1 << 3;
+/// Ceiling and floor of a cell in maps with stacked floors (`Map<3>` indexed as [floor, row, col]). Like the other
+/// walls, a cleared bit is a passage: stairs going up or down
+pub const WALL_UP: i8 = 1 << 4;
+pub const WALL_DOWN: i8 = 1 << 5;
+/// Crossing of a weave maze (square tiles only). The sides of the tile without a wall are the ends of a bridge, and
+/// a tunnel runs under the bridge between the two sides with a wall. Those walls are the railings of the bridge, so
+/// they don't close the tunnel
+pub const TUNNEL: i8 = 1 << 6;
+
+/// Cell types that can be stored in a map. Generators, solvers and collisions only look at the wall bits
+pub trait WallBits: Copy + Default {
+    fn walls(&self) -> i8;
+    fn set_walls(&mut self, walls: i8);
+}
+
+impl WallBits for i8 {
+    fn walls(&self) -> i8 {
+        *self
+    }
+    fn set_walls(&mut self, walls: i8) {
+        *self = walls;
+    }
+}
 
-pub struct MapSlice<'a, const D: usize> {
-    map: &'a mut Map<D>,
+/// Mutable view of a box-shaped region of a map. Positions are relative to the slice's origin, and cells outside of
+/// the region are reported as not contained in the view, so generators working on it never touch them
+pub struct MapSlice<'a, const D: usize, T = i8> {
+    map: &'a mut Map<D, T>,
     origin: [usize; D],
     dimensions: [usize; D],
 }
 
-impl<'a, const D: usize> MapSlice<'a, D> {
-    pub fn from(map: &'a mut Map<D>, origin: [usize; D], dimensions: [usize; D]) -> Self {
+impl<'a, const D: usize, T> MapSlice<'a, D, T> {
+    /// Creates a view of the region starting at `origin`. The dimensions are clipped to the map's border
+    pub fn from(map: &'a mut Map<D, T>, origin: [usize; D], dimensions: [usize; D]) -> Self {
+        let dimensions = array::from_fn(|i| dimensions[i].min(map.dimensions[i].saturating_sub(origin[i])));
         Self {
             map: map,
             origin,
             dimensions,
         }
     }
+
+    /// Position of the slice's first cell in the underlying map
+    pub fn origin(&self) -> [usize; D] {
+        self.origin
+    }
+
+    /// Creates a view of a region of this slice. `origin` is relative to this slice, and the dimensions are clipped
+    /// to this slice's border
+    pub fn slice(&mut self, origin: [usize; D], dimensions: [usize; D]) -> MapSlice<'_, D, T> {
+        let dimensions = array::from_fn(|i| dimensions[i].min(self.dimensions[i].saturating_sub(origin[i])));
+        MapSlice {
+            map: self.map,
+            origin: array::from_fn(|i| self.origin[i] + origin[i]),
+            dimensions,
+        }
+    }
+
+    /// Iterates over the positions of the slice's cells, relative to its origin, in row-major order
+    pub fn positions(&self) -> impl Iterator<Item = [usize; D]> {
+        positions(self.dimensions)
+    }
+
+    /// Iterates over the slice's cells and their positions relative to its origin
+    pub fn iter(&self) -> impl Iterator<Item = ([usize; D], &T)> + '_ {
+        self.positions().map(|p| {
+            let i = pos2i(&self.map.dimensions, array::from_fn(|k| p[k] + self.origin[k]));
+            (p, &self.map.tiles[i])
+        })
+    }
+}
+
+impl<const D: usize, T> Map<D, T> {
+    /// Creates a mutable view of a region of the map, see `MapSlice`
+    pub fn slice(&mut self, origin: [usize; D], dimensions: [usize; D]) -> MapSlice<'_, D, T> {
+        MapSlice::from(self, origin, dimensions)
+    }
+}
+
+/// Iterates over every position of a box of the given dimensions in row-major order
+pub fn positions<const D: usize>(dimensions: [usize; D]) -> impl Iterator<Item = [usize; D]> {
+    let size: usize = dimensions.iter().product();
+    (0..size).map(move |mut index| {
+        let mut position = [0; D];
+        for i in (0..D).rev() {
+            position[i] = index % dimensions[i];
+            index /= dimensions[i];
+        }
+        position
+    })
 }
 
 pub trait RectangularMap<const D: usize> {
     fn dimensions(&self) -> [usize; D];
+    fn topology(&self) -> Topology {
+        Topology::Square
+    }
+    /// Whether the map wraps around, see `Map::with_wrapping`
+    fn wraps(&self) -> bool {
+        false
+    }
 }
 
-impl<const D: usize> RectangularMap<D> for Map<D> {
+impl<const D: usize, T> RectangularMap<D> for Map<D, T> {
     fn dimensions(&self) -> [usize; D] {
         self.dimensions
     }
+    fn topology(&self) -> Topology {
+        self.topology
+    }
+    fn wraps(&self) -> bool {
+        self.wraps
+    }
 }
 
-impl<'a, const D: usize> RectangularMap<D> for MapSlice<'a, D> {
+impl<'a, const D: usize, T> RectangularMap<D> for MapSlice<'a, D, T> {
     fn dimensions(&self) -> [usize; D] {
         self.dimensions
     }
+    fn topology(&self) -> Topology {
+        self.map.topology
+    }
 }
 
 pub trait GetSetMap<P: TryInto<usize> + Copy, const D: usize>
 where
     Self: RectangularMap<D>,
 {
+    type Cell: WallBits;
+
     fn contains(&self, position: [P; D]) -> bool;
-    fn get_item(&self, position: [P; D]) -> i8;
-    fn set_item(&mut self, position: [P; D], value: i8);
+    /// Returns the cell at the given position, or the default cell if the position is outside of the map
+    fn get_item(&self, position: [P; D]) -> Self::Cell;
+    fn set_item(&mut self, position: [P; D], value: Self::Cell);
+
+    fn get_walls(&self, position: [P; D]) -> i8 {
+        self.get_item(position).walls()
+    }
+    /// Replaces the wall bits of a cell, keeping the rest of its data
+    fn set_walls(&mut self, position: [P; D], walls: i8) {
+        let mut cell = self.get_item(position);
+        cell.set_walls(walls);
+        self.set_item(position, cell);
+    }
 }
 
-impl<P: TryInto<usize> + Copy, const D: usize> GetSetMap<P, D> for Map<D> {
+impl<P: TryInto<usize> + Copy, const D: usize, T: WallBits> GetSetMap<P, D> for Map<D, T> {
+    type Cell = T;
+
     fn contains(&self, position: [P; D]) -> bool {
         (0..D).all(|i| match position[i].try_into() {
             Ok(ui) => ui < self.dimensions[i],
             _ => false,
         })
     }
-    fn get_item(&self, position: [P; D]) -> i8 {
+    fn get_item(&self, position: [P; D]) -> T {
         if !self.contains(position) {
-            return 0;
+            return T::default();
         }
         let p = position.map(|v| v.try_into().unwrap_or(0));
-        *self.tiles.get(pos2i(&self.dimensions, p)).unwrap_or(&0)
+        self.tiles.get(pos2i(&self.dimensions, p)).copied().unwrap_or_default()
     }
-    fn set_item(&mut self, position: [P; D], value: i8) {
+    fn set_item(&mut self, position: [P; D], value: T) {
+        if !self.contains(position) {
+            return;
+        }
         let p = position.map(|v| v.try_into().unwrap_or(0));
         let i = pos2i(&self.dimensions, p);
-        if i < self.tiles.len() {
-            self.tiles[i] = value;
-        }
+        self.tiles[i] = value;
     }
 }
 
-impl<'a, P: TryInto<usize> + Copy, const D: usize> GetSetMap<P, D> for MapSlice<'a, D> {
+impl<'a, P: TryInto<usize> + Copy, const D: usize, T: WallBits> GetSetMap<P, D> for MapSlice<'a, D, T> {
+    type Cell = T;
+
     fn contains(&self, position: [P; D]) -> bool {
         (0..D).all(|i| match position[i].try_into() {
-            Ok(ui) => ui + self.origin[i] < self.dimensions[i],
+            Ok(ui) => ui < self.dimensions[i],
             _ => false,
         })
     }
-    fn get_item(&self, position: [P; D]) -> i8 {
+    fn get_item(&self, position: [P; D]) -> T {
         if !self.contains(position) {
-            return 0;
+            return T::default();
         }
         let p = array::from_fn(|i| position[i].try_into().unwrap_or(0) + self.origin[i]);
         self.map.get_item(p)
     }
 
-    fn set_item(&mut self, position: [P; D], value: i8) {
+    fn set_item(&mut self, position: [P; D], value: T) {
+        if !self.contains(position) {
+            return;
+        }
         let p = array::from_fn(|i| position[i].try_into().unwrap_or(0) + self.origin[i]);
-        let i = pos2i(&self.map.dimensions, p);
-        if i < self.map.tiles.len() {
-            self.map.tiles[i] = value;
+        self.map.set_item(p, value);
+    }
+}
+
+/// View of a map restricted to the cells set in a mask (indexed like the map's tiles). Cells outside of the mask are
+/// reported as not contained in the view, so generators working on it never carve them
+pub struct MaskedMap<'a, const D: usize, T = i8> {
+    map: &'a mut Map<D, T>,
+    mask: &'a [bool],
+}
+
+impl<'a, const D: usize, T> MaskedMap<'a, D, T> {
+    pub fn from(map: &'a mut Map<D, T>, mask: &'a [bool]) -> Self {
+        Self { map, mask }
+    }
+}
+
+impl<'a, const D: usize, T> RectangularMap<D> for MaskedMap<'a, D, T> {
+    fn dimensions(&self) -> [usize; D] {
+        self.map.dimensions
+    }
+    fn topology(&self) -> Topology {
+        self.map.topology
+    }
+    fn wraps(&self) -> bool {
+        self.map.wraps
+    }
+}
+
+impl<'a, P: TryInto<usize> + Copy, const D: usize, T: WallBits> GetSetMap<P, D> for MaskedMap<'a, D, T> {
+    type Cell = T;
+
+    fn contains(&self, position: [P; D]) -> bool {
+        if !self.map.contains(position) {
+            return false;
+        }
+        let p = position.map(|v| v.try_into().unwrap_or(0));
+        *self.mask.get(pos2i(&self.map.dimensions, p)).unwrap_or(&false)
+    }
+    fn get_item(&self, position: [P; D]) -> T {
+        if !self.contains(position) {
+            return T::default();
+        }
+        self.map.get_item(position)
+    }
+    fn set_item(&mut self, position: [P; D], value: T) {
+        if self.contains(position) {
+            self.map.set_item(position, value);
+        }
+    }
+}
+
+impl<T: WallBits> Map<2, T> {
+    /// Gives all their walls to the cells flagged as solid (indexed like the map's tiles), and puts walls between
+    /// the other cells and their solid neighbours or the map border
+    pub fn wall_off_solid_cells(&mut self, solid: &[bool]) {
+        let all_walls = WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH;
+        let [rows, cols] = self.dimensions;
+        let is_solid = |i: i32, j: i32| solid[i as usize * cols + j as usize];
+        for i in 0..rows as i32 {
+            for j in 0..cols as i32 {
+                if is_solid(i, j) {
+                    self.set_walls([i, j], all_walls);
+                    continue;
+                }
+                for (wall, [di, dj]) in
+                    [(WALL_EAST, [0, 1]), (WALL_WEST, [0, -1]), (WALL_NORTH, [-1, 0]), (WALL_SOUTH, [1, 0])]
+                {
+                    if !self.contains([i + di, j + dj]) || is_solid(i + di, j + dj) {
+                        self.set_walls([i, j], self.get_walls([i, j]) | wall);
+                    }
+                }
+            }
         }
     }
 }
 
-impl fmt::Display for Map<2> {
+impl<T: WallBits> Map<3, T> {
+    /// Copies one of the floors of the map. The cells keep their up and down bits
+    pub fn floor(&self, index: usize) -> Map<2, T> {
+        let [_, rows, cols] = self.dimensions;
+        let start = (index * rows * cols).min(self.tiles.len());
+        let end = (start + rows * cols).min(self.tiles.len());
+        Map {
+            tiles: self.tiles[start..end].to_vec(),
+            dimensions: if end - start == rows * cols {
+                [rows, cols]
+            } else {
+                [0, 0]
+            },
+            topology: self.topology,
+            wraps: self.wraps,
+        }
+    }
+}
+
+impl<T: WallBits> fmt::Display for Map<2, T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for i in 0..self.dimensions[0] {
             for vertical_edge in [WALL_NORTH, WALL_SOUTH] {
                 for j in 0..self.dimensions[1] {
-                    let v = self.get_item([i, j]);
+                    let v = self.get_walls([i, j]);
                     for horizontal_edge in [WALL_WEST, WALL_EAST] {
                         let flags = v & (vertical_edge | horizontal_edge);
                         write!(
@@ -161,3 +409,165 @@ impl fmt::Display for Map<2> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dfs::random_dfs;
+    use crate::solver::reachable_tiles;
+
+    const ALL_WALLS: i8 = WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH;
+
+    #[test]
+    fn offset_slice_contains_its_own_cells() {
+        let mut map = Map::<2>::from(0, [6, 6]);
+        let slice = map.slice([2, 3], [3, 2]);
+        assert_eq!(slice.dimensions(), [3, 2]);
+        assert!(slice.contains([0, 0]));
+        assert!(slice.contains([2, 1]));
+        assert!(!slice.contains([3, 0]));
+        assert!(!slice.contains([0, 2]));
+        assert!(!slice.contains([-1, 0]));
+    }
+
+    #[test]
+    fn slice_is_clipped_to_the_map() {
+        let mut map = Map::<2>::from(0, [4, 4]);
+        assert_eq!(map.slice([3, 1], [5, 2]).dimensions(), [1, 2]);
+        assert_eq!(map.slice([6, 0], [2, 2]).dimensions(), [0, 2]);
+    }
+
+    #[test]
+    fn slice_reads_and_writes_through_its_origin() {
+        let mut map = Map::<2>::from(0, [4, 4]);
+        let mut slice = map.slice([1, 2], [2, 2]);
+        slice.set_item([1, 1], WALL_EAST);
+        slice.set_item([2, 0], WALL_WEST);
+        slice.set_item([0, -1], WALL_NORTH);
+        assert_eq!(slice.get_item([1, 1]), WALL_EAST);
+        assert_eq!(slice.get_item([2, 0]), 0);
+        assert_eq!(map.get_item([2, 3]), WALL_EAST);
+        assert_eq!(map.tiles.iter().filter(|&&v| v != 0).count(), 1);
+    }
+
+    #[test]
+    fn map_ignores_writes_outside_of_it() {
+        let mut map = Map::<2>::from(0, [3, 3]);
+        map.set_item([-1, 0], WALL_SOUTH);
+        map.set_item([0, 3], WALL_SOUTH);
+        assert!(map.tiles.iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn nested_slices_compose_origins() {
+        let mut map = Map::<2>::from(0, [8, 8]);
+        let mut outer = map.slice([1, 2], [5, 5]);
+        let mut inner = outer.slice([2, 1], [10, 2]);
+        assert_eq!(inner.origin(), [3, 3]);
+        assert_eq!(inner.dimensions(), [3, 2]);
+        inner.set_item([0, 0], WALL_NORTH);
+        assert_eq!(outer.get_item([2, 1]), WALL_NORTH);
+        assert_eq!(map.get_item([3, 3]), WALL_NORTH);
+    }
+
+    #[test]
+    fn slice_iterates_in_row_major_order() {
+        let mut map = Map::<2>::from(0, [4, 4]);
+        map.set_item([2, 2], WALL_WEST);
+        let slice = map.slice([1, 1], [2, 3]);
+        let positions: Vec<_> = slice.positions().collect();
+        assert_eq!(positions, vec![[0, 0], [0, 1], [0, 2], [1, 0], [1, 1], [1, 2]]);
+        let walls: Vec<_> = slice.iter().filter(|(_, &v)| v != 0).map(|(p, _)| p).collect();
+        assert_eq!(walls, vec![[1, 1]]);
+    }
+
+    #[test]
+    fn generator_on_offset_slice_stays_inside() {
+        for _ in 0..20 {
+            let mut map = Map::<2>::from(ALL_WALLS, [8, 9]);
+            let (origin, dimensions) = ([2, 3], [4, 5]);
+            random_dfs(&mut map.slice(origin, dimensions), [0, 0]);
+
+            let inside = |[i, j]: [usize; 2]| {
+                (origin[0]..origin[0] + dimensions[0]).contains(&i)
+                    && (origin[1]..origin[1] + dimensions[1]).contains(&j)
+            };
+            for p in positions(map.dimensions()) {
+                if !inside(p) {
+                    assert_eq!(map.get_item(p), ALL_WALLS, "cell {p:?} outside of the slice was carved");
+                }
+            }
+            let mut region = reachable_tiles(&map, origin);
+            region.sort();
+            let mut expected: Vec<_> = positions(map.dimensions()).filter(|&p| inside(p)).collect();
+            expected.sort();
+            assert_eq!(region, expected);
+        }
+    }
+
+    /// Cell with data besides its walls, which generators must leave alone
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    struct Labelled {
+        walls: i8,
+        label: u16,
+    }
+
+    impl WallBits for Labelled {
+        fn walls(&self) -> i8 {
+            self.walls
+        }
+        fn set_walls(&mut self, walls: i8) {
+            self.walls = walls;
+        }
+    }
+
+    #[test]
+    fn cells_keep_their_data_through_views_and_generators() {
+        let mut map = Map::<2, Labelled>::from(
+            Labelled {
+                walls: ALL_WALLS,
+                label: 0,
+            },
+            [6, 7],
+        );
+        for (index, p) in positions(map.dimensions()).enumerate() {
+            map.set_item(
+                p,
+                Labelled {
+                    walls: ALL_WALLS,
+                    label: index as u16,
+                },
+            );
+        }
+        map.set_walls([0, 0], WALL_NORTH);
+        assert_eq!(
+            map.get_item([0, 0]),
+            Labelled {
+                walls: WALL_NORTH,
+                label: 0
+            }
+        );
+        assert_eq!(map.get_item([6, 0]), Labelled::default());
+
+        let (origin, dimensions) = ([1, 2], [4, 4]);
+        let mut slice = map.slice(origin, dimensions);
+        assert_eq!(slice.get_item([0, 0]).label, 9);
+        slice.set_item(
+            [3, 3],
+            Labelled {
+                walls: ALL_WALLS,
+                label: 1000,
+            },
+        );
+        assert_eq!(slice.get_walls([3, 3]), ALL_WALLS);
+        random_dfs(&mut slice, [0, 0]);
+
+        assert_eq!(map.get_item([4, 5]).label, 1000);
+        for (index, p) in positions(map.dimensions()).enumerate().filter(|&(_, p)| p != [4, 5]) {
+            assert_eq!(map.get_item(p).label, index as u16);
+        }
+        let region = reachable_tiles(&map, origin);
+        assert_eq!(region.len(), dimensions[0] * dimensions[1]);
+        assert!(region.iter().all(|&[i, j]| i >= origin[0] && j >= origin[1]));
+    }
+}
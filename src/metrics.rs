@@ -0,0 +1,222 @@
+use std::fmt;
+
+use crate::grid::Grid;
+use crate::map::{Map, WallBits};
+use crate::solver::shortest_path_with_links;
+
+/// Statistics about the layout of a maze and how hard it is to solve
+pub struct MazeMetrics {
+    pub walkable_tiles: usize,
+    /// Tiles with a single way in or out
+    pub dead_ends: usize,
+    /// Tiles with three or more ways in or out
+    pub junctions: usize,
+    /// Number of corridors of each length. Corridors are runs of tiles with exactly two exits, and
+    /// `corridor_lengths[n]` counts the corridors that are `n` tiles long
+    pub corridor_lengths: Vec<usize>,
+    /// Number of tiles in the shortest path from start to end, including both. None if the end can't be reached
+    pub solution_length: Option<usize>,
+    /// Share of the walkable tiles that are on the solution path, between 0 and 1
+    pub solution_coverage: f32,
+    /// Junctions on the solution path, where the player has to pick the right way
+    pub solution_decisions: usize,
+    /// Combined difficulty score between 0 (trivial) and 100
+    pub difficulty: f32,
+}
+
+impl MazeMetrics {
+    pub fn corridor_count(&self) -> usize {
+        self.corridor_lengths.iter().sum()
+    }
+
+    pub fn mean_corridor_length(&self) -> f32 {
+        let total: usize = self
+            .corridor_lengths
+            .iter()
+            .enumerate()
+            .map(|(length, count)| length * count)
+            .sum();
+        total as f32 / self.corridor_count().max(1) as f32
+    }
+}
+
+impl fmt::Display for MazeMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Walkable tiles: {}", self.walkable_tiles)?;
+        writeln!(f, "Dead ends: {}", self.dead_ends)?;
+        writeln!(f, "Junctions: {}", self.junctions)?;
+        writeln!(
+            f,
+            "Corridors: {} (mean length {:.2})",
+            self.corridor_count(),
+            self.mean_corridor_length()
+        )?;
+        match self.solution_length {
+            Some(length) => writeln!(
+                f,
+                "Solution: {} tiles, {:.1}% of the maze, {} decisions",
+                length,
+                self.solution_coverage * 100.0,
+                self.solution_decisions
+            )?,
+            None => writeln!(f, "Solution: none")?,
+        }
+        write!(f, "Difficulty: {:.1}", self.difficulty)
+    }
+}
+
+/// Measures the maze for a player going from `start` to `end`. The two tiles of each link, such as the pads of a
+/// teleporter, are one step apart on the solution.
+///
+/// The difficulty score mixes three measures, each mapped to 0..1:
+/// - decisions: junctions met along the solution (weight 0.5)
+/// - tortuosity: how much longer the solution is than the straight distance between start and end (weight 0.3)
+/// - dead ends: the share of walkable tiles that are dead ends (weight 0.2)
+pub fn analyze<T: WallBits>(
+    map: &Map<2, T>,
+    start: [usize; 2],
+    end: [usize; 2],
+    links: &[[[usize; 2]; 2]],
+) -> MazeMetrics {
+    let graph = map.graph();
+    let mut degree = vec![0; graph.capacity()];
+    let mut walkable_tiles = 0;
+    let mut dead_ends = 0;
+    let mut junctions = 0;
+    for node in graph.nodes() {
+        walkable_tiles += 1;
+        let exits = graph.degree(node);
+        degree[graph.index(node)] = exits;
+        match exits {
+            1 => dead_ends += 1,
+            3.. => junctions += 1,
+            _ => {}
+        }
+    }
+
+    // walk along every run of corridor tiles, starting from any of its tiles and extending both ways
+    let mut corridor_lengths = Vec::new();
+    let mut visited = vec![false; graph.capacity()];
+    for node in graph.nodes() {
+        let index = graph.index(node);
+        if degree[index] != 2 || visited[index] {
+            continue;
+        }
+        visited[index] = true;
+        let mut length = 1;
+        let mut stack = vec![node];
+        while let Some(current) = stack.pop() {
+            for neighbour in graph.neighbours(current) {
+                let index = graph.index(neighbour);
+                if degree[index] == 2 && !visited[index] {
+                    visited[index] = true;
+                    length += 1;
+                    stack.push(neighbour);
+                }
+            }
+        }
+        if corridor_lengths.len() <= length {
+            corridor_lengths.resize(length + 1, 0);
+        }
+        corridor_lengths[length] += 1;
+    }
+
+    let solution = shortest_path_with_links(map, start, end, links);
+    let solution_length = solution.as_ref().map(|path| path.len());
+    let solution_decisions = solution.as_ref().map_or(0, |path| {
+        // the end tile is not a decision, the player has already arrived
+        path[..path.len() - 1]
+            .iter()
+            .filter(|&&node| degree[graph.index(node)] >= 3)
+            .count()
+    });
+    let solution_coverage = solution_length.unwrap_or(0) as f32 / walkable_tiles.max(1) as f32;
+
+    let distance = start[0].abs_diff(end[0]) + start[1].abs_diff(end[1]) + 1;
+    let tortuosity = solution_length.map_or(1.0, |length| length as f32 / distance as f32);
+    let dead_end_ratio = dead_ends as f32 / walkable_tiles.max(1) as f32;
+    let difficulty = if solution_length.is_some() {
+        100.0
+            * (0.5 * (1.0 - (-(solution_decisions as f32) / 10.0).exp())
+                + 0.3 * (1.0 - (-(tortuosity - 1.0) / 3.0).exp())
+                + 0.2 * (dead_end_ratio * 4.0).min(1.0))
+    } else {
+        0.0
+    };
+
+    MazeMetrics {
+        walkable_tiles,
+        dead_ends,
+        junctions,
+        corridor_lengths,
+        solution_length,
+        solution_coverage,
+        solution_decisions,
+        difficulty,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{GetSetMap, RectangularMap};
+
+    /// A 3x3 maze with every wall closed except between the given pairs of tiles
+    fn maze(passages: &[[[usize; 2]; 2]]) -> Map<2> {
+        let mut map = Map::<2>::from(0, [3, 3]);
+        let all_walls = map.topology().all_walls();
+        for tile in map.cells().collect::<Vec<_>>() {
+            map.set_walls(tile, all_walls);
+        }
+        for &[a, b] in passages {
+            let topology = map.topology();
+            let sides = topology.sides(a);
+            let &(side, opposite, _) = sides
+                .iter()
+                .find(|&&(side, _, _)| map.neighbour(a, side) == Some(b))
+                .unwrap();
+            map.set_walls(a, map.get_walls(a) & !side);
+            map.set_walls(b, map.get_walls(b) & !opposite);
+        }
+        map
+    }
+
+    #[test]
+    fn hand_built_maze_is_measured() {
+        // a junction at the top middle, with corridors of 2, 1 and 2 tiles leading to the bottom row
+        let map = maze(&[
+            [[0, 0], [1, 0]],
+            [[1, 0], [2, 0]],
+            [[0, 0], [0, 1]],
+            [[0, 1], [1, 1]],
+            [[1, 1], [2, 1]],
+            [[0, 1], [0, 2]],
+            [[0, 2], [1, 2]],
+            [[1, 2], [2, 2]],
+        ]);
+        let metrics = analyze(&map, [2, 0], [2, 2], &[]);
+        assert_eq!(metrics.walkable_tiles, 9);
+        assert_eq!(metrics.dead_ends, 3);
+        assert_eq!(metrics.junctions, 1);
+        assert_eq!(metrics.corridor_lengths, [0, 1, 2]);
+        assert_eq!(metrics.corridor_count(), 3);
+        assert_eq!(metrics.solution_length, Some(7));
+        assert_eq!(metrics.solution_decisions, 1);
+        assert!((metrics.solution_coverage - 7.0 / 9.0).abs() < 1e-6);
+        assert!((metrics.difficulty - 35.52).abs() < 0.01, "{}", metrics.difficulty);
+
+        // a teleporter between the two ends makes the solution a single step
+        let metrics = analyze(&map, [2, 0], [2, 2], &[[[2, 0], [2, 2]]]);
+        assert_eq!(metrics.solution_length, Some(2));
+        assert_eq!(metrics.solution_decisions, 0);
+    }
+
+    #[test]
+    fn unreachable_end_has_no_solution() {
+        let map = maze(&[[[0, 0], [0, 1]], [[0, 1], [0, 2]]]);
+        let metrics = analyze(&map, [0, 0], [2, 2], &[]);
+        assert_eq!(metrics.solution_length, None);
+        assert_eq!(metrics.difficulty, 0.0);
+        assert_eq!(metrics.walkable_tiles, 3);
+    }
+}
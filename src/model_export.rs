@@ -0,0 +1,403 @@
+use raylib::prelude::*;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use crate::base64;
+use crate::constants::TILE_SIZE;
+use crate::grid::Grid;
+use crate::map::{Map, RectangularMap, WallBits};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Surface {
+    Floor,
+    Ceiling,
+    Wall,
+}
+
+impl Surface {
+    pub const ALL: [Surface; 3] = [Surface::Floor, Surface::Ceiling, Surface::Wall];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Surface::Floor => "floor",
+            Surface::Ceiling => "ceiling",
+            Surface::Wall => "wall",
+        }
+    }
+
+    /// Texture used for the surface in game
+    pub fn texture_path(&self) -> &'static str {
+        match self {
+            Surface::Floor => "assets/3dmaze/floor.png",
+            Surface::Ceiling => "assets/3dmaze/ceiling.png",
+            Surface::Wall => "assets/3dmaze/wall.png",
+        }
+    }
+}
+
+/// Triangle mesh of the whole maze. Every quad has its own 4 vertices so that normals and UVs are not shared.
+/// UVs use a top-left origin and span each tile (and each wall face) exactly once, like the in-game textures
+pub struct MazeMesh {
+    pub positions: Vec<Vector3>,
+    pub normals: Vec<Vector3>,
+    pub uvs: Vec<Vector2>,
+    /// Triangle indices for each surface, in the same order as `Surface::ALL`
+    pub indices: [Vec<u32>; 3],
+}
+
+impl MazeMesh {
+    /// Adds a quad given its corners in counter-clockwise order as seen from the front face
+    fn add_quad(&mut self, surface: Surface, corners: [Vector3; 4], normal: Vector3, uvs: [Vector2; 4]) {
+        let first = self.positions.len() as u32;
+        self.positions.extend(corners);
+        self.normals.extend([normal; 4]);
+        self.uvs.extend(uvs);
+        let part = Surface::ALL.iter().position(|&s| s == surface).unwrap();
+        self.indices[part].extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+
+    /// Adds a convex polygon given its corners in counter-clockwise order as seen from the front face
+    fn add_polygon(&mut self, surface: Surface, corners: &[Vector3], normal: Vector3, uvs: &[Vector2]) {
+        let first = self.positions.len() as u32;
+        self.positions.extend(corners);
+        self.normals.extend(vec![normal; corners.len()]);
+        self.uvs.extend(uvs);
+        let part = Surface::ALL.iter().position(|&s| s == surface).unwrap();
+        for k in 1..corners.len() as u32 - 1 {
+            self.indices[part].extend([first, first + k, first + k + 1]);
+        }
+    }
+
+    /// Adds a wall face standing on the floor along the segment between `a` and `b`, facing `center`.
+    /// Points are measured in tiles like in `Topology`
+    fn add_wall(&mut self, [a, b]: [Vector2; 2], center: Vector2) {
+        let direction = b - a;
+        let mut normal = Vector2::new(-direction.y, direction.x).normalized();
+        if normal.dot(center - a) < 0.0 {
+            normal = -normal;
+        }
+        // adding zero turns negative zeros into positive ones
+        let normal = Vector3::new(normal.x + 0.0, 0.0, normal.y + 0.0);
+        let middle = (a + b) / 2.0 * TILE_SIZE;
+        let base_center = Vector3::new(middle.x, 0.0, middle.y);
+        let right = (-normal).cross(Vector3::up()) * (direction.length() * TILE_SIZE / 2.0);
+        let up = Vector3::up() * TILE_SIZE;
+        let bottom_left = base_center - right;
+        let bottom_right = base_center + right;
+        self.add_quad(
+            Surface::Wall,
+            [bottom_left, bottom_right, bottom_right + up, bottom_left + up],
+            normal,
+            [Vector2::new(0.0, 1.0), Vector2::new(1.0, 1.0), Vector2::new(1.0, 0.0), Vector2::new(0.0, 0.0)],
+        );
+    }
+
+    /// Builds the floor, ceiling and wall geometry of the map as laid out in game.
+    /// Faces that can't be seen from a walkable tile are skipped
+    pub fn from_map<T: WallBits>(map: &Map<2, T>) -> Self {
+        let mut mesh = Self {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: [Vec::new(), Vec::new(), Vec::new()],
+        };
+        let topology = map.topology();
+        for tile in map.graph().nodes() {
+            let center = topology.center(tile);
+            // corners are counter-clockwise with the north up, which is clockwise when seen from above. Start from
+            // the second one so that square tiles begin at their north-west corner
+            let mut corners = topology.corners(tile);
+            corners.rotate_left(1);
+            let uvs: Vec<Vector2> = corners.iter().map(|&c| c - center + Vector2::one() * 0.5).collect();
+            let points = |y: f32| -> Vec<Vector3> {
+                corners
+                    .iter()
+                    .map(|c| Vector3::new(c.x * TILE_SIZE, y, c.y * TILE_SIZE))
+                    .collect()
+            };
+            mesh.add_polygon(Surface::Floor, &points(0.0), Vector3::up(), &uvs);
+
+            let mut ceiling = points(TILE_SIZE);
+            let mut ceiling_uvs = uvs.clone();
+            ceiling[1..].reverse();
+            ceiling_uvs[1..].reverse();
+            mesh.add_polygon(Surface::Ceiling, &ceiling, Vector3::new(0.0, -1.0, 0.0), &ceiling_uvs);
+
+            // a wall is present if either of the tiles sharing it says so, just like in wall collisions
+            for side in map.walls_around(tile) {
+                mesh.add_wall(topology.wall_segment(tile, side), center);
+            }
+        }
+        mesh
+    }
+}
+
+/// Copies the textures next to the exported file as `<stem>_<surface>.png` and returns their file names
+fn copy_textures(path: &Path) -> io::Result<[String; 3]> {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("maze");
+    let directory = path.parent().map(PathBuf::from).unwrap_or_default();
+    let mut names = [String::new(), String::new(), String::new()];
+    for (name, surface) in names.iter_mut().zip(Surface::ALL) {
+        *name = format!("{stem}_{}.png", surface.name());
+        fs::copy(surface.texture_path(), directory.join(name.as_str()))?;
+    }
+    Ok(names)
+}
+
+/// Writes the maze as a Wavefront OBJ file, along with its material library (`.mtl`) and textures
+pub fn export_obj<T: WallBits>(map: &Map<2, T>, path: &str) -> io::Result<()> {
+    let path = Path::new(path);
+    let mesh = MazeMesh::from_map(map);
+    let textures = copy_textures(path)?;
+    let mtl_path = path.with_extension("mtl");
+
+    let mut mtl = String::new();
+    for (surface, texture) in Surface::ALL.iter().zip(&textures) {
+        writeln!(
+            mtl,
+            "newmtl {}\nKa 1 1 1\nKd 1 1 1\nKs 0 0 0\nillum 1\nmap_Kd {texture}\n",
+            surface.name()
+        )
+        .unwrap();
+    }
+    fs::write(&mtl_path, mtl)?;
+
+    let mut obj = String::new();
+    writeln!(obj, "# maze3d").unwrap();
+    writeln!(obj, "mtllib {}", mtl_path.file_name().unwrap().to_string_lossy()).unwrap();
+    writeln!(obj, "o maze").unwrap();
+    for v in &mesh.positions {
+        writeln!(obj, "v {} {} {}", v.x, v.y, v.z).unwrap();
+    }
+    // OBJ texture coordinates have their origin at the bottom-left corner
+    for vt in &mesh.uvs {
+        writeln!(obj, "vt {} {}", vt.x, 1.0 - vt.y).unwrap();
+    }
+    for vn in &mesh.normals {
+        writeln!(obj, "vn {} {} {}", vn.x, vn.y, vn.z).unwrap();
+    }
+    for (surface, indices) in Surface::ALL.iter().zip(&mesh.indices) {
+        writeln!(obj, "usemtl {}", surface.name()).unwrap();
+        for triangle in indices.chunks(3) {
+            let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+            writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}").unwrap();
+        }
+    }
+    fs::write(path, obj)
+}
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// Part of the glTF binary buffer that holds one of the arrays of the mesh
+struct BufferView {
+    offset: usize,
+    length: usize,
+    target: u32,
+}
+
+/// Lays out the mesh in a glTF binary buffer: positions, normals, uvs, then the indices of every surface. Every array
+/// holds 4 byte values, so the views are aligned as glTF requires. glTF doesn't allow empty views, so surfaces without
+/// triangles get none. Returns the buffer, its views and the view of the indices of each surface
+fn gltf_buffer(mesh: &MazeMesh) -> (Vec<u8>, Vec<BufferView>, [Option<usize>; 3]) {
+    let mut buffer = Vec::<u8>::new();
+    let mut views = Vec::<BufferView>::new();
+    let mut push_view = |buffer: &mut Vec<u8>, bytes: Vec<u8>, target: u32| {
+        views.push(BufferView {
+            offset: buffer.len(),
+            length: bytes.len(),
+            target,
+        });
+        buffer.extend(bytes);
+        views.len() - 1
+    };
+    let vec3_bytes = |v: &Vec<Vector3>| {
+        v.iter()
+            .flat_map(|v| [v.x, v.y, v.z])
+            .flat_map(f32::to_le_bytes)
+            .collect()
+    };
+    push_view(&mut buffer, vec3_bytes(&mesh.positions), ARRAY_BUFFER);
+    push_view(&mut buffer, vec3_bytes(&mesh.normals), ARRAY_BUFFER);
+    let uvs = mesh
+        .uvs
+        .iter()
+        .flat_map(|v| [v.x, v.y])
+        .flat_map(f32::to_le_bytes)
+        .collect();
+    push_view(&mut buffer, uvs, ARRAY_BUFFER);
+    let mut index_views = [None; 3];
+    for (view, indices) in index_views.iter_mut().zip(&mesh.indices) {
+        if !indices.is_empty() {
+            let bytes = indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+            *view = Some(push_view(&mut buffer, bytes, ELEMENT_ARRAY_BUFFER));
+        }
+    }
+    (buffer, views, index_views)
+}
+
+/// Writes the maze as a glTF 2.0 file. The geometry is embedded in the file and the textures are copied next to it
+pub fn export_gltf<T: WallBits>(map: &Map<2, T>, path: &str) -> io::Result<()> {
+    let path = Path::new(path);
+    let mesh = MazeMesh::from_map(map);
+    if mesh.positions.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the maze has no walkable tiles",
+        ));
+    }
+    let textures = copy_textures(path)?;
+    let (buffer, views, index_views) = gltf_buffer(&mesh);
+
+    let (min, max) = mesh.positions.iter().fold(
+        (Vector3::one() * f32::MAX, Vector3::one() * f32::MIN),
+        |(min, max), v| (min.min(*v), max.max(*v)),
+    );
+    let vertex_count = mesh.positions.len();
+
+    let mut accessors = vec![
+        format!(
+            r#"{{"bufferView":0,"componentType":5126,"count":{vertex_count},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+            min.x, min.y, min.z, max.x, max.y, max.z
+        ),
+        format!(r#"{{"bufferView":1,"componentType":5126,"count":{vertex_count},"type":"VEC3"}}"#),
+        format!(r#"{{"bufferView":2,"componentType":5126,"count":{vertex_count},"type":"VEC2"}}"#),
+    ];
+    let mut primitives = Vec::new();
+    for (i, (indices, view)) in mesh.indices.iter().zip(index_views).enumerate() {
+        let Some(view) = view else {
+            continue;
+        };
+        primitives.push(format!(
+            r#"{{"attributes":{{"POSITION":0,"NORMAL":1,"TEXCOORD_0":2}},"indices":{},"material":{i}}}"#,
+            accessors.len()
+        ));
+        accessors.push(format!(
+            r#"{{"bufferView":{view},"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+            indices.len()
+        ));
+    }
+    let buffer_views: Vec<String> = views
+        .iter()
+        .map(|view| {
+            format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+                view.offset, view.length, view.target
+            )
+        })
+        .collect();
+    let materials: Vec<String> = Surface::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, surface)| {
+            format!(
+                r#"{{"name":"{}","pbrMetallicRoughness":{{"baseColorTexture":{{"index":{i}}},"metallicFactor":0,"roughnessFactor":1}}}}"#,
+                surface.name()
+            )
+        })
+        .collect();
+    let images: Vec<String> = textures.iter().map(|t| format!(r#"{{"uri":"{t}"}}"#)).collect();
+    let gltf_textures: Vec<String> = (0..textures.len())
+        .map(|i| format!(r#"{{"sampler":0,"source":{i}}}"#))
+        .collect();
+
+    let mut json = String::new();
+    writeln!(json, "{{").unwrap();
+    writeln!(json, r#""asset":{{"version":"2.0","generator":"maze3d"}},"#).unwrap();
+    writeln!(json, r#""scene":0,"scenes":[{{"nodes":[0]}}],"#).unwrap();
+    writeln!(json, r#""nodes":[{{"name":"maze","mesh":0}}],"#).unwrap();
+    writeln!(
+        json,
+        r#""meshes":[{{"name":"maze","primitives":[{}]}}],"#,
+        primitives.join(",")
+    )
+    .unwrap();
+    writeln!(json, r#""materials":[{}],"#, materials.join(",")).unwrap();
+    writeln!(json, r#""textures":[{}],"#, gltf_textures.join(",")).unwrap();
+    writeln!(json, r#""images":[{}],"#, images.join(",")).unwrap();
+    writeln!(
+        json,
+        r#""samplers":[{{"magFilter":9729,"minFilter":9987,"wrapS":10497,"wrapT":10497}}],"#
+    )
+    .unwrap();
+    writeln!(json, r#""accessors":[{}],"#, accessors.join(",")).unwrap();
+    writeln!(json, r#""bufferViews":[{}],"#, buffer_views.join(",")).unwrap();
+    writeln!(
+        json,
+        r#""buffers":[{{"byteLength":{},"uri":"data:application/octet-stream;base64,{}"}}]"#,
+        buffer.len(),
+        base64::encode(&buffer)
+    )
+    .unwrap();
+    writeln!(json, "}}").unwrap();
+    fs::write(path, json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dfs::random_maze;
+    use crate::map::{GetSetMap, WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST};
+
+    /// A directory of its own for the files written by a test
+    fn output_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("maze3d-{name}-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn obj_has_two_triangles_for_each_face() {
+        // two tiles with a passage between them: 2 floors, 2 ceilings and 6 walls
+        let mut map = Map::<2>::from(WALL_NORTH | WALL_SOUTH, [1, 2]);
+        map.set_walls([0, 0], WALL_NORTH | WALL_SOUTH | WALL_WEST);
+        map.set_walls([0, 1], WALL_NORTH | WALL_SOUTH | WALL_EAST);
+        let mesh = MazeMesh::from_map(&map);
+        assert_eq!(mesh.indices.each_ref().map(|indices| indices.len() / 3), [4, 4, 12]);
+        assert_eq!(mesh.positions.len(), 10 * 4);
+
+        let path = output_dir("obj").join("maze.obj");
+        export_obj(&map, path.to_str().unwrap()).unwrap();
+        let obj = fs::read_to_string(&path).unwrap();
+        assert_eq!(obj.lines().filter(|line| line.starts_with("f ")).count(), 20);
+        assert_eq!(obj.lines().filter(|line| line.starts_with("v ")).count(), 40);
+        assert!(path.with_extension("mtl").exists());
+    }
+
+    #[test]
+    fn gltf_views_cover_the_buffer() {
+        for map in [random_maze([4, 5]), Map::<2>::from(0, [2, 2]).with_wrapping(true)] {
+            let mesh = MazeMesh::from_map(&map);
+            let (buffer, views, index_views) = gltf_buffer(&mesh);
+            let mut end = 0;
+            for view in views.iter() {
+                assert!(view.length > 0);
+                assert_eq!(view.offset % 4, 0);
+                assert_eq!(view.offset, end);
+                end += view.length;
+            }
+            assert_eq!(end, buffer.len());
+            for (indices, view) in mesh.indices.iter().zip(index_views) {
+                assert_eq!(
+                    view.map(|view| views[view].length),
+                    (!indices.is_empty()).then_some(indices.len() * 4)
+                );
+            }
+        }
+
+        // a map that wraps around without walls has no wall triangles, and no view for them
+        let map = Map::<2>::from(0, [2, 2]).with_wrapping(true);
+        let path = output_dir("gltf").join("maze.gltf");
+        export_gltf(&map, path.to_str().unwrap()).unwrap();
+        let gltf = fs::read_to_string(&path).unwrap();
+        assert!(!gltf.contains(r#""byteLength":0"#));
+        assert_eq!(gltf.matches(r#""target":34963"#).count(), 2);
+
+        // a failed export leaves no textures behind
+        let solid = Map::<2>::from(WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH, [2, 2]);
+        let directory = output_dir("gltf-solid");
+        assert!(export_gltf(&solid, directory.join("maze.gltf").to_str().unwrap()).is_err());
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+    }
+}
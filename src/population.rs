@@ -0,0 +1,334 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::entities::{DODECAHEDRON, OPENGL, RAT};
+use crate::grid::Grid;
+use crate::solver::{distances_from, shortest_path};
+use crate::xml::Element;
+
+/// Kind of tile an entity can be placed on, by the number of open sides of the tile
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Placement {
+    Anywhere,
+    /// Tiles with a single open side
+    DeadEnd,
+    /// Tiles with three open sides or more
+    Junction,
+}
+
+impl Placement {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "anywhere" => Some(Self::Anywhere),
+            "dead-end" => Some(Self::DeadEnd),
+            "junction" => Some(Self::Junction),
+            _ => None,
+        }
+    }
+
+    fn accepts(self, open_sides: usize) -> bool {
+        match self {
+            Self::Anywhere => true,
+            Self::DeadEnd => open_sides == 1,
+            Self::Junction => open_sides >= 3,
+        }
+    }
+}
+
+/// How the entities of a rule are placed two by two, for teleporters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pairing {
+    /// The two tiles farthest apart by walking, so that the pair makes the largest shortcut
+    Shortcut,
+    /// Any two tiles, which can make loops that lead back near where the player came from
+    Random,
+}
+
+impl Pairing {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "shortcut" => Some(Self::Shortcut),
+            "random" => Some(Self::Random),
+            _ => None,
+        }
+    }
+}
+
+/// Tiles that the shortcut pairing walks the maze from to find the tiles farthest apart. Each walk visits the whole
+/// maze, so only a few of the candidate tiles are tried
+const PAIR_SOURCES: usize = 8;
+
+/// Two of the tiles that are the most steps apart, see `PAIR_SOURCES`. Tiles that can't reach each other are never
+/// picked, unless no two tiles can
+fn farthest_pair(map: &impl Grid, tiles: &[[usize; 2]]) -> [[usize; 2]; 2] {
+    let graph = map.graph();
+    tiles
+        .iter()
+        .take(PAIR_SOURCES)
+        .flat_map(|&a| {
+            let distances = distances_from(map, a);
+            tiles
+                .iter()
+                .filter_map(move |&b| Some((distances[graph.index(b)]?, [a, b])))
+        })
+        .max_by_key(|&(distance, _)| distance)
+        .map_or([tiles[0], tiles[1]], |(_, pair)| pair)
+}
+
+/// How many entities a rule places
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Amount {
+    Count(usize),
+    /// Entities per free tile of the map, rounded to the nearest count
+    Density(f32),
+}
+
+/// Entities of one archetype to place when a level doesn't list its entities, and where they may go
+#[derive(Clone, Debug)]
+pub struct PopulationRule {
+    pub archetype: String,
+    pub amount: Amount,
+    pub placement: Placement,
+    /// Fewest steps from the start to the tile of the entity
+    pub min_distance: usize,
+    /// Keeps the entities off the shortest path from the start to the end
+    pub off_solution: bool,
+    /// Places the entities two by two, and the amount counts pairs
+    pub pairs: Option<Pairing>,
+}
+
+/// Rules that fill a level with entities, applied in order. A tile gets one entity at most, and a rule places fewer
+/// entities than asked for when it runs out of tiles that follow it. The two entities of a pair are placed one after
+/// the other
+#[derive(Clone, Debug)]
+pub struct Population {
+    pub rules: Vec<PopulationRule>,
+    /// Doors locking the way to the end, each with a key to find first, see `Map::lock`
+    pub doors: usize,
+}
+
+impl Default for Population {
+    /// 3 rats, 2 OpenGL logos and 4 dodecahedra anywhere, and no doors
+    fn default() -> Self {
+        let rule = |archetype: &str, count| PopulationRule {
+            archetype: archetype.to_string(),
+            amount: Amount::Count(count),
+            placement: Placement::Anywhere,
+            min_distance: 0,
+            off_solution: false,
+            pairs: None,
+        };
+        Self {
+            rules: vec![rule(RAT, 3), rule(OPENGL, 2), rule(DODECAHEDRON, 4)],
+            doors: 0,
+        }
+    }
+}
+
+fn parse_rule(element: &Element) -> Result<PopulationRule, String> {
+    let archetype = element
+        .attr("archetype")
+        .ok_or("spawn without an archetype")?
+        .to_lowercase();
+    let amount = match (element.attr("count"), element.attr("density")) {
+        (Some(v), None) => Amount::Count(v.parse().map_err(|_| format!("invalid count '{v}'"))?),
+        (None, Some(v)) => Amount::Density(
+            v.parse()
+                .ok()
+                .filter(|&d: &f32| d >= 0.0)
+                .ok_or_else(|| format!("invalid density '{v}'"))?,
+        ),
+        _ => return Err(format!("spawn of '{archetype}' needs either a count or a density")),
+    };
+    let placement = match element.attr("place") {
+        Some(v) => Placement::from_name(v).ok_or_else(|| format!("unknown placement '{v}'"))?,
+        None => Placement::Anywhere,
+    };
+    let min_distance = match element.attr("min-distance") {
+        Some(v) => v.parse().map_err(|_| format!("invalid min-distance '{v}'"))?,
+        None => 0,
+    };
+    let off_solution = match element.attr("off-solution") {
+        Some("true") => true,
+        Some("false") | None => false,
+        Some(v) => return Err(format!("invalid off-solution '{v}'")),
+    };
+    let pairs = match element.attr("pairs") {
+        Some(v) => Some(Pairing::from_name(v).ok_or_else(|| format!("unknown pairing '{v}'"))?),
+        None => None,
+    };
+    Ok(PopulationRule {
+        archetype,
+        amount,
+        placement,
+        min_distance,
+        off_solution,
+        pairs,
+    })
+}
+
+/// Reads the `<spawn>` rules of a `<population>` element, and the count of its `<doors>` element if it has one
+pub fn parse_population(element: &Element) -> Result<Population, String> {
+    let rules = element
+        .children_named("spawn")
+        .map(parse_rule)
+        .collect::<Result<_, _>>()?;
+    let doors = match element.children_named("doors").next() {
+        Some(doors) => {
+            let v = doors.attr("count").ok_or("doors without a count")?;
+            v.parse().map_err(|_| format!("invalid doors count '{v}'"))?
+        }
+        None => 0,
+    };
+    Ok(Population { rules, doors })
+}
+
+impl Population {
+    /// Picks the tiles of the entities among `free`. The start and end, when given, are used by the distance and
+    /// solution rules; rules that need a start are ignored without one
+    pub fn place(
+        &self,
+        map: &impl Grid,
+        free: &[[usize; 2]],
+        start: Option<[usize; 2]>,
+        end: Option<[usize; 2]>,
+        rng: &mut impl Rng,
+    ) -> Vec<(String, [usize; 2])> {
+        let graph = map.graph();
+        let distances = start.map(|start| distances_from(map, start));
+        let solution = match (start, end) {
+            (Some(start), Some(end)) => shortest_path(map, start, end).unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        let mut taken = vec![false; graph.capacity()];
+        let mut placed = Vec::new();
+        for rule in self.rules.iter() {
+            let count = match rule.amount {
+                Amount::Count(count) => count,
+                Amount::Density(density) => (density * free.len() as f32).round() as usize,
+            };
+            let distance_ok = |tile: [usize; 2]| match &distances {
+                Some(distances) => distances[graph.index(tile)].is_some_and(|d| d >= rule.min_distance),
+                None => true,
+            };
+            let mut candidates: Vec<[usize; 2]> = free
+                .iter()
+                .copied()
+                .filter(|&tile| !taken[graph.index(tile)])
+                .filter(|&tile| rule.placement.accepts(graph.degree(tile)))
+                .filter(|&tile| distance_ok(tile))
+                .filter(|tile| !rule.off_solution || !solution.contains(tile))
+                .collect();
+            candidates.shuffle(rng);
+            let Some(pairing) = rule.pairs else {
+                for tile in candidates.into_iter().take(count) {
+                    taken[graph.index(tile)] = true;
+                    placed.push((rule.archetype.clone(), tile));
+                }
+                continue;
+            };
+            for _ in 0..count {
+                candidates.retain(|&tile| !taken[graph.index(tile)]);
+                if candidates.len() < 2 {
+                    break;
+                }
+                let pair = match pairing {
+                    Pairing::Shortcut => farthest_pair(map, &candidates),
+                    Pairing::Random => [candidates[0], candidates[1]],
+                };
+                for tile in pair {
+                    taken[graph.index(tile)] = true;
+                    placed.push((rule.archetype.clone(), tile));
+                }
+            }
+        }
+        placed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dfs::random_maze;
+    use crate::map::positions;
+    use rand::thread_rng;
+
+    #[test]
+    fn placement_rules_are_followed() {
+        let rule = |archetype: &str, amount, placement, min_distance, off_solution| PopulationRule {
+            archetype: archetype.to_string(),
+            amount,
+            placement,
+            min_distance,
+            off_solution,
+            pairs: None,
+        };
+        let population = Population {
+            rules: vec![
+                rule("rat", Amount::Count(100), Placement::DeadEnd, 0, false),
+                rule("opengl", Amount::Count(100), Placement::Junction, 0, false),
+                rule("dodecahedron", Amount::Density(1.0), Placement::Anywhere, 4, true),
+            ],
+            doors: 0,
+        };
+        for _ in 0..10 {
+            let map = random_maze([9, 9]);
+            let graph = map.graph();
+            let (start, end) = ([0, 0], [8, 8]);
+            let free: Vec<[usize; 2]> = positions([9, 9]).filter(|&tile| tile != start && tile != end).collect();
+            let placed = population.place(&map, &free, Some(start), Some(end), &mut thread_rng());
+
+            let distances = distances_from(&map, start);
+            let solution = shortest_path(&map, start, end).unwrap();
+            let mut tiles: Vec<[usize; 2]> = placed.iter().map(|&(_, tile)| tile).collect();
+            for (archetype, tile) in placed.iter() {
+                assert!(free.contains(tile));
+                match archetype.as_str() {
+                    "rat" => assert_eq!(graph.degree(*tile), 1),
+                    "opengl" => assert!(graph.degree(*tile) >= 3),
+                    _ => {
+                        assert!(distances[graph.index(*tile)].unwrap() >= 4);
+                        assert!(!solution.contains(tile));
+                    }
+                }
+            }
+            // every dead end gets a rat, and no tile gets two entities
+            let dead_ends = free.iter().filter(|&&tile| graph.degree(tile) == 1).count();
+            assert_eq!(
+                placed.iter().filter(|(archetype, _)| archetype == "rat").count(),
+                dead_ends
+            );
+            tiles.sort();
+            tiles.dedup();
+            assert_eq!(tiles.len(), placed.len());
+        }
+    }
+
+    #[test]
+    fn shortcut_pairs_are_far_apart() {
+        let population = Population {
+            rules: vec![PopulationRule {
+                archetype: "teleporter".to_string(),
+                amount: Amount::Count(1),
+                placement: Placement::DeadEnd,
+                min_distance: 0,
+                off_solution: false,
+                pairs: Some(Pairing::Shortcut),
+            }],
+            doors: 0,
+        };
+        let map = random_maze([9, 9]);
+        let graph = map.graph();
+        let free: Vec<[usize; 2]> = positions([9, 9]).collect();
+        let placed = population.place(&map, &free, None, None, &mut thread_rng());
+        let [(_, a), (_, b)] = placed.as_slice() else {
+            panic!("expected a pair, got {placed:?}");
+        };
+        // the first tile of the pair is one of the tiles walked from, and the second is the farthest from it
+        let distances = distances_from(&map, *a);
+        assert!(a != b && graph.degree(*a) == 1 && graph.degree(*b) == 1);
+        for tile in free.iter().filter(|&&tile| graph.degree(tile) == 1) {
+            assert!(distances[graph.index(*tile)] <= distances[graph.index(*b)]);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::grid::Grid;
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+/// Breadth-first search for the shortest path between two tiles. The returned path includes both the start and end tiles
+pub fn shortest_path(map: &impl Grid, start: [usize; 2], end: [usize; 2]) -> Option<Vec<[usize; 2]>> {
+    shortest_path_with_links(map, start, end, &[])
+}
+
+/// Same as `shortest_path`, where the two tiles of each link are also one step apart, like the pads of a teleporter
+pub fn shortest_path_with_links(
+    map: &impl Grid,
+    start: [usize; 2],
+    end: [usize; 2],
+    links: &[[[usize; 2]; 2]],
+) -> Option<Vec<[usize; 2]>> {
+    let graph = map.graph();
+    if !map.contains([start[0] as i32, start[1] as i32]) {
+        return None;
+    }
+    let mut previous: Vec<Option<[usize; 2]>> = vec![None; graph.capacity()];
+    previous[graph.index(start)] = Some(start);
+    let mut queue = VecDeque::from([start]);
+    while let Some(node) = queue.pop_front() {
+        if node == end {
+            let mut path = vec![end];
+            let mut position = end;
+            while position != start {
+                position = previous[graph.index(position)].unwrap();
+                path.push(position);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        let linked = links.iter().filter_map(|&[a, b]| match node {
+            _ if node == a => Some(b),
+            _ if node == b => Some(a),
+            _ => None,
+        });
+        for neighbour in graph.neighbours(node).chain(linked) {
+            if previous[graph.index(neighbour)].is_none() {
+                previous[graph.index(neighbour)] = Some(node);
+                queue.push_back(neighbour);
+            }
+        }
+    }
+    None
+}
+
+/// Number of steps from the given tile to every tile, indexed like `MazeGraph::index`. Tiles that can't be reached
+/// are None
+pub fn distances_from(map: &impl Grid, start: [usize; 2]) -> Vec<Option<usize>> {
+    let graph = map.graph();
+    let mut distances = vec![None; graph.capacity()];
+    for (node, from) in graph.breadth_first(start) {
+        distances[graph.index(node)] = Some(distances[graph.index(from)].map_or(0, |d: usize| d + 1));
+    }
+    distances
+}
+
+/// Flood fill from the given tile. Returns every tile reachable from it, including itself
+pub fn reachable_tiles(map: &impl Grid, start: [usize; 2]) -> Vec<[usize; 2]> {
+    map.graph().breadth_first(start).map(|(node, _)| node).collect()
+}
+
+/// Splits the tiles accepted by `filter` into groups of tiles that are reachable from each other.
+/// Regions are sorted from largest to smallest
+pub fn connected_regions(map: &impl Grid, filter: impl Fn([usize; 2]) -> bool) -> Vec<Vec<[usize; 2]>> {
+    let graph = map.graph();
+    let mut assigned = vec![false; graph.capacity()];
+    let mut regions = Vec::new();
+    for tile in map.cells() {
+        if assigned[graph.index(tile)] || !filter(tile) {
+            continue;
+        }
+        let region = reachable_tiles(map, tile);
+        for &t in region.iter() {
+            assigned[graph.index(t)] = true;
+        }
+        regions.push(region);
+    }
+    regions.sort_by_key(|r| std::cmp::Reverse(r.len()));
+    regions
+}
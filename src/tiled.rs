@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::archetypes::Archetypes;
+use crate::base64;
+use crate::game::Spawns;
+use crate::goal::{parse_goal, Goal};
+use crate::grid::SIDES;
+use crate::map::{GetSetMap, Map, WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST};
+use crate::xml::{self, Element};
+
+const FLIPPED_HORIZONTALLY: u32 = 0x80000000;
+const FLIPPED_VERTICALLY: u32 = 0x40000000;
+const FLIPPED_DIAGONALLY: u32 = 0x20000000;
+const GID_MASK: u32 = 0x0fffffff;
+
+/// A level loaded from a Tiled map
+pub struct TiledLevel {
+    pub map: Map<2>,
+    pub spawns: Spawns,
+    /// Goal given by the properties of the map, if it has any of them
+    pub goal: Option<Goal>,
+    /// Problems found in the file that did not prevent loading it
+    pub warnings: Vec<String>,
+}
+
+impl TiledLevel {
+    /// Warnings about the objects of the level whose type is not the name of an archetype. Their entities are still
+    /// spawned, but can't be seen or touched
+    pub fn archetype_warnings(&self, archetypes: &Archetypes) -> Vec<String> {
+        let mut unknown: Vec<&str> = Vec::new();
+        for (name, _) in self
+            .spawns
+            .entities
+            .iter()
+            .filter(|(name, _)| archetypes.get(name).is_none())
+        {
+            if !unknown.contains(&name.as_str()) {
+                unknown.push(name);
+            }
+        }
+        unknown
+            .into_iter()
+            .map(|name| format!("no archetype named '{name}', its entities can't be seen or touched"))
+            .collect()
+    }
+}
+
+/// What a tile means for the maze, read from the tile's class and custom properties:
+/// - tiles with the `wall` class or a `solid` bool property set to true fill the whole cell
+/// - tiles with a `walls` string property (any of the letters N, E, S, W) put walls on those edges of the cell
+#[derive(Clone, Copy, Default)]
+struct TileInfo {
+    solid: bool,
+    walls: i8,
+}
+
+struct Tileset {
+    first_gid: u32,
+    tiles: HashMap<u32, TileInfo>,
+}
+
+fn parse_walls(value: &str, warnings: &mut Vec<String>) -> i8 {
+    let mut walls = 0;
+    for c in value.chars() {
+        match c.to_ascii_uppercase() {
+            'N' => walls |= WALL_NORTH,
+            'E' => walls |= WALL_EAST,
+            'S' => walls |= WALL_SOUTH,
+            'W' => walls |= WALL_WEST,
+            ',' | ' ' => {}
+            _ => warnings.push(format!("unknown wall direction '{c}' in \"{value}\"")),
+        }
+    }
+    walls
+}
+
+fn parse_tileset(element: &Element, first_gid: u32, warnings: &mut Vec<String>) -> Tileset {
+    let mut tiles = HashMap::new();
+    for tile in element.children_named("tile") {
+        let Some(id) = tile.attr("id").and_then(|v| v.parse::<u32>().ok()) else {
+            warnings.push("tileset tile without a valid id, ignored".to_string());
+            continue;
+        };
+        let mut info = TileInfo {
+            solid: tile.attr("class").or(tile.attr("type")) == Some("wall"),
+            walls: 0,
+        };
+        for property in tile
+            .children_named("properties")
+            .flat_map(|p| p.children_named("property"))
+        {
+            let value = property.attr("value").unwrap_or(property.text.as_str());
+            match property.attr("name") {
+                Some("solid") => info.solid = value == "true",
+                Some("walls") => info.walls = parse_walls(value, warnings),
+                _ => {}
+            }
+        }
+        tiles.insert(id, info);
+    }
+    Tileset { first_gid, tiles }
+}
+
+fn load_tileset(element: &Element, base_dir: &Path, warnings: &mut Vec<String>) -> Result<Tileset, String> {
+    let first_gid = element
+        .attr("firstgid")
+        .and_then(|v| v.parse::<u32>().ok())
+        .ok_or("tileset without a valid firstgid")?;
+    match element.attr("source") {
+        Some(source) => {
+            let path = base_dir.join(source);
+            let text = fs::read_to_string(&path).map_err(|e| format!("could not read {}: {e}", path.display()))?;
+            let root = xml::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+            Ok(parse_tileset(&root, first_gid, warnings))
+        }
+        None => Ok(parse_tileset(element, first_gid, warnings)),
+    }
+}
+
+/// Returns the wall bits after applying Tiled's flip flags. The diagonal flip is applied first, like Tiled does
+fn flip_walls(mut walls: i8, flags: u32) -> i8 {
+    let swap = |walls: i8, a: i8, b: i8| {
+        let mut result = walls & !(a | b);
+        if walls & a == a {
+            result |= b;
+        }
+        if walls & b == b {
+            result |= a;
+        }
+        result
+    };
+    if flags & FLIPPED_DIAGONALLY != 0 {
+        walls = swap(swap(walls, WALL_NORTH, WALL_WEST), WALL_SOUTH, WALL_EAST);
+    }
+    if flags & FLIPPED_HORIZONTALLY != 0 {
+        walls = swap(walls, WALL_EAST, WALL_WEST);
+    }
+    if flags & FLIPPED_VERTICALLY != 0 {
+        walls = swap(walls, WALL_NORTH, WALL_SOUTH);
+    }
+    walls
+}
+
+/// Reads the global tile ids of a tile layer, in row-major order
+fn read_layer_data(layer: &Element) -> Result<Vec<u32>, String> {
+    let data = layer.child("data").ok_or("tile layer without data")?;
+    if data.attr("compression").is_some() {
+        return Err("compressed tile layers are not supported, save the map with CSV or uncompressed Base64".into());
+    }
+    if data.child("chunk").is_some() {
+        return Err("infinite maps are not supported".into());
+    }
+    match data.attr("encoding") {
+        Some("csv") => data
+            .text
+            .split(',')
+            .map(|v| {
+                v.trim()
+                    .parse::<u32>()
+                    .map_err(|_| format!("invalid tile id '{}'", v.trim()))
+            })
+            .collect(),
+        Some("base64") => {
+            let bytes = base64::decode(&data.text)?;
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect())
+        }
+        Some(encoding) => Err(format!("unsupported tile layer encoding '{encoding}'")),
+        None => Ok(data
+            .children_named("tile")
+            .map(|t| t.attr("gid").and_then(|v| v.parse().ok()).unwrap_or(0))
+            .collect()),
+    }
+}
+
+fn set_spawn(spawns: &mut Spawns, kind: &str, tile: [usize; 2]) {
+    match kind {
+        "start" | "player" => spawns.start = Some(tile),
+        "end" | "exit" => spawns.end = Some(tile),
+        _ => spawns.entities.push((kind.to_string(), tile)),
+    }
+}
+
+/// Builds a level from the contents of a .tmx file. `base_dir` is used to find external tilesets.
+///
+/// Every tile of a tile layer becomes one cell of the maze. Cells covered by solid tiles get all their walls,
+/// and the cells around them get a wall on the shared edge. Edge walls are mirrored on the neighbouring cell.
+/// Objects in object layers place the start, the end and entities of any archetype; the object type is read from its
+/// class (or type, or name). The `collect`, `collect-count` and `checkpoints` properties of the map give its goal
+pub fn parse_tmx(source: &str, base_dir: &Path) -> Result<TiledLevel, String> {
+    let root = xml::parse(source)?;
+    if root.name != "map" {
+        return Err(format!("expected a <map> element, found <{}>", root.name));
+    }
+    if root.attr("orientation").is_some_and(|o| o != "orthogonal") {
+        return Err("only orthogonal maps are supported".into());
+    }
+    if root.attr("infinite") == Some("1") {
+        return Err("infinite maps are not supported".into());
+    }
+    let attr_usize = |name: &str| {
+        root.attr(name)
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|&v| v > 0)
+            .ok_or(format!("map without a valid {name}"))
+    };
+    let (cols, rows) = (attr_usize("width")?, attr_usize("height")?);
+    let (tile_width, tile_height) = (attr_usize("tilewidth")? as f32, attr_usize("tileheight")? as f32);
+
+    let mut warnings = Vec::new();
+    let mut tilesets = Vec::new();
+    for element in root.children_named("tileset") {
+        tilesets.push(load_tileset(element, base_dir, &mut warnings)?);
+    }
+    tilesets.sort_by_key(|t| t.first_gid);
+    let tile_info = |gid: u32| -> TileInfo {
+        let id = gid & GID_MASK;
+        let Some(tileset) = tilesets.iter().rev().find(|t| t.first_gid <= id) else {
+            return TileInfo::default();
+        };
+        let info = tileset
+            .tiles
+            .get(&(id - tileset.first_gid))
+            .copied()
+            .unwrap_or_default();
+        TileInfo {
+            walls: flip_walls(info.walls, gid),
+            ..info
+        }
+    };
+
+    let mut solid = vec![false; rows * cols];
+    let mut map = Map::<2>::from(0, [rows, cols]);
+    let mut layer_count = 0;
+    for layer in root.children_named("layer") {
+        let gids = read_layer_data(layer)?;
+        if gids.len() != rows * cols {
+            return Err(format!(
+                "layer '{}' has {} tiles, expected {}",
+                layer.attr("name").unwrap_or_default(),
+                gids.len(),
+                rows * cols
+            ));
+        }
+        layer_count += 1;
+        for (index, &gid) in gids.iter().enumerate() {
+            if gid == 0 {
+                continue;
+            }
+            let info = tile_info(gid);
+            let [i, j] = [(index / cols) as i32, (index % cols) as i32];
+            solid[index] |= info.solid;
+            map.set_item([i, j], map.get_item([i, j]) | info.walls);
+            for (wall, opposite, [di, dj]) in SIDES {
+                if info.walls & wall == wall && map.contains([i + di, j + dj]) {
+                    map.set_item([i + di, j + dj], map.get_item([i + di, j + dj]) | opposite);
+                }
+            }
+        }
+    }
+    if layer_count == 0 {
+        return Err("map without tile layers".into());
+    }
+
+    map.wall_off_solid_cells(&solid);
+
+    let mut spawns = Spawns::default();
+    for object in root
+        .children_named("objectgroup")
+        .flat_map(|g| g.children_named("object"))
+    {
+        let id = object.attr("id").unwrap_or("?");
+        let kind = object
+            .attr("class")
+            .or(object.attr("type"))
+            .filter(|k| !k.is_empty())
+            .or(object.attr("name"))
+            .unwrap_or_default()
+            .to_lowercase();
+        let number = |name: &str| object.attr(name).and_then(|v| v.parse::<f32>().ok()).unwrap_or(0.0);
+        let (width, height) = (number("width"), number("height"));
+        let x = number("x") + width / 2.0;
+        // tile objects are anchored at their bottom-left corner, other objects at their top-left corner
+        let y = if object.attr("gid").is_some() {
+            number("y") - height / 2.0
+        } else {
+            number("y") + height / 2.0
+        };
+        let (row, col) = ((y / tile_height).floor(), (x / tile_width).floor());
+        if row < 0.0 || col < 0.0 || row as usize >= rows || col as usize >= cols {
+            warnings.push(format!("object {id} ('{kind}') is outside of the map, ignored"));
+            continue;
+        }
+        let tile = [row as usize, col as usize];
+        if solid[tile[0] * cols + tile[1]] {
+            warnings.push(format!("object {id} ('{kind}') is inside a solid tile"));
+        }
+        if kind.is_empty() {
+            warnings.push(format!("object {id} has no type, ignored"));
+            continue;
+        }
+        set_spawn(&mut spawns, &kind, tile);
+    }
+
+    let properties: HashMap<&str, &str> = root
+        .children_named("properties")
+        .flat_map(|p| p.children_named("property"))
+        .filter_map(|property| {
+            Some((
+                property.attr("name")?,
+                property.attr("value").unwrap_or(property.text.as_str()),
+            ))
+        })
+        .collect();
+    let goal = if ["collect", "collect-count", "checkpoints"]
+        .iter()
+        .any(|name| properties.contains_key(name))
+    {
+        Some(parse_goal(|name| properties.get(name).copied()).map_err(|e| format!("goal: {e}"))?)
+    } else {
+        None
+    };
+
+    Ok(TiledLevel {
+        map,
+        spawns,
+        goal,
+        warnings,
+    })
+}
+
+/// Loads a level from a .tmx file saved by the Tiled editor
+pub fn load_tmx(path: &str) -> Result<TiledLevel, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    let base_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    parse_tmx(&source, base_dir).map_err(|e| format!("{path}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archetypes::parse_archetypes;
+    use crate::game::GameState;
+    use crate::map::RectangularMap;
+
+    const ALL_WALLS: i8 = WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH;
+
+    /// A 3x2 map with a solid tile and a tile with an east wall, and one layer for each of the given encodings
+    fn tmx(layers: &[(&str, &str)], objects: &str) -> String {
+        let layers: String = layers
+            .iter()
+            .map(|(encoding, data)| {
+                format!(r#"<layer width="3" height="2"><data encoding="{encoding}">{data}</data></layer>"#)
+            })
+            .collect();
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <map orientation="orthogonal" width="3" height="2" tilewidth="16" tileheight="16">
+                <tileset firstgid="1">
+                    <tile id="0" class="wall"/>
+                    <tile id="1"><properties><property name="walls" value="E"/></properties></tile>
+                </tileset>
+                {layers}
+                <objectgroup>{objects}</objectgroup>
+            </map>"#
+        )
+    }
+
+    fn base64_layer(gids: &[u32]) -> String {
+        base64::encode(&gids.iter().flat_map(|gid| gid.to_le_bytes()).collect::<Vec<u8>>())
+    }
+
+    #[test]
+    fn csv_and_base64_layers_are_merged() {
+        // the base64 layer flips the tile with the east wall horizontally, which moves its wall to the west
+        let flipped = base64_layer(&[0, 0, 2 | FLIPPED_HORIZONTALLY, 0, 0, 0]);
+        let level = parse_tmx(
+            &tmx(&[("csv", "2,0,0,\n0,0,1"), ("base64", &flipped)], ""),
+            Path::new(""),
+        )
+        .unwrap();
+        let map = &level.map;
+        assert_eq!(map.dimensions(), [2, 3]);
+        assert_eq!(map.get_item([0, 0]), WALL_NORTH | WALL_WEST | WALL_EAST);
+        // edge walls are mirrored on the neighbouring tile
+        assert_eq!(map.get_item([0, 1]), WALL_NORTH | WALL_WEST | WALL_EAST);
+        assert_eq!(map.get_item([0, 2]), WALL_NORTH | WALL_EAST | WALL_WEST | WALL_SOUTH);
+        assert_eq!(map.get_item([1, 2]), ALL_WALLS);
+        assert_eq!(map.get_item([1, 1]), WALL_SOUTH | WALL_EAST);
+        assert!(level.warnings.is_empty());
+        assert!(level.goal.is_none());
+
+        let unflipped = base64_layer(&[2, 0, 0, 0, 0, 0]);
+        let level = parse_tmx(&tmx(&[("base64", &unflipped)], ""), Path::new("")).unwrap();
+        assert_eq!(level.map.get_item([0, 1]) & WALL_WEST, WALL_WEST);
+        assert!(parse_tmx(&tmx(&[("csv", "2,0,0")], ""), Path::new("")).is_err());
+    }
+
+    #[test]
+    fn objects_become_spawns() {
+        let objects = r#"
+            <object id="1" type="start" x="0" y="0" width="16" height="16"/>
+            <object id="2" class="end" x="0" y="16" width="16" height="16"/>
+            <object id="3" name="dragon" x="20" y="4"/>
+            <object id="4" x="20" y="20"/>
+            <object id="5" type="rat" x="100" y="4"/>
+            <object id="6" type="rat" x="40" y="20"/>"#;
+        let level = parse_tmx(&tmx(&[("csv", "0,0,0,0,0,1")], objects), Path::new("")).unwrap();
+        assert_eq!(level.spawns.start, Some([0, 0]));
+        assert_eq!(level.spawns.end, Some([1, 0]));
+        assert_eq!(
+            level.spawns.entities,
+            [("dragon".to_string(), [0, 1]), ("rat".to_string(), [1, 2])]
+        );
+        assert_eq!(level.warnings.len(), 3, "{:?}", level.warnings);
+
+        // objects without an archetype are still spawned
+        let archetypes = parse_archetypes(include_str!("../assets/entities.xml")).unwrap();
+        assert_eq!(level.archetype_warnings(&archetypes).len(), 1);
+        let game = GameState::with_map(0.0, level.map, Some(level.spawns), archetypes);
+        assert!(game.entities.iter().any(|entity| entity.name == "dragon"));
+    }
+
+    #[test]
+    fn sample_level_asks_for_every_smiley() {
+        let level = parse_tmx(include_str!("../assets/levels/smileys.tmx"), Path::new("assets/levels")).unwrap();
+        assert!(level.warnings.is_empty(), "{:?}", level.warnings);
+        assert_eq!(
+            level.goal.as_ref().and_then(|goal| goal.collect.as_deref()),
+            Some("smiley")
+        );
+        assert_eq!(level.spawns.entities.len(), 5);
+
+        let archetypes = parse_archetypes(include_str!("../assets/entities.xml")).unwrap();
+        assert!(level.archetype_warnings(&archetypes).is_empty());
+        let mut game = GameState::with_map(0.0, level.map, Some(level.spawns), archetypes);
+        game.set_goal(level.goal.unwrap());
+        assert_eq!(game.collected(), Some((0, 5)));
+    }
+}
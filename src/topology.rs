@@ -0,0 +1,212 @@
+use raylib::prelude::*;
+
+use crate::grid::SIDES;
+use crate::map::{WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST};
+
+pub const HEX_EAST: i8 = 1 << 0;
+pub const HEX_NORTHEAST: i8 = 1 << 1;
+pub const HEX_NORTHWEST: i8 = 1 << 2;
+pub const HEX_WEST: i8 = 1 << 3;
+pub const HEX_SOUTHWEST: i8 = 1 << 4;
+pub const HEX_SOUTHEAST: i8 = 1 << 5;
+
+/// Sides of a hexagonal tile in an even row, with the wall on the other side and the offset to the neighbouring tile
+const HEX_SIDES_EVEN: [(i8, i8, [i32; 2]); 6] = [
+    (HEX_EAST, HEX_WEST, [0, 1]),
+    (HEX_NORTHEAST, HEX_SOUTHWEST, [-1, 0]),
+    (HEX_NORTHWEST, HEX_SOUTHEAST, [-1, -1]),
+    (HEX_WEST, HEX_EAST, [0, -1]),
+    (HEX_SOUTHWEST, HEX_NORTHEAST, [1, -1]),
+    (HEX_SOUTHEAST, HEX_NORTHWEST, [1, 0]),
+];
+
+/// Odd rows are shifted half a tile to the east, so their diagonal neighbours are one column further
+const HEX_SIDES_ODD: [(i8, i8, [i32; 2]); 6] = [
+    (HEX_EAST, HEX_WEST, [0, 1]),
+    (HEX_NORTHEAST, HEX_SOUTHWEST, [-1, 1]),
+    (HEX_NORTHWEST, HEX_SOUTHEAST, [-1, 0]),
+    (HEX_WEST, HEX_EAST, [0, -1]),
+    (HEX_SOUTHWEST, HEX_NORTHEAST, [1, 0]),
+    (HEX_SOUTHEAST, HEX_NORTHWEST, [1, 1]),
+];
+
+/// Distance from the center of a hexagonal tile to its corners. Hexagons are one tile wide from side to side
+const HEX_RADIUS: f32 = 0.577_350_26;
+
+/// Shape of the tiles of a map and how they connect.
+///
+/// Positions of the tile geometry are measured in tiles, with x growing to the east and y to the south, so they map to
+/// the world's x and z axes once multiplied by `TILE_SIZE`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Topology {
+    /// Square tiles with four walls
+    #[default]
+    Square,
+    /// Pointy-top hexagons with six walls. Odd rows are shifted half a tile to the east
+    Hex,
+}
+
+impl Topology {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "square" => Some(Self::Square),
+            "hex" => Some(Self::Hex),
+            _ => None,
+        }
+    }
+
+    /// Each side of the tile with the wall on the other side of the same edge and the offset to the neighbouring tile
+    pub fn sides(self, [row, _]: [usize; 2]) -> &'static [(i8, i8, [i32; 2])] {
+        match self {
+            Self::Square => &SIDES,
+            Self::Hex if row % 2 == 0 => &HEX_SIDES_EVEN,
+            Self::Hex => &HEX_SIDES_ODD,
+        }
+    }
+
+    /// Wall bits of a tile that is closed on every side
+    pub fn all_walls(self) -> i8 {
+        match self {
+            Self::Square => WALL_EAST | WALL_NORTH | WALL_WEST | WALL_SOUTH,
+            Self::Hex => HEX_EAST | HEX_NORTHEAST | HEX_NORTHWEST | HEX_WEST | HEX_SOUTHWEST | HEX_SOUTHEAST,
+        }
+    }
+
+    pub fn side_count(self) -> usize {
+        match self {
+            Self::Square => 4,
+            Self::Hex => 6,
+        }
+    }
+
+    pub fn side_name(self, side: i8) -> &'static str {
+        match (self, side) {
+            (Self::Square, WALL_EAST) => "east",
+            (Self::Square, WALL_NORTH) => "north",
+            (Self::Square, WALL_WEST) => "west",
+            (Self::Square, WALL_SOUTH) => "south",
+            (Self::Hex, HEX_EAST) => "east",
+            (Self::Hex, HEX_NORTHEAST) => "north-east",
+            (Self::Hex, HEX_NORTHWEST) => "north-west",
+            (Self::Hex, HEX_WEST) => "west",
+            (Self::Hex, HEX_SOUTHWEST) => "south-west",
+            (Self::Hex, HEX_SOUTHEAST) => "south-east",
+            _ => "unknown",
+        }
+    }
+
+    /// Distance between the centers of two rows of tiles
+    pub fn row_height(self) -> f32 {
+        match self {
+            Self::Square => 1.0,
+            Self::Hex => 1.5 * HEX_RADIUS,
+        }
+    }
+
+    pub fn center(self, [row, col]: [usize; 2]) -> Vector2 {
+        match self {
+            Self::Square => Vector2::new(col as f32 + 0.5, row as f32 + 0.5),
+            Self::Hex => Vector2::new(
+                col as f32 + 0.5 + (row % 2) as f32 * 0.5,
+                HEX_RADIUS + row as f32 * self.row_height(),
+            ),
+        }
+    }
+
+    /// Width and height of a map with the given dimensions
+    pub fn extent(self, [rows, cols]: [usize; 2]) -> Vector2 {
+        match self {
+            Self::Square => Vector2::new(cols as f32, rows as f32),
+            Self::Hex => Vector2::new(
+                cols as f32 + if rows > 1 { 0.5 } else { 0.0 },
+                2.0 * HEX_RADIUS + rows.saturating_sub(1) as f32 * self.row_height(),
+            ),
+        }
+    }
+
+    /// Distance after which the tiles of a map that wraps around repeat
+    pub fn period(self, [rows, cols]: [usize; 2]) -> Vector2 {
+        Vector2::new(cols as f32, rows as f32 * self.row_height())
+    }
+
+    /// Direction in which a side of the tile faces, in degrees counter-clockwise from the east with the north up
+    fn side_angle(self, side: i8) -> f32 {
+        let index = match self {
+            Self::Square => [WALL_EAST, WALL_NORTH, WALL_WEST, WALL_SOUTH]
+                .iter()
+                .position(|&s| s == side),
+            Self::Hex => HEX_SIDES_EVEN.iter().position(|&(s, _, _)| s == side),
+        };
+        index.unwrap_or(0) as f32 * 360.0 / self.side_count() as f32
+    }
+
+    /// Distance from the center of a tile to its corners
+    pub fn corner_radius(self) -> f32 {
+        match self {
+            Self::Square => std::f32::consts::FRAC_1_SQRT_2,
+            Self::Hex => HEX_RADIUS,
+        }
+    }
+
+    fn corner(self, center: Vector2, angle: f32) -> Vector2 {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        center + Vector2::new(cos, -sin) * self.corner_radius()
+    }
+
+    /// Corners of the tile, counter-clockwise with the north up
+    pub fn corners(self, tile: [usize; 2]) -> Vec<Vector2> {
+        let [row, col] = tile;
+        match self {
+            Self::Square => {
+                let (x0, y0, x1, y1) = (col as f32, row as f32, col as f32 + 1.0, row as f32 + 1.0);
+                vec![Vector2::new(x1, y0), Vector2::new(x0, y0), Vector2::new(x0, y1), Vector2::new(x1, y1)]
+            }
+            Self::Hex => (0..6)
+                .map(|k| self.corner(self.center(tile), 30.0 + 60.0 * k as f32))
+                .collect(),
+        }
+    }
+
+    /// End points of the wall on one side of the tile, counter-clockwise around the tile
+    pub fn wall_segment(self, tile: [usize; 2], side: i8) -> [Vector2; 2] {
+        match self {
+            Self::Square => {
+                let c = self.corners(tile);
+                match side {
+                    WALL_EAST => [c[3], c[0]],
+                    WALL_NORTH => [c[0], c[1]],
+                    WALL_WEST => [c[1], c[2]],
+                    _ => [c[2], c[3]],
+                }
+            }
+            Self::Hex => {
+                let (center, angle) = (self.center(tile), self.side_angle(side));
+                [self.corner(center, angle - 30.0), self.corner(center, angle + 30.0)]
+            }
+        }
+    }
+
+    /// Tile containing the point. The tile may be outside of the map
+    pub fn tile_at(self, point: Vector2) -> [i32; 2] {
+        match self {
+            Self::Square => [point.y.floor() as i32, point.x.floor() as i32],
+            Self::Hex => {
+                // hexagons are the cells of the Voronoi diagram of their centers, so look for the closest center
+                let row = ((point.y - HEX_RADIUS) / self.row_height()).round() as i32;
+                let mut closest = ([row, point.x.floor() as i32], f32::MAX);
+                for r in row - 1..=row + 1 {
+                    let shift = r.rem_euclid(2) as f32 * 0.5;
+                    let col = (point.x - 0.5 - shift).round() as i32;
+                    for c in col - 1..=col + 1 {
+                        let center = Vector2::new(c as f32 + 0.5 + shift, HEX_RADIUS + r as f32 * self.row_height());
+                        let distance = (center - point).length_sqr();
+                        if distance < closest.1 {
+                            closest = ([r, c], distance);
+                        }
+                    }
+                }
+                closest.0
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::fmt;
+
+use crate::grid::Grid;
+use crate::map::{GetSetMap, Map, RectangularMap, WallBits};
+use crate::solver::connected_regions;
+use crate::topology::Topology;
+
+pub enum MapIssue {
+    /// The tile has a wall that its neighbour doesn't have on the other side of the same edge
+    AsymmetricWall {
+        tile: [usize; 2],
+        wall: i8,
+        topology: Topology,
+    },
+    /// The tile is on the border of the map and is open towards the outside
+    MissingBorder {
+        tile: [usize; 2],
+        wall: i8,
+        topology: Topology,
+    },
+    /// Walkable tiles that can't be reached from the largest region of the map
+    UnreachableRegion { tiles: Vec<[usize; 2]> },
+}
+
+impl fmt::Display for MapIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AsymmetricWall { tile, wall, topology } => write!(
+                f,
+                "tile {tile:?} has a {} wall that is missing on its neighbour",
+                topology.side_name(*wall)
+            ),
+            Self::MissingBorder { tile, wall, topology } => {
+                write!(
+                    f,
+                    "tile {tile:?} is open to the {} of the map",
+                    topology.side_name(*wall)
+                )
+            }
+            Self::UnreachableRegion { tiles } => {
+                write!(f, "{} tile(s) starting at {:?} can't be reached", tiles.len(), tiles[0])
+            }
+        }
+    }
+}
+
+/// Checks that every wall is set on both tiles sharing it, that the map is closed by an outer border, and that
+/// every walkable tile can be reached from the others
+pub fn validate<T: WallBits>(map: &Map<2, T>) -> Vec<MapIssue> {
+    let mut issues = Vec::new();
+    let topology = map.topology();
+    for tile in map.cells() {
+        let v = map.get_walls(tile);
+        for &(wall, opposite, _) in topology.sides(tile) {
+            match map.neighbour(tile, wall) {
+                None if v & wall == 0 => issues.push(MapIssue::MissingBorder { tile, wall, topology }),
+                // the walls of a crossing are the railings of its bridge, with its tunnel going through them
+                Some(neighbour)
+                    if v & wall == wall && map.get_walls(neighbour) & opposite == 0 && !map.is_crossing(tile) =>
+                {
+                    issues.push(MapIssue::AsymmetricWall { tile, wall, topology })
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let regions = connected_regions(map, |tile| !map.is_solid(tile));
+    for region in regions.into_iter().skip(1) {
+        issues.push(MapIssue::UnreachableRegion { tiles: region });
+    }
+    issues
+}
+
+/// Makes the map consistent and returns the issues that were fixed:
+/// - one-sided walls are added to the other side of the edge, since collisions already treat them as walls
+/// - open edges on the border of the map are closed
+/// - unreachable regions are connected to a neighbouring reachable tile by removing the wall between them.
+///   Regions that can't be connected because they are surrounded by solid tiles are made solid
+pub fn repair<T: WallBits>(map: &mut Map<2, T>) -> Vec<MapIssue> {
+    let issues = validate(map);
+    for issue in issues.iter() {
+        match issue {
+            MapIssue::AsymmetricWall { tile, wall, topology } => {
+                let sides = topology.sides(*tile);
+                let opposite = sides
+                    .iter()
+                    .find(|(w, _, _)| w == wall)
+                    .map_or(0, |(_, opposite, _)| *opposite);
+                if let Some(neighbour) = map.neighbour(*tile, *wall) {
+                    map.set_walls(neighbour, map.get_walls(neighbour) | opposite);
+                }
+            }
+            MapIssue::MissingBorder { tile, wall, .. } => {
+                map.set_walls(*tile, map.get_walls(*tile) | wall);
+            }
+            MapIssue::UnreachableRegion { .. } => {}
+        }
+    }
+
+    // connect regions one at a time, since connecting a region may also connect others
+    loop {
+        let regions = connected_regions(map, |tile| !map.is_solid(tile));
+        if regions.len() <= 1 {
+            break;
+        }
+        let [rows, cols] = map.dimensions();
+        let mut reachable = vec![false; rows * cols];
+        for &[i, j] in regions[0].iter() {
+            reachable[i * cols + j] = true;
+        }
+        let door = regions[1..].iter().flatten().find_map(|&tile| {
+            map.topology().sides(tile).iter().find_map(|&(wall, opposite, _)| {
+                let neighbour = map.neighbour(tile, wall)?;
+                reachable[neighbour[0] * cols + neighbour[1]].then_some((tile, wall, neighbour, opposite))
+            })
+        });
+        match door {
+            Some((tile, wall, neighbour, opposite)) => {
+                map.set_walls(tile, map.get_walls(tile) & !wall);
+                map.set_walls(neighbour, map.get_walls(neighbour) & !opposite);
+            }
+            None => {
+                for &tile in regions[1..].iter().flatten() {
+                    map.set_walls(tile, map.topology().all_walls());
+                }
+            }
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST};
+
+    /// A 3x3 room with walls only around it
+    fn room() -> Map<2> {
+        let mut map = Map::<2>::from(0, [3, 3]);
+        map.wall_off_solid_cells(&[false; 9]);
+        map
+    }
+
+    fn add_walls(map: &mut Map<2>, tile: [usize; 2], walls: i8) {
+        map.set_walls(tile, map.get_walls(tile) | walls);
+    }
+
+    #[test]
+    fn issues_are_found_and_repaired() {
+        let mut map = room();
+        assert!(validate(&map).is_empty());
+
+        add_walls(&mut map, [1, 1], WALL_EAST);
+        map.set_walls([0, 0], map.get_walls([0, 0]) & !WALL_NORTH);
+        // the bottom right corner and its west neighbour are closed off from the rest of the room
+        add_walls(&mut map, [2, 1], WALL_NORTH | WALL_WEST);
+        add_walls(&mut map, [2, 2], WALL_NORTH);
+        add_walls(&mut map, [1, 1], WALL_SOUTH);
+        add_walls(&mut map, [1, 2], WALL_SOUTH);
+        add_walls(&mut map, [2, 0], WALL_EAST);
+
+        let issues = validate(&map);
+        assert_eq!(
+            issues.len(),
+            3,
+            "{:?}",
+            issues.iter().map(|i| i.to_string()).collect::<Vec<_>>()
+        );
+        assert!(issues.iter().any(|issue| matches!(
+            issue,
+            MapIssue::AsymmetricWall {
+                tile: [1, 1],
+                wall: WALL_EAST,
+                ..
+            }
+        )));
+        assert!(issues.iter().any(|issue| matches!(
+            issue,
+            MapIssue::MissingBorder {
+                tile: [0, 0],
+                wall: WALL_NORTH,
+                ..
+            }
+        )));
+        assert!(issues.iter().any(|issue| match issue {
+            MapIssue::UnreachableRegion { tiles } => tiles.len() == 2 && tiles.contains(&[2, 1]),
+            _ => false,
+        }));
+
+        assert_eq!(repair(&mut map).len(), 3);
+        assert!(validate(&map).is_empty());
+        assert_eq!(map.get_walls([1, 2]) & WALL_WEST, WALL_WEST);
+        assert_eq!(map.get_walls([0, 0]) & WALL_NORTH, WALL_NORTH);
+    }
+
+    #[test]
+    fn enclosed_regions_are_made_solid() {
+        let mut map = room();
+        // the middle column is solid, which cuts the right column off from the left one
+        for tile in [[0, 1], [1, 1], [2, 1]] {
+            map.set_walls(tile, map.topology().all_walls());
+        }
+        for i in 0..3 {
+            add_walls(&mut map, [i, 0], WALL_EAST);
+            add_walls(&mut map, [i, 2], WALL_WEST);
+        }
+        assert_eq!(validate(&map).len(), 1);
+        repair(&mut map);
+        assert!(validate(&map).is_empty());
+        assert_eq!(map.cells().filter(|&tile| map.is_solid(tile)).count(), 6);
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::dfs::random_dfs_with_rng;
+use crate::entities::EntityId;
+use crate::map::{positions, GetSetMap, Map, WALL_EAST, WALL_NORTH, WALL_SOUTH, WALL_WEST};
+use crate::topology::Topology;
+
+/// Chunks loaded on each side of the chunk the player is in
+const WINDOW_RADIUS: i64 = 1;
+/// Chunks along each side of the window of loaded chunks
+const WINDOW_CHUNKS: usize = 2 * WINDOW_RADIUS as usize + 1;
+
+/// Salts mixed into the seed so that each use of a chunk's coordinates draws different numbers
+const MAZE_SALT: u64 = 1;
+const NORTH_BORDER_SALT: u64 = 2;
+const WEST_BORDER_SALT: u64 = 3;
+const ENTITY_SALT: u64 = 4;
+
+/// Part of an endless maze, with the entities that were spawned in it
+struct Chunk {
+    map: Map<2>,
+    entities: Vec<EntityId>,
+}
+
+/// Endless maze made of square chunks. Each chunk is a perfect maze generated from the seed and the chunk's
+/// coordinates ([row, col], which grow to the south and east), and has one opening on each of its borders. Both
+/// chunks on a border pick the same opening, so the chunks line up and the whole maze is connected.
+/// Only the chunks in a window around the player are kept, and `window` joins them into one map
+pub struct World {
+    seed: u64,
+    chunk_dimensions: [usize; 2],
+    /// Coordinates of the chunk in the middle of the window
+    center: [i64; 2],
+    chunks: HashMap<[i64; 2], Chunk>,
+}
+
+impl World {
+    /// Creates a world centered on the chunk at [0, 0] and generates the chunks of the window around it
+    pub fn new(seed: u64, chunk_dimensions: [usize; 2]) -> Self {
+        let mut world = Self {
+            seed,
+            chunk_dimensions,
+            center: [0, 0],
+            chunks: HashMap::new(),
+        };
+        world.recenter([0, 0]);
+        world
+    }
+
+    pub fn chunk_dimensions(&self) -> [usize; 2] {
+        self.chunk_dimensions
+    }
+
+    pub fn center(&self) -> [i64; 2] {
+        self.center
+    }
+
+    /// Generator of numbers that only depend on the seed, the coordinates and the salt
+    fn rng(&self, coords: [i64; 2], salt: u64) -> StdRng {
+        // splitmix64 finalizer, to spread nearby coordinates over unrelated seeds
+        let mut x = self.seed ^ salt.wrapping_mul(0x9e3779b97f4a7c15);
+        for v in coords {
+            x = (x ^ v as u64).wrapping_add(0x9e3779b97f4a7c15);
+            x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+            x ^= x >> 31;
+        }
+        StdRng::seed_from_u64(x)
+    }
+
+    /// Column of the opening between a chunk and the chunk north of it
+    fn north_opening(&self, coords: [i64; 2]) -> usize {
+        self.rng(coords, NORTH_BORDER_SALT)
+            .gen_range(0..self.chunk_dimensions[1])
+    }
+
+    /// Row of the opening between a chunk and the chunk west of it
+    fn west_opening(&self, coords: [i64; 2]) -> usize {
+        self.rng(coords, WEST_BORDER_SALT)
+            .gen_range(0..self.chunk_dimensions[0])
+    }
+
+    /// Generates the maze of a chunk. The same seed and coordinates always give the same maze
+    pub fn chunk_maze(&self, coords: [i64; 2]) -> Map<2> {
+        let [rows, cols] = self.chunk_dimensions;
+        let [row, col] = coords;
+        let mut map = Map::<2>::from(Topology::Square.all_walls(), self.chunk_dimensions);
+        random_dfs_with_rng(&mut map, [0, 0], &mut self.rng(coords, MAZE_SALT));
+        let openings = [
+            ([0, self.north_opening(coords)], WALL_NORTH),
+            ([rows - 1, self.north_opening([row + 1, col])], WALL_SOUTH),
+            ([self.west_opening(coords), 0], WALL_WEST),
+            ([self.west_opening([row, col + 1]), cols - 1], WALL_EAST),
+        ];
+        for (tile, wall) in openings {
+            let tile = tile.map(|v| v as i32);
+            map.set_walls(tile, map.get_walls(tile) & !wall);
+        }
+        map
+    }
+
+    /// Maze of a loaded chunk
+    pub fn chunk_map(&self, coords: [i64; 2]) -> Option<&Map<2>> {
+        self.chunks.get(&coords).map(|chunk| &chunk.map)
+    }
+
+    /// Generator for the entities of a chunk, which are also the same every time the chunk is loaded
+    pub fn entity_rng(&self, coords: [i64; 2]) -> StdRng {
+        self.rng(coords, ENTITY_SALT)
+    }
+
+    /// Coordinates of the chunks in the window, in row-major order
+    pub fn window_coords(&self) -> impl Iterator<Item = [i64; 2]> + '_ {
+        positions([WINDOW_CHUNKS; 2])
+            .map(|[i, j]| [self.center[0] + i as i64 - WINDOW_RADIUS, self.center[1] + j as i64 - WINDOW_RADIUS])
+    }
+
+    /// Position in the map made by `window` of the first tile of a chunk of the window
+    pub fn window_origin(&self, coords: [i64; 2]) -> [usize; 2] {
+        std::array::from_fn(|i| (coords[i] - self.center[i] + WINDOW_RADIUS) as usize * self.chunk_dimensions[i])
+    }
+
+    /// Coordinates of the chunk of a tile of the map made by `window`
+    pub fn chunk_at(&self, tile: [usize; 2]) -> [i64; 2] {
+        std::array::from_fn(|i| self.center[i] + (tile[i] / self.chunk_dimensions[i]) as i64 - WINDOW_RADIUS)
+    }
+
+    /// Joins the chunks of the window into one map. Its border is open where the chunks around the window connect
+    pub fn window(&self) -> Map<2> {
+        let dimensions = self.chunk_dimensions.map(|v| v * WINDOW_CHUNKS);
+        let mut window = Map::<2>::from(Topology::Square.all_walls(), dimensions);
+        for (&coords, chunk) in self.chunks.iter() {
+            let mut slice = window.slice(self.window_origin(coords), self.chunk_dimensions);
+            for tile in positions(self.chunk_dimensions) {
+                let tile = tile.map(|v| v as i32);
+                slice.set_walls(tile, chunk.map.get_walls(tile));
+            }
+        }
+        window
+    }
+
+    /// Records that an entity was spawned in a chunk, so that it is dropped along with the chunk
+    pub fn add_entity(&mut self, coords: [i64; 2], id: EntityId) {
+        if let Some(chunk) = self.chunks.get_mut(&coords) {
+            chunk.entities.push(id);
+        }
+    }
+
+    /// Moves the window to be centered on the given chunk. Chunks that are left out of the window are dropped and the
+    /// ones that came into it are generated. Returns the entities of the dropped chunks and the coordinates of the
+    /// generated ones
+    pub fn recenter(&mut self, center: [i64; 2]) -> (Vec<EntityId>, Vec<[i64; 2]>) {
+        self.center = center;
+        let in_window = |coords: &[i64; 2]| (0..2).all(|i| (coords[i] - center[i]).abs() <= WINDOW_RADIUS);
+        let mut dropped = Vec::new();
+        self.chunks.retain(|coords, chunk| {
+            let keep = in_window(coords);
+            if !keep {
+                dropped.append(&mut chunk.entities);
+            }
+            keep
+        });
+        let loaded: Vec<[i64; 2]> = self
+            .window_coords()
+            .filter(|coords| !self.chunks.contains_key(coords))
+            .collect();
+        for &coords in loaded.iter() {
+            let map = self.chunk_maze(coords);
+            self.chunks.insert(
+                coords,
+                Chunk {
+                    map,
+                    entities: Vec::new(),
+                },
+            );
+        }
+        (dropped, loaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::Grid;
+
+    #[test]
+    fn chunks_line_up() {
+        let world = World::new(42, [5, 7]);
+        for coords in [[0, 0], [-3, 8], [1000, -1000]] {
+            let [row, col] = coords;
+            let (chunk, south, east) = (
+                world.chunk_maze(coords),
+                world.chunk_maze([row + 1, col]),
+                world.chunk_maze([row, col + 1]),
+            );
+            assert_eq!(chunk.passages().count(), 5 * 7 - 1);
+            for j in 0..7 {
+                assert_eq!(
+                    chunk.get_walls([4, j]) & WALL_SOUTH == 0,
+                    south.get_walls([0, j]) & WALL_NORTH == 0
+                );
+            }
+            for i in 0..5 {
+                assert_eq!(
+                    chunk.get_walls([i, 6]) & WALL_EAST == 0,
+                    east.get_walls([i, 0]) & WALL_WEST == 0
+                );
+            }
+        }
+        let window = world.window();
+        assert_eq!(window.graph().breadth_first([7, 10]).count(), 9 * 5 * 7);
+    }
+}
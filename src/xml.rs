@@ -0,0 +1,272 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Minimal XML element tree. Only supports what is needed to read level files:
+/// elements, attributes, text, comments, CDATA and the predefined entities
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> String {
+        format!("line {}: {}", self.line, message)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(&format!("expected '{expected}', found '{c}'"))),
+            None => Err(self.error(&format!("expected '{expected}', found end of file"))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.next();
+        }
+    }
+
+    /// Consumes characters until `terminator` has been read, returning everything before it
+    fn read_until(&mut self, terminator: &str) -> Result<String, String> {
+        let mut result = String::new();
+        while !result.ends_with(terminator) {
+            match self.next() {
+                Some(c) => result.push(c),
+                None => return Err(self.error(&format!("missing '{terminator}'"))),
+            }
+        }
+        result.truncate(result.len() - terminator.len());
+        Ok(result)
+    }
+
+    fn read_name(&mut self) -> Result<String, String> {
+        let mut name = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':') {
+                name.push(c);
+                self.next();
+            } else {
+                break;
+            }
+        }
+        if name.is_empty() {
+            return Err(self.error("expected a name"));
+        }
+        Ok(name)
+    }
+
+    /// Skips the prolog, comments and doctype before the root element
+    fn skip_misc(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_whitespace();
+            let mut lookahead = self.chars.clone();
+            match (lookahead.next(), lookahead.next()) {
+                (Some('<'), Some('?')) => {
+                    self.read_until("?>")?;
+                }
+                (Some('<'), Some('!')) => {
+                    if self.chars.clone().take(4).eq("<!--".chars()) {
+                        self.read_until("-->")?;
+                    } else {
+                        self.read_until(">")?;
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn parse_element(&mut self) -> Result<Element, String> {
+        self.expect('<')?;
+        let name = self.read_name()?;
+        let mut element = Element {
+            name,
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: String::new(),
+        };
+        loop {
+            self.skip_whitespace();
+            match self.chars.peek() {
+                Some('/') => {
+                    self.next();
+                    self.expect('>')?;
+                    return Ok(element);
+                }
+                Some('>') => {
+                    self.next();
+                    break;
+                }
+                Some(_) => {
+                    let key = self.read_name()?;
+                    self.skip_whitespace();
+                    self.expect('=')?;
+                    self.skip_whitespace();
+                    let quote = match self.next() {
+                        Some(q @ ('"' | '\'')) => q,
+                        _ => return Err(self.error("expected a quoted attribute value")),
+                    };
+                    let value = self.read_until(&quote.to_string())?;
+                    element.attributes.push((key, unescape(&value)));
+                }
+                None => return Err(self.error("unexpected end of file")),
+            }
+        }
+
+        // element content
+        loop {
+            let mut lookahead = self.chars.clone();
+            match (lookahead.next(), lookahead.next()) {
+                (Some('<'), Some('/')) => {
+                    self.next();
+                    self.next();
+                    let closing = self.read_name()?;
+                    if closing != element.name {
+                        return Err(self.error(&format!("expected </{}>, found </{closing}>", element.name)));
+                    }
+                    self.skip_whitespace();
+                    self.expect('>')?;
+                    return Ok(element);
+                }
+                (Some('<'), Some('!')) => {
+                    if self.chars.clone().take(9).eq("<![CDATA[".chars()) {
+                        self.read_until("<![CDATA[")?;
+                        let data = self.read_until("]]>")?;
+                        element.text.push_str(&data);
+                    } else {
+                        self.read_until("-->")?;
+                    }
+                }
+                (Some('<'), Some('?')) => {
+                    self.read_until("?>")?;
+                }
+                (Some('<'), _) => element.children.push(self.parse_element()?),
+                (Some(_), _) => {
+                    let mut text = String::new();
+                    while self.chars.peek().is_some_and(|&c| c != '<') {
+                        text.push(self.next().unwrap());
+                    }
+                    element.text.push_str(&unescape(&text));
+                }
+                (None, _) => return Err(self.error(&format!("missing </{}>", element.name))),
+            }
+        }
+    }
+}
+
+fn unescape(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else { break };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(char::from_u32),
+            _ => None,
+        };
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Parses an XML document and returns its root element
+pub fn parse(input: &str) -> Result<Element, String> {
+    let mut parser = Parser {
+        chars: input.chars().peekable(),
+        line: 1,
+    };
+    parser.skip_misc()?;
+    parser.parse_element()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elements_attributes_and_text_are_read() {
+        let root = parse(
+            r#"<?xml version="1.0"?>
+            <!-- levels -->
+            <map width='3' name="a &amp; b">
+                <layer id="1"/>
+                <!-- <layer id="commented"/> -->
+                <data>1,2<![CDATA[<3>]]>&#x34;&lt;</data>
+                <layer id="2" />
+            </map>"#,
+        )
+        .unwrap();
+        assert_eq!(root.name, "map");
+        assert_eq!(root.attr("width"), Some("3"));
+        assert_eq!(root.attr("name"), Some("a & b"));
+        assert_eq!(root.attr("height"), None);
+        let layers: Vec<_> = root
+            .children_named("layer")
+            .map(|layer| layer.attr("id").unwrap())
+            .collect();
+        assert_eq!(layers, ["1", "2"]);
+        assert!(root.children.iter().all(|child| child.children.is_empty()));
+        assert_eq!(root.child("data").unwrap().text, "1,2<3>4<");
+    }
+
+    #[test]
+    fn malformed_documents_are_rejected() {
+        let error = parse("<map>\n<layer>\n</map>").err().unwrap();
+        assert_eq!(error, "line 3: expected </layer>, found </map>");
+        assert!(parse("<map>").is_err());
+        assert!(parse("<map width=3/>").is_err());
+        assert!(parse("<map><!-- open").is_err());
+        assert_eq!(unescape("&unknown; &amp"), "&unknown; &amp");
+    }
+}